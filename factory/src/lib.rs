@@ -0,0 +1,204 @@
+// deploys fresh Product stores to sub-accounts of the factory
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::{LazyOption, Vector};
+use near_sdk::json_types::{Base64VecU8, U128};
+use near_sdk::serde::{Serialize, Deserialize};
+use near_sdk::{env, ext_contract, near_bindgen, setup_alloc, AccountId, Balance, Gas, Promise};
+
+setup_alloc!();
+
+// minimum deposit forwarded to every new store to cover its storage, 3 NEAR
+const MIN_STORE_DEPOSIT:Balance = 3_000_000_000_000_000_000_000_000;
+const STORE_INIT_GAS:Gas = 20_000_000_000_000;
+const CALLBACK_GAS:Gas = 10_000_000_000_000;
+
+#[near_bindgen]
+#[derive( BorshDeserialize, BorshSerialize )]
+pub struct StoreFactory {
+    owner: AccountId,
+    code: LazyOption<Vec<u8>>,
+    stores: Vector<StoreInfo>,
+}
+
+#[derive(Debug)]
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StoreInfo {
+    account_id: AccountId,
+    owner: AccountId,
+    created_at: u64,
+}
+
+#[ext_contract(ext_self)]
+pub trait FactoryCallbacks {
+    fn on_store_created(&mut self, account_id: AccountId, owner: AccountId, deposit: U128) -> Option<AccountId>;
+}
+
+impl Default for StoreFactory {
+    fn default() -> Self {
+        env::panic(b"Factory contract should be initialized before usage")
+    }
+}
+
+#[near_bindgen]
+impl StoreFactory{
+
+    #[init]
+    pub fn new()-> Self{
+        assert!(!env::state_exists(), "The contract is already initialized");
+
+        Self{
+            owner: env::signer_account_id(),
+            code: LazyOption::new(b"a".to_vec(), None),
+            stores: Vector::new(b"b".to_vec()),
+        }
+    }
+
+    // the compiled Product contract every store is created from
+    pub fn set_store_code(&mut self, code: Base64VecU8) {
+        //validate if owner
+        assert_eq!(env::predecessor_account_id(), self.owner, "401");
+        self.code.set(&code.into());
+    }
+
+    #[payable]
+    pub fn create_store(&mut self, name: String) -> Promise {
+        let deposit = env::attached_deposit();
+        assert!(deposit >= MIN_STORE_DEPOSIT, "Attach at least {} yoctoNEAR to fund the store storage", MIN_STORE_DEPOSIT);
+        assert!(!name.contains('.'), "Store name must not contain dots");
+        let account_id = format!("{}.{}", name, env::current_account_id());
+        assert!(env::is_valid_account_id(account_id.as_bytes()), "Invalid store name");
+        let code = self.code.get().expect("Store code is not set");
+
+        // named in the init call, the store's new_with_owner makes it the owner whoever signed
+        let owner = env::predecessor_account_id();
+        let args = near_sdk::serde_json::json!({ "owner_id": owner }).to_string();
+        env::log(format!("create_store '{}' ", account_id).as_bytes());
+        Promise::new(account_id.clone())
+            .create_account()
+            .transfer(deposit)
+            .deploy_contract(code)
+            .function_call(b"new_with_owner".to_vec(), args.into_bytes(), 0, STORE_INIT_GAS)
+            .then(ext_self::on_store_created(account_id, owner, deposit.into(), &env::current_account_id(), 0, CALLBACK_GAS))
+    }
+
+    // returns the new store account so wallets can link to it, the deposit goes back if creation failed
+    #[private]
+    pub fn on_store_created(&mut self, account_id: AccountId, owner: AccountId, deposit: U128) -> Option<AccountId> {
+        if near_sdk::is_promise_success() {
+            self.stores.push(&StoreInfo { account_id: account_id.clone(), owner, created_at: env::block_timestamp() });
+            Some(account_id)
+        } else {
+            env::log(format!("create_store_failed '{}' ", account_id).as_bytes());
+            Promise::new(owner).transfer(deposit.0);
+            None
+        }
+    }
+
+    pub fn get_stores(&self, from_index: u64, limit: u64) -> Vec<StoreInfo> {
+        let end = std::cmp::min(self.stores.len(), from_index.saturating_add(limit));
+        (from_index..end).filter_map(|index| self.stores.get(index)).collect()
+    }
+
+    pub fn get_store_count(&self) -> u64 {
+        self.stores.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::{MockedBlockchain, PromiseResult};
+    use near_sdk::{testing_env, VMContext};
+
+    fn get_context(predecessor: &str) -> VMContext {
+        VMContext {
+            current_account_id: "factory.near".to_string(),
+            signer_account_id: "paul.near".to_string(),
+            signer_account_pk: vec![0, 1, 2],
+            predecessor_account_id: predecessor.to_string(),
+            input: vec![],
+            block_index: 0,
+            block_timestamp: 0,
+            account_balance: 10u128.pow(26),
+            account_locked_balance: 0,
+            storage_usage: 0,
+            attached_deposit: 0,
+            prepaid_gas: 10u64.pow(18),
+            random_seed: vec![0, 1, 2],
+            is_view: false,
+            output_data_receivers: vec![],
+            epoch_height: 19,
+        }
+    }
+
+    #[test]
+    fn create_store_deploys_to_sub_account() {
+        testing_env!(get_context("paul.near"));
+        let mut contract = StoreFactory::new();
+        contract.set_store_code(vec![0, 97, 115, 109].into());
+
+        let mut context = get_context("paul.near");
+        context.attached_deposit = MIN_STORE_DEPOSIT;
+        testing_env!(context);
+
+        contract.create_store("shoes".to_string());
+        // the store deployment plus the callback into the factory
+        assert_eq!(2, near_sdk::test_utils::get_created_receipts().len());
+    }
+
+    #[test]
+    fn create_store_names_the_caller_as_owner() {
+        testing_env!(get_context("paul.near"));
+        let mut contract = StoreFactory::new();
+        contract.set_store_code(vec![0, 97, 115, 109].into());
+
+        // signed by paul.near, relayed through another contract
+        let mut context = get_context("alice.near");
+        context.attached_deposit = MIN_STORE_DEPOSIT;
+        testing_env!(context);
+
+        contract.create_store("shoes".to_string());
+        let receipts = near_sdk::serde_json::to_string(&near_sdk::test_utils::get_created_receipts()).unwrap();
+        assert!(receipts.contains(r#""method_name":"new_with_owner""#));
+        assert!(receipts.contains(r#"{\"owner_id\":\"alice.near\"}"#));
+    }
+
+    #[test]
+    fn successful_creation_is_registered() {
+        testing_env!(get_context("paul.near"));
+        let mut contract = StoreFactory::new();
+
+        let context = get_context("factory.near");
+        testing_env!(context, Default::default(), Default::default(), Default::default(), vec![PromiseResult::Successful(vec![])]);
+        let store = contract.on_store_created("shoes.factory.near".to_string(), "paul.near".to_string(), MIN_STORE_DEPOSIT.into());
+        assert_eq!(Some("shoes.factory.near".to_string()), store);
+
+        let stores = contract.get_stores(0, 10);
+        assert_eq!(1, stores.len());
+        assert_eq!("paul.near", stores[0].owner);
+    }
+
+    #[test]
+    fn failed_creation_is_not_registered() {
+        testing_env!(get_context("paul.near"));
+        let mut contract = StoreFactory::new();
+
+        let context = get_context("factory.near");
+        testing_env!(context, Default::default(), Default::default(), Default::default(), vec![PromiseResult::Failed]);
+        assert!(contract.on_store_created("shoes.factory.near".to_string(), "paul.near".to_string(), MIN_STORE_DEPOSIT.into()).is_none());
+        assert_eq!(0, contract.get_store_count());
+        // the refund of the forwarded deposit
+        assert_eq!(1, near_sdk::test_utils::get_created_receipts().len());
+    }
+
+    #[test]
+    #[should_panic(expected = "to fund the store storage")]
+    fn create_store_requires_deposit() {
+        testing_env!(get_context("paul.near"));
+        let mut contract = StoreFactory::new();
+        contract.set_store_code(vec![0, 97, 115, 109].into());
+
+        contract.create_store("shoes".to_string());
+    }
+}
@@ -0,0 +1,107 @@
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Serialize, Deserialize};
+use near_sdk::{env, near_bindgen, AccountId, Balance};
+use near_contract_standards::upgrade::Ownable;
+
+use crate::Product;
+use crate::ProductContract;
+use crate::gating::Checkout;
+
+const MAX_CODE_LEN:usize = 16;
+
+// commission_bps of every attributed sale accrues to owner as revenue
+#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AffiliateCode {
+     pub owner: AccountId,
+     pub commission_bps: u16,
+     // revoked codes are refused at checkout, sales already attributed still pay out
+     pub active: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AffiliateStats {
+     pub sales: u64,
+     pub volume: Balance,
+     pub commission: Balance,
+}
+
+// affiliate codes
+#[near_bindgen]
+impl Product{
+    // short enough to share, letters and digits only
+    pub fn mint_affiliate_code(&mut self, code: String, owner: AccountId, commission_bps: u16) {
+        //validate if owner
+        self.assert_owner();
+        assert!(!code.is_empty() && code.len() <= MAX_CODE_LEN && code.chars().all(|c| c.is_ascii_alphanumeric()), "Invalid affiliate code");
+        assert!(!self.affiliate_codes.contains_key(&code), "Affiliate code already exists");
+        assert!(env::is_valid_account_id(owner.as_bytes()), "Invalid account");
+        assert!(commission_bps <= 10_000, "Commission cannot exceed 10000 basis points");
+        let affiliate = AffiliateCode { owner, commission_bps, active: true };
+        self.audit("mint affiliate code", &format!("{} {:?}", code, affiliate));
+        self.affiliate_codes.insert(&code, &affiliate);
+    }
+
+    pub fn revoke_affiliate_code(&mut self, code: String) {
+        //validate if owner
+        self.assert_owner();
+        let mut affiliate = self.affiliate_codes.get(&code).expect("Unknown affiliate code");
+        affiliate.active = false;
+        self.audit("revoke affiliate code", &code);
+        self.affiliate_codes.insert(&code, &affiliate);
+    }
+
+    pub fn get_affiliate_code(&self, code: String) -> Option<AffiliateCode> {
+        self.affiliate_codes.get(&code)
+    }
+
+    // settled sales only, escrowed orders count once released
+    pub fn get_affiliate_stats(&self, code: String) -> AffiliateStats {
+        self.affiliate_stats.get(&code).unwrap_or_default()
+    }
+
+    #[payable]
+    pub fn buy_product_with_code(&mut self, address: String, quantity: u8, code: String) -> u64 {
+        let checkout = Checkout { affiliate: Some(code), ..Default::default() };
+        let (order_id, spent) = self.internal_buy(&env::predecessor_account_id(), address, quantity, 0, env::attached_deposit(), checkout);
+        self.refund_surplus(spent);
+        order_id
+    }
+
+    pub(crate) fn check_affiliate_code(&self, code: &String, buyer: &AccountId) -> Result<(), String> {
+        let affiliate = self.affiliate_codes.get(code).ok_or_else(|| "Unknown affiliate code".to_string())?;
+        if !affiliate.active {
+            return Err("Affiliate code is revoked".to_string());
+        }
+        if affiliate.owner == *buyer {
+            return Err("Cannot use your own affiliate code".to_string());
+        }
+        Ok(())
+    }
+
+    // the commission comes off the top, the store fee and splits apply to the rest
+    pub(crate) fn settle_sale(&mut self, address: &str, vendor: AccountId, buyer: &AccountId, total: Balance, affiliate: &Option<String>) -> Vec<(AccountId, Balance)> {
+        let (code, affiliate) = match affiliate.as_ref().and_then(|code| self.affiliate_codes.get(code).map(|affiliate| (code, affiliate))) {
+            Some(attributed) => attributed,
+            None => return self.pay_vendor(address, vendor, buyer, total),
+        };
+        let commission = total * affiliate.commission_bps as u128 / 10_000;
+        let mut stats = self.affiliate_stats.get(code).unwrap_or_default();
+        stats.sales += 1;
+        stats.volume += total;
+        stats.commission += commission;
+        self.affiliate_stats.insert(code, &stats);
+        if commission > 0 {
+            self.credit_revenue(&affiliate.owner, commission);
+            self.emit_event("affiliate_commission", format!("{} {} {} {}", code, address, affiliate.owner, commission));
+        }
+        let mut payouts = self.pay_vendor(address, vendor, buyer, total - commission);
+        if commission > 0 {
+            payouts.push((affiliate.owner, commission));
+        }
+        payouts
+    }
+}
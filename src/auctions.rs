@@ -0,0 +1,100 @@
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Serialize, Deserialize};
+use near_sdk::json_types::U128;
+use near_sdk::{env, near_bindgen, AccountId, Balance};
+
+use crate::{LifecycleState, Product};
+use crate::ProductContract;
+use crate::events::{BidData, CommerceEvent};
+
+// english auction for one unit of a product, ends_at is a block timestamp in nanoseconds
+#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Auction {
+     pub address: String,
+     pub vendor: AccountId,
+     pub reserve: Balance,
+     pub ends_at: u64,
+     pub highest_bid: Option<(AccountId, Balance)>,
+     pub settled: bool,
+}
+
+// auctions
+#[near_bindgen]
+impl Product{
+    pub fn create_auction(&mut self, address: String, reserve: Balance, ends_at: u64) -> u64 {
+        let item = self.load_product(&address).expect("Product does not exist");
+        self.assert_creator_or_override(&item, false);
+        self.assert_purchasable(&item);
+        assert!(item.stock > 0, "Not enough stock");
+        assert!(ends_at > env::block_timestamp(), "Auction must end in the future");
+
+        let auction_id = self.next_auction_id;
+        self.next_auction_id += 1;
+        let auction = Auction { address, vendor: item.creator, reserve, ends_at, highest_bid: None, settled: false };
+        self.emit_event("create_auction", format!("{} {:?}", auction_id, auction));
+        self.auctions.insert(&auction_id, &auction);
+        auction_id
+    }
+
+    // the attached deposit is the bid, the bid it beats is refunded right away
+    #[payable]
+    pub fn bid(&mut self, auction_id: u64) {
+        self.assert_not_paused();
+        let mut auction = self.auctions.get(&auction_id).expect("Auction does not exist");
+        assert!(env::block_timestamp() < auction.ends_at, "Auction has ended");
+        let bidder = env::predecessor_account_id();
+        assert!(bidder != auction.vendor, "Vendors cannot bid on their own auction");
+        let item = self.load_product(&auction.address).expect("Product does not exist");
+        self.assert_can_buy(&env::predecessor_account_id(), &auction.address, &item);
+        let amount = env::attached_deposit();
+        assert!(amount >= auction.reserve, "Bid is below the reserve");
+        let outbid = auction.highest_bid.take().map(|(previous, highest)| {
+            assert!(amount > highest, "Bid must beat the highest bid");
+            self.safe_transfer(previous.clone(), highest);
+            previous
+        });
+        self.emit_event("bid", format!("{} {} {}", auction_id, bidder, amount));
+        self.log_commerce_event(CommerceEvent::Bid(vec![BidData {
+            auction_id,
+            address: auction.address.clone(),
+            bidder: bidder.clone(),
+            amount: U128(amount),
+            outbid,
+        }]));
+        auction.highest_bid = Some((bidder, amount));
+        self.auctions.insert(&auction_id, &auction);
+    }
+
+    // anyone can settle an ended auction, the winner is refunded if the unit is gone meanwhile or the
+    // listing stopped selling, the same lifecycle states a direct purchase refuses
+    pub fn finalize_auction(&mut self, auction_id: u64) -> Option<AccountId> {
+        self.assert_not_paused();
+        let mut auction = self.auctions.get(&auction_id).expect("Auction does not exist");
+        assert!(env::block_timestamp() >= auction.ends_at, "Auction has not ended");
+        assert!(!auction.settled, "Auction is already settled");
+        auction.settled = true;
+        self.auctions.insert(&auction_id, &auction);
+
+        let (winner, amount) = auction.highest_bid?;
+        match self.load_product(&auction.address) {
+            Some(mut item) if item.stock > 0 && !item.frozen && item.state == LifecycleState::Active => {
+                self.take_stock(&auction.address, &mut item, 1);
+                self.pay_vendor(&auction.address, auction.vendor, &winner, amount);
+                self.emit_event("finalize_auction", format!("{} {} {}", auction_id, winner, amount));
+                self.log_purchase("auction", &auction.address, &winner, 1, amount, None);
+                Some(winner)
+            },
+            _ => {
+                self.safe_transfer(winner, amount);
+                self.emit_event("finalize_auction", format!("{} unsold", auction_id));
+                None
+            },
+        }
+    }
+
+    pub fn get_auction(&self, auction_id: u64) -> Option<Auction> {
+        self.auctions.get(&auction_id)
+    }
+}
@@ -0,0 +1,138 @@
+use near_sdk::serde::{Serialize, Deserialize};
+use near_sdk::{env, near_bindgen};
+
+use crate::Product;
+use crate::ProductContract;
+use crate::catalog::LifecycleState;
+use crate::events::{CommerceEvent, StockChangeData};
+
+pub const MAX_BATCH_SIZE:usize = 100;
+// basis points in one whole
+const BPS:u128 = 10_000;
+
+#[derive(Debug)]
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ProductEntry {
+     pub address: String,
+     pub name: String,
+     pub price: u128,
+     pub stock: u8,
+}
+
+// logged once per batch call instead of one line per entry
+#[derive(Debug, Default)]
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BatchSummary {
+     pub created: Vec<String>,
+     pub updated: Vec<String>,
+     pub failed: Vec<(String, String)>,
+}
+
+// collected while a batch runs and flushed once by finish_batch, never stored
+#[derive(Default)]
+pub(crate) struct BatchLog {
+     pub changed: Vec<String>,
+     pub stock_changes: Vec<StockChangeData>,
+     // address, old price, new price
+     pub price_changes: Vec<(String, u128, u128)>,
+}
+
+// batch operations
+#[near_bindgen]
+impl Product{
+    // new listings take their listing deposit out of the attached deposit, in entry order
+    #[payable]
+    pub fn set_products_batch(&mut self, entries: Vec<ProductEntry>) -> BatchSummary {
+        //validate sender has permition of ROLE_SET_PRODUCT
+        self.assert_permission("set_products_batch");
+        assert!(entries.len() <= MAX_BATCH_SIZE, "Too many entries in one batch");
+
+        let mut remaining = env::attached_deposit();
+        let mut summary = BatchSummary::default();
+        self.begin_batch();
+        for entry in entries {
+            let created = !self.records.contains_key(&entry.address);
+            if let Err(error) = self.check_product_write(&entry.address, &entry.name, remaining) {
+                summary.failed.push((entry.address, error));
+                continue;
+            }
+            let deposit = if created { self.listing_deposit } else { 0 };
+            remaining -= deposit;
+            self.apply_product_write(&entry.address, entry.name, entry.price, entry.stock, deposit, LifecycleState::Active);
+            if created {
+                summary.created.push(entry.address);
+            } else {
+                summary.updated.push(entry.address);
+            }
+        }
+        self.finish_batch("set_products_batch", &summary);
+        self.refund_surplus(env::attached_deposit() - remaining);
+        summary
+    }
+
+    // store-wide sales or inflation adjustments, bps_delta of -1000 takes 10% off every listed price
+    pub fn adjust_prices(&mut self, addresses: Vec<String>, bps_delta: i32) -> BatchSummary {
+        //validate sender has permition of ROLE_SET_PRODUCT
+        self.assert_permission("adjust_prices");
+        assert!(addresses.len() <= MAX_BATCH_SIZE, "Too many entries in one batch");
+        assert!(bps_delta >= -(BPS as i32), "Price cannot drop below zero");
+
+        let mut summary = BatchSummary::default();
+        self.begin_batch();
+        for address in addresses {
+            let item = match self.load_product(&address) {
+                Some(item) => item,
+                None => {
+                    summary.failed.push((address, "Product does not exist".to_string()));
+                    continue;
+                },
+            };
+            if let Err(error) = self.check_product_write(&address, &item.name, 0) {
+                summary.failed.push((address, error));
+                continue;
+            }
+            let price = match item.price.checked_mul((BPS as i64 + bps_delta as i64) as u128) {
+                Some(scaled) => scaled / BPS,
+                None => {
+                    summary.failed.push((address, "Price overflow".to_string()));
+                    continue;
+                },
+            };
+            if price != item.price {
+                self.apply_product_write(&address, item.name, price, item.stock, 0, LifecycleState::Active);
+            }
+            summary.updated.push(address);
+        }
+        self.finish_batch("adjust_prices", &summary);
+        summary
+    }
+
+    pub(crate) fn emit_batch_summary(&mut self, event: &str, summary: &BatchSummary) {
+        self.emit_event(event, near_sdk::serde_json::to_string(summary).unwrap());
+    }
+
+    // from here until finish_batch, per-entry notifications, stock events and price audits are collected instead of sent
+    pub(crate) fn begin_batch(&mut self) {
+        self.batch_log = Some(BatchLog::default());
+    }
+
+    pub(crate) fn finish_batch(&mut self, event: &str, summary: &BatchSummary) {
+        self.flush_batch(event);
+        self.emit_batch_summary(event, summary);
+    }
+
+    // for batch paths without a summary of their own
+    pub(crate) fn flush_batch(&mut self, event: &str) {
+        if let Some(log) = self.batch_log.take() {
+            self.notify_listeners_of_all(log.changed, event);
+            if !log.stock_changes.is_empty() {
+                self.log_commerce_event(CommerceEvent::StockChange(log.stock_changes));
+            }
+            if !log.price_changes.is_empty() {
+                self.audit(&format!("{} prices", event), &format!("{:?}", log.price_changes));
+            }
+        }
+    }
+}
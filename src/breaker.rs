@@ -0,0 +1,71 @@
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Serialize, Deserialize};
+use near_sdk::{env, near_bindgen};
+use near_contract_standards::upgrade::Ownable;
+
+use crate::Product;
+use crate::ProductContract;
+
+// deletions and price changes counted over the current window of config.breaker_window blocks
+#[derive(Debug, Clone, Default)]
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CircuitBreaker {
+     pub window_start: u64,
+     pub deletes: u32,
+     pub price_changes: u32,
+     // block the breaker tripped at, destructive calls are refused until the owner resets it
+     pub tripped_at: Option<u64>,
+}
+
+// anomaly circuit breaker
+#[near_bindgen]
+impl Product{
+    pub fn get_circuit_breaker(&self) -> CircuitBreaker {
+        self.breaker.clone()
+    }
+
+    pub fn reset_circuit_breaker(&mut self) {
+        //validate if owner
+        self.assert_owner();
+        self.audit("reset_circuit_breaker", &format!("{:?}", self.breaker));
+        self.breaker = CircuitBreaker { window_start: env::block_index(), ..Default::default() };
+    }
+
+    pub(crate) fn assert_breaker_closed(&self) {
+        assert!(self.breaker.tripped_at.is_none(), "Circuit breaker is tripped");
+    }
+
+    // the call that crosses a threshold still goes through, the ones after it are refused
+    pub(crate) fn record_deletion(&mut self) {
+        self.assert_breaker_closed();
+        let limit = self.config().max_deletes_per_window;
+        self.roll_breaker_window();
+        self.breaker.deletes += 1;
+        if limit > 0 && self.breaker.deletes > limit {
+            self.trip_breaker("deletes");
+        }
+    }
+
+    pub(crate) fn record_price_change(&mut self) {
+        self.assert_breaker_closed();
+        let limit = self.config().max_price_changes_per_window;
+        self.roll_breaker_window();
+        self.breaker.price_changes += 1;
+        if limit > 0 && self.breaker.price_changes > limit {
+            self.trip_breaker("price_changes");
+        }
+    }
+
+    fn roll_breaker_window(&mut self) {
+        let now = env::block_index();
+        if now >= self.breaker.window_start + self.config().breaker_window {
+            self.breaker = CircuitBreaker { window_start: now, ..Default::default() };
+        }
+    }
+
+    fn trip_breaker(&mut self, reason: &str) {
+        self.breaker.tripped_at = Some(env::block_index());
+        self.emit_event("circuit_breaker_tripped", format!("{} {:?}", reason, self.breaker));
+    }
+}
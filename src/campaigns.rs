@@ -0,0 +1,91 @@
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Serialize, Deserialize};
+use near_sdk::json_types::U128;
+use near_sdk::{env, near_bindgen, Balance};
+use near_contract_standards::upgrade::Ownable;
+
+use crate::Product;
+use crate::ProductContract;
+
+const MAX_CAMPAIGN_PRODUCTS:usize = 50;
+
+// discount_bps off the bundled products between starts_at and ends_at, until the discounts granted reach budget
+#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Campaign {
+     pub products: Vec<String>,
+     pub discount_bps: u16,
+     pub budget: Balance,
+     pub spent: Balance,
+     pub starts_at: u64,
+     pub ends_at: u64,
+}
+
+impl Campaign {
+    fn is_running(&self, now: u64) -> bool {
+        self.starts_at <= now && now < self.ends_at && self.spent < self.budget
+    }
+}
+
+// promotion campaigns
+#[near_bindgen]
+impl Product{
+    pub fn create_campaign(&mut self, products: Vec<String>, discount_bps: u16, budget: U128, starts_at: u64, ends_at: u64) -> u64 {
+        //validate if owner
+        self.assert_owner();
+        assert!(!products.is_empty() && products.len() <= MAX_CAMPAIGN_PRODUCTS, "A campaign bundles 1 to 50 products");
+        assert!(discount_bps > 0 && discount_bps <= 10_000, "Discount must be between 1 and 10000 basis points");
+        assert!(starts_at < ends_at, "Campaign must end after it starts");
+        let now = env::block_timestamp();
+        let campaign_id = self.next_campaign_id;
+        self.next_campaign_id += 1;
+        for address in products.iter() {
+            assert!(self.records.contains_key(address), "Product does not exist");
+            // ended campaigns are dropped from the index whenever a new one is added
+            let mut ids: Vec<u64> = self.product_campaigns.get(address).unwrap_or_default().into_iter()
+                .filter(|id| self.campaigns.get(id).map(|campaign| campaign.ends_at > now).unwrap_or(false))
+                .collect();
+            ids.push(campaign_id);
+            self.product_campaigns.insert(address, &ids);
+        }
+        let campaign = Campaign { products, discount_bps, budget: budget.0, spent: 0, starts_at, ends_at };
+        self.audit("create campaign", &format!("{} {:?}", campaign_id, campaign));
+        self.campaigns.insert(&campaign_id, &campaign);
+        campaign_id
+    }
+
+    pub fn end_campaign(&mut self, campaign_id: u64) {
+        //validate if owner
+        self.assert_owner();
+        let mut campaign = self.campaigns.get(&campaign_id).expect("Campaign does not exist");
+        campaign.ends_at = std::cmp::min(campaign.ends_at, env::block_timestamp());
+        self.audit("end campaign", &campaign_id.to_string());
+        self.campaigns.insert(&campaign_id, &campaign);
+    }
+
+    pub fn get_campaign(&self, campaign_id: u64) -> Option<Campaign> {
+        self.campaigns.get(&campaign_id)
+    }
+
+    pub fn get_product_campaigns(&self, address: String) -> Vec<u64> {
+        self.product_campaigns.get(&address).unwrap_or_default()
+    }
+
+    // the campaign granting the largest discount on listed, capped at what is left of its budget
+    pub(crate) fn best_campaign(&self, address: &String, listed: Balance) -> Option<(u64, Balance)> {
+        let now = env::block_timestamp();
+        self.product_campaigns.get(address).unwrap_or_default().into_iter()
+            .filter_map(|id| self.campaigns.get(&id).map(|campaign| (id, campaign)))
+            .filter(|(_, campaign)| campaign.is_running(now))
+            .map(|(id, campaign)| (id, std::cmp::min(listed * campaign.discount_bps as u128 / 10_000, campaign.budget - campaign.spent)))
+            .max_by_key(|(_, discount)| *discount)
+    }
+
+    pub(crate) fn charge_campaign(&mut self, campaign_id: u64, address: &str, discount: Balance) {
+        let mut campaign = self.campaigns.get(&campaign_id).unwrap();
+        campaign.spent += discount;
+        self.campaigns.insert(&campaign_id, &campaign);
+        self.emit_event("campaign_discount", format!("{} {} {}", campaign_id, address, discount));
+    }
+}
@@ -0,0 +1,323 @@
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Serialize, Deserialize};
+use near_sdk::collections::TreeMap;
+use near_sdk::json_types::U128;
+use near_sdk::{env, near_bindgen, AccountId};
+use std::ops::Bound;
+
+use crate::{Item, Product};
+use crate::ProductContract;
+use crate::history::SalesVelocity;
+use crate::moderation::Approval;
+use crate::utils::pagination::{scan, Page, MAX_SCAN};
+
+const MAX_INVITEES:usize = 100;
+
+#[derive(Debug, Clone, PartialEq)]
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub enum Visibility {
+    Public,
+    // left out of enumeration and sold only to the accounts on the invite list
+    Private,
+    // left out of enumeration, anyone with the key can still buy it
+    Hidden,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub enum LifecycleState {
+    // saved but never published, hidden from customers
+    Draft,
+    Active,
+    // listed but temporarily not for sale
+    Paused,
+    // no longer sold, still readable by key
+    Discontinued,
+    // kept for the record, no more edits
+    Archived,
+}
+
+impl LifecycleState {
+    fn can_move_to(self, next: LifecycleState) -> bool {
+        use LifecycleState::*;
+        matches!((self, next),
+            (Draft, Active) | (Draft, Archived)
+            | (Active, Paused) | (Paused, Active)
+            | (Active, Discontinued) | (Paused, Discontinued)
+            | (Discontinued, Archived))
+    }
+
+    fn is_listed(self) -> bool {
+        self == LifecycleState::Active || self == LifecycleState::Paused
+    }
+}
+
+// summary of the stored price history, the full list stays behind get_price_history
+#[derive(Debug)]
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PriceSummary {
+     pub lowest: u128,
+     pub highest: u128,
+     pub changes: u32,
+     pub last_changed_at: Option<u64>,
+}
+
+// everything a product page renders, in one call
+#[derive(Debug)]
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ProductDetail {
+     pub address: String,
+     pub item: Item,
+     pub vendor: AccountId,
+     pub sales: SalesVelocity,
+     pub price_summary: PriceSummary,
+     // the price converted at the cached display rates, NEAR first
+     pub display_prices: Vec<(String, U128)>,
+}
+
+// fields to change on the copy, unset fields keep the source value
+#[derive(Debug, Default)]
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CloneOverrides {
+     pub name: Option<String>,
+     pub price: Option<u128>,
+     pub stock: Option<u8>,
+}
+
+// product pages
+#[near_bindgen]
+impl Product{
+    pub fn get_product_detail(&self, address: String) -> Option<ProductDetail> {
+        let item = self.load_product(&address)?;
+        let history = self.price_history_of(&address);
+        let price_summary = PriceSummary {
+            lowest: history.iter().map(|point| point.price).min().unwrap_or(item.price),
+            highest: history.iter().map(|point| point.price).max().unwrap_or(item.price),
+            changes: history.len() as u32,
+            last_changed_at: history.last().map(|point| point.changed_at),
+        };
+        Some(ProductDetail {
+            vendor: item.creator.clone(),
+            display_prices: self.display_prices(item.price),
+            sales: self.get_sales_velocity(address.clone()),
+            price_summary,
+            address,
+            item,
+        })
+    }
+}
+
+// ordered key index
+#[near_bindgen]
+impl Product{
+    // keys in lexicographic order, so "vendorA/shoes/" lists everything under it
+    pub fn get_products_with_prefix(&self, prefix: String, limit: u64) -> Vec<(String, Item)> {
+        self.ordered_keys.range((Bound::Included(prefix.clone()), Bound::Unbounded))
+            .take_while(|(address, _)| address.starts_with(&prefix))
+            .take(std::cmp::min(limit, MAX_SCAN) as usize)
+            .filter_map(|(address, _)| self.load_product(&address).filter(|item| self.is_listed(item)).map(|item| (address, item)))
+            .collect()
+    }
+}
+
+// vendor to product keys index
+#[near_bindgen]
+impl Product{
+    // listings created by one account, in key order, for seller profile pages
+    pub fn get_vendor_catalog(&self, vendor: AccountId, cursor: Option<String>, limit: u64) -> Page<(String, Item)> {
+        match self.vendor_products.get(&vendor) {
+            Some(keys) => scan(&keys, &self.cursor_secret, cursor, limit, |address| {
+                self.load_product(&address).filter(|item| self.is_listed(item)).map(|item| (address, item))
+            }),
+            None => Page { items: Vec::new(), cursor: None },
+        }
+    }
+
+    pub(crate) fn index_vendor(&mut self, vendor: &AccountId, address: &String) {
+        let mut keys = self.vendor_products.get(vendor).unwrap_or_else(|| {
+            // hashed so one vendor's prefix can never be the start of another's
+            let mut prefix = b"q".to_vec();
+            prefix.extend(env::sha256(vendor.as_bytes()));
+            TreeMap::new(prefix)
+        });
+        if keys.insert(address, &()).is_none() {
+            self.vendor_products.insert(vendor, &keys);
+        }
+    }
+
+    pub(crate) fn unindex_vendor(&mut self, vendor: &AccountId, address: &String) {
+        if let Some(mut keys) = self.vendor_products.get(vendor) {
+            keys.remove(address);
+            if keys.len() == 0 {
+                self.vendor_products.remove(vendor);
+            } else {
+                self.vendor_products.insert(vendor, &keys);
+            }
+        }
+    }
+}
+
+// name to product keys index
+#[near_bindgen]
+impl Product{
+    pub fn find_products_by_name(&self, name: String) -> Vec<(String, Item)> {
+        self.name_index.get(&normalize_name(&name)).unwrap_or_default().into_iter()
+            .filter_map(|address| self.load_product(&address).filter(|item| self.is_listed(item)).map(|item| (address, item)))
+            .collect()
+    }
+
+    pub(crate) fn index_name(&mut self, search_key: &str, address: &String) {
+        let key = search_key.to_string();
+        let mut keys = self.name_index.get(&key).unwrap_or_default();
+        if !keys.contains(address) {
+            keys.push(address.clone());
+            self.name_index.insert(&key, &keys);
+        }
+    }
+
+    pub(crate) fn unindex_name(&mut self, search_key: &str, address: &String) {
+        let key = search_key.to_string();
+        let mut keys = self.name_index.get(&key).unwrap_or_default();
+        keys.retain(|indexed| indexed != address);
+        if keys.is_empty() {
+            self.name_index.remove(&key);
+        } else {
+            self.name_index.insert(&key, &keys);
+        }
+    }
+}
+
+// listing visibility
+#[near_bindgen]
+impl Product{
+    pub fn set_visibility(&mut self, address: String, visibility: Visibility) {
+        let mut item = self.load_product(&address).expect("Product does not exist");
+        self.assert_editable(&item);
+        self.assert_creator_or_override(&item, false);
+        self.emit_event("set_visibility", format!("{} {:?}", address, visibility));
+        item.visibility = visibility;
+        self.save_product(&address, &mut item);
+    }
+
+    pub fn add_invitees(&mut self, address: String, accounts: Vec<AccountId>) {
+        let item = self.load_product(&address).expect("Product does not exist");
+        self.assert_editable(&item);
+        self.assert_creator_or_override(&item, false);
+        let mut invited = self.invitees.get(&address).unwrap_or_default();
+        for account in accounts {
+            if !invited.contains(&account) {
+                invited.push(account);
+            }
+        }
+        assert!(invited.len() <= MAX_INVITEES, "Too many invitees");
+        self.invitees.insert(&address, &invited);
+    }
+
+    pub fn remove_invitees(&mut self, address: String, accounts: Vec<AccountId>) {
+        let item = self.load_product(&address).expect("Product does not exist");
+        self.assert_editable(&item);
+        self.assert_creator_or_override(&item, false);
+        let mut invited = self.invitees.get(&address).unwrap_or_default();
+        invited.retain(|account| !accounts.contains(account));
+        self.invitees.insert(&address, &invited);
+    }
+
+    pub fn get_invitees(&self, address: String) -> Vec<AccountId> {
+        self.invitees.get(&address).unwrap_or_default()
+    }
+
+    // what every enumeration and search view shows, exact key lookups still return everything
+    pub(crate) fn is_listed(&self, item: &Item) -> bool {
+        item.approval == Approval::Approved && item.visibility == Visibility::Public && item.state.is_listed()
+    }
+}
+
+// product cloning
+#[near_bindgen]
+impl Product{
+    // the copy is a new listing of the caller, with its own deposit, approval and history; the selling
+    // settings come along, a listing only its vendor can see can only be cloned by them
+    #[payable]
+    pub fn clone_product(&mut self, source_address: String, new_address: String, overrides: CloneOverrides) {
+        //validate sender has permition of ROLE_SET_PRODUCT
+        self.assert_permission("clone_product");
+        let source = self.load_product(&source_address).expect("Product does not exist");
+        assert!(self.is_listed(&source) || self.is_creator_or_override(&source, false), "Product does not exist");
+        assert!(!self.records.contains_key(&new_address), "Product already exists");
+
+        let name = overrides.name.unwrap_or_else(|| source.name.clone());
+        if let Err(error) = self.check_product_write(&new_address, &name, env::attached_deposit()) {
+            env::panic(error.as_bytes());
+        }
+        let deposit = self.listing_deposit;
+        let price = overrides.price.unwrap_or(source.price);
+        let stock = overrides.stock.unwrap_or(source.stock);
+        let mut item = self.new_item(name, price, stock, deposit, LifecycleState::Active);
+        item.visibility = source.visibility;
+        item.max_per_buyer = source.max_per_buyer;
+        item.rental = source.rental;
+        item.pay_what_you_want = source.pay_what_you_want;
+        // side tables first, so listeners notified by the save see the whole copy
+        if let Some(invited) = self.invitees.get(&source_address) {
+            self.invitees.insert(&new_address, &invited);
+        }
+        if let Some(splits) = self.payment_splits.get(&source_address) {
+            self.payment_splits.insert(&new_address, &splits);
+        }
+        if let Some(gate) = self.token_gates.get(&source_address) {
+            self.token_gates.insert(&new_address, &gate);
+        }
+        self.insert_new_product(&new_address, item);
+        self.emit_event("clone_product", format!("{} {}", source_address, new_address));
+        self.refund_surplus(deposit);
+    }
+}
+
+// listing lifecycle
+#[near_bindgen]
+impl Product{
+    // same checks and deposit as set_products, the listing stays out of the catalog until published
+    #[payable]
+    pub fn save_draft(&mut self, address: String, name: String, price: u128, stock: u8) {
+        //validate sender has permition of ROLE_SET_PRODUCT
+        self.assert_permission("save_draft");
+        if let Some(existing) = self.load_product(&address) {
+            assert!(existing.state == LifecycleState::Draft, "Product is already published");
+        }
+        if let Err(error) = self.check_product_write(&address, &name, env::attached_deposit()) {
+            env::panic(error.as_bytes());
+        }
+        let deposit = if self.records.contains_key(&address) { 0 } else { self.listing_deposit };
+        let item = self.apply_product_write(&address, name, price, stock, deposit, LifecycleState::Draft);
+        self.emit_event("save_draft", format!("{:?}", item));
+        self.refund_surplus(deposit);
+    }
+
+    pub fn publish_product(&mut self, address: String) {
+        let item = self.load_product(&address).expect("Product does not exist");
+        assert!(item.state == LifecycleState::Draft, "Product is already published");
+        self.set_lifecycle_state(address, LifecycleState::Active);
+    }
+
+    // Draft -> Active <-> Paused -> Discontinued -> Archived, a draft can also be archived unpublished
+    pub fn set_lifecycle_state(&mut self, address: String, state: LifecycleState) {
+        let mut item = self.load_product(&address).expect("Product does not exist");
+        self.assert_editable(&item);
+        self.assert_creator_or_override(&item, false);
+        let previous = item.state;
+        assert!(previous.can_move_to(state), "Cannot move a product from {:?} to {:?}", previous, state);
+        item.state = state;
+        self.save_product(&address, &mut item);
+        self.notify_listeners(&address, "set_lifecycle_state");
+        self.emit_event("set_lifecycle_state", format!("{} {:?} -> {:?}", address, previous, state));
+    }
+}
+
+pub(crate) fn normalize_name(name: &str) -> String {
+    name.trim().to_lowercase()
+}
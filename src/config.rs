@@ -0,0 +1,229 @@
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Serialize, Deserialize, Deserializer};
+use near_sdk::{env, near_bindgen, AccountId, Balance};
+use near_contract_standards::upgrade::Ownable;
+
+use crate::Product;
+use crate::ProductContract;
+use crate::purchase::LIMIT_BUCKETS;
+
+// basis points in one whole
+const MAX_FEE_BPS:u16 = 10_000;
+
+// store wide limits and switches, one place instead of constants spread over the modules
+#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Config {
+     pub max_products: u64,
+     pub max_name_len: u32,
+     // cut of every sale sent to the treasury
+     pub fee_bps: u16,
+     // charged on top of fee_bps for every settlement, capped at the settled amount
+     pub flat_fee: Balance,
+     pub purchase_enabled: bool,
+     pub report_deposit: Balance,
+     // per-account limits over a rolling window of limit_window nanoseconds, 0 turns a limit off
+     pub account_spend_limit: Balance,
+     pub account_quantity_limit: u32,
+     pub limit_window: u64,
+     // how long a delivered order stays escrowed for the buyer to dispute it
+     pub escrow_release_delay: u64,
+     // direct purchases are escrowed like pre-orders instead of paid out at checkout
+     pub escrow_direct_sales: bool,
+     // wrap.near or its testnet twin, None turns wNEAR payouts and payments off
+     pub wnear_contract: Option<AccountId>,
+     // how old the NEAR/USD rate may be before USD priced listings stop selling
+     pub max_rate_staleness: u64,
+     // holders of a token of this NFT contract get holder_discount_bps off, None turns the discount off
+     pub holder_nft_contract: Option<AccountId>,
+     pub holder_discount_bps: u16,
+     // how long unstaked NEAR stays locked before it can be withdrawn
+     pub unstake_cooldown: u64,
+     // how long a deleted product can be restored, 0 deletes for good straight away
+     pub trash_grace_period: u64,
+     // how long a role grant lasts before it has to be renewed, 0 grants for good
+     pub role_term: u64,
+     // circuit breaker thresholds over a window of breaker_window blocks, 0 turns a threshold off
+     pub breaker_window: u64,
+     pub max_deletes_per_window: u32,
+     pub max_price_changes_per_window: u32,
+     // how long after a rented unit is due the vendor has to settle the return before the renter
+     // can reclaim the deposit
+     pub rental_grace_period: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            max_products: 10_000,
+            max_name_len: 128,
+            fee_bps: 0,
+            flat_fee: 0,
+            purchase_enabled: true,
+            // small anti-spam deposit kept by the contract, 0.01 NEAR
+            report_deposit: 10_000_000_000_000_000_000_000,
+            account_spend_limit: 0,
+            account_quantity_limit: 0,
+            // one day
+            limit_window: 24 * 60 * 60 * 1_000_000_000,
+            // seven days
+            escrow_release_delay: 7 * 24 * 60 * 60 * 1_000_000_000,
+            escrow_direct_sales: false,
+            wnear_contract: None,
+            // one hour
+            max_rate_staleness: 60 * 60 * 1_000_000_000,
+            holder_nft_contract: None,
+            holder_discount_bps: 0,
+            // seven days
+            unstake_cooldown: 7 * 24 * 60 * 60 * 1_000_000_000,
+            // thirty days
+            trash_grace_period: 30 * 24 * 60 * 60 * 1_000_000_000,
+            role_term: 0,
+            breaker_window: 100,
+            // off until the owner picks thresholds that fit the store's normal traffic
+            max_deletes_per_window: 0,
+            max_price_changes_per_window: 0,
+            // seven days
+            rental_grace_period: 7 * 24 * 60 * 60 * 1_000_000_000,
+        }
+    }
+}
+
+// only the fields that are set get changed, an account field set to null is cleared
+#[derive(Debug, Default)]
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ConfigPatch {
+     pub max_products: Option<u64>,
+     pub max_name_len: Option<u32>,
+     pub fee_bps: Option<u16>,
+     pub flat_fee: Option<Balance>,
+     pub purchase_enabled: Option<bool>,
+     pub report_deposit: Option<Balance>,
+     pub account_spend_limit: Option<Balance>,
+     pub account_quantity_limit: Option<u32>,
+     pub limit_window: Option<u64>,
+     pub escrow_release_delay: Option<u64>,
+     pub escrow_direct_sales: Option<bool>,
+     #[serde(default, deserialize_with = "set_or_clear")]
+     pub wnear_contract: Option<Option<AccountId>>,
+     pub max_rate_staleness: Option<u64>,
+     #[serde(default, deserialize_with = "set_or_clear")]
+     pub holder_nft_contract: Option<Option<AccountId>>,
+     pub holder_discount_bps: Option<u16>,
+     pub unstake_cooldown: Option<u64>,
+     pub trash_grace_period: Option<u64>,
+     pub role_term: Option<u64>,
+     pub breaker_window: Option<u64>,
+     pub max_deletes_per_window: Option<u32>,
+     pub max_price_changes_per_window: Option<u32>,
+     pub rental_grace_period: Option<u64>,
+}
+
+// a missing field stays None, an explicit null becomes Some(None)
+fn set_or_clear<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Option<AccountId>>, D::Error> {
+    <Option<AccountId> as Deserialize>::deserialize(deserializer).map(Some)
+}
+
+// contract configuration
+#[near_bindgen]
+impl Product{
+    pub fn update_config(&mut self, patch: ConfigPatch) -> Config {
+        //validate if owner
+        self.assert_owner();
+        self.touch();
+
+        let mut config = self.config();
+        if let Some(max_products) = patch.max_products {
+            // the cap can always be raised, lowering it never strands existing listings
+            assert!(max_products >= self.product_keys.len(), "Cap is below the current catalog size");
+            config.max_products = max_products;
+        }
+        if let Some(max_name_len) = patch.max_name_len {
+            assert!(max_name_len > 0, "Name length limit must be positive");
+            config.max_name_len = max_name_len;
+        }
+        if let Some(fee_bps) = patch.fee_bps {
+            assert!(fee_bps <= MAX_FEE_BPS, "Fee cannot exceed 10000 basis points");
+            config.fee_bps = fee_bps;
+        }
+        if let Some(flat_fee) = patch.flat_fee {
+            config.flat_fee = flat_fee;
+        }
+        if let Some(purchase_enabled) = patch.purchase_enabled {
+            config.purchase_enabled = purchase_enabled;
+        }
+        if let Some(report_deposit) = patch.report_deposit {
+            config.report_deposit = report_deposit;
+        }
+        if let Some(account_spend_limit) = patch.account_spend_limit {
+            config.account_spend_limit = account_spend_limit;
+        }
+        if let Some(account_quantity_limit) = patch.account_quantity_limit {
+            config.account_quantity_limit = account_quantity_limit;
+        }
+        if let Some(limit_window) = patch.limit_window {
+            assert!(limit_window >= LIMIT_BUCKETS, "Limit window is too short");
+            config.limit_window = limit_window;
+        }
+        if let Some(escrow_release_delay) = patch.escrow_release_delay {
+            config.escrow_release_delay = escrow_release_delay;
+        }
+        if let Some(escrow_direct_sales) = patch.escrow_direct_sales {
+            config.escrow_direct_sales = escrow_direct_sales;
+        }
+        if let Some(wnear_contract) = patch.wnear_contract {
+            if let Some(account) = &wnear_contract {
+                assert!(env::is_valid_account_id(account.as_bytes()), "Invalid wNEAR account");
+            }
+            config.wnear_contract = wnear_contract;
+        }
+        if let Some(max_rate_staleness) = patch.max_rate_staleness {
+            config.max_rate_staleness = max_rate_staleness;
+        }
+        if let Some(holder_nft_contract) = patch.holder_nft_contract {
+            if let Some(account) = &holder_nft_contract {
+                assert!(env::is_valid_account_id(account.as_bytes()), "Invalid NFT account");
+            }
+            config.holder_nft_contract = holder_nft_contract;
+        }
+        if let Some(holder_discount_bps) = patch.holder_discount_bps {
+            assert!(holder_discount_bps <= MAX_FEE_BPS, "Discount cannot exceed 10000 basis points");
+            config.holder_discount_bps = holder_discount_bps;
+        }
+        if let Some(unstake_cooldown) = patch.unstake_cooldown {
+            config.unstake_cooldown = unstake_cooldown;
+        }
+        if let Some(trash_grace_period) = patch.trash_grace_period {
+            config.trash_grace_period = trash_grace_period;
+        }
+        if let Some(role_term) = patch.role_term {
+            config.role_term = role_term;
+        }
+        if let Some(breaker_window) = patch.breaker_window {
+            assert!(breaker_window > 0, "Breaker window must be positive");
+            config.breaker_window = breaker_window;
+        }
+        if let Some(max_deletes_per_window) = patch.max_deletes_per_window {
+            config.max_deletes_per_window = max_deletes_per_window;
+        }
+        if let Some(max_price_changes_per_window) = patch.max_price_changes_per_window {
+            config.max_price_changes_per_window = max_price_changes_per_window;
+        }
+        if let Some(rental_grace_period) = patch.rental_grace_period {
+            config.rental_grace_period = rental_grace_period;
+        }
+        self.audit("update_config", &format!("{:?}", config));
+        self.config.set(&config);
+        config
+    }
+
+    pub fn get_config(&self) -> Config {
+        self.config()
+    }
+
+    pub(crate) fn config(&self) -> Config {
+        self.config.get().unwrap_or_default()
+    }
+}
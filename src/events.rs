@@ -0,0 +1,162 @@
+use near_sdk::serde::Serialize;
+use near_sdk::json_types::U128;
+use near_sdk::{env, AccountId, Balance};
+
+use crate::Product;
+use crate::history::StockReason;
+use crate::offers::Offer;
+use crate::orders::Order;
+
+const EVENT_STANDARD:&str = "product_store";
+const EVENT_VERSION:&str = "1.0.0";
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PurchaseData {
+     // direct, order, offer, quote, auction, rental or subscription
+     pub channel: String,
+     pub address: String,
+     pub buyer: AccountId,
+     pub quantity: u8,
+     pub amount: U128,
+     pub order_id: Option<u64>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RefundData {
+     pub order_id: u64,
+     pub address: String,
+     pub buyer: AccountId,
+     pub quantity: u8,
+     pub amount: U128,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OfferData {
+     // made, accepted, rejected or withdrawn
+     pub action: String,
+     pub offer_id: u64,
+     pub address: String,
+     pub buyer: AccountId,
+     pub quantity: u8,
+     pub amount: U128,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BidData {
+     pub auction_id: u64,
+     pub address: String,
+     pub bidder: AccountId,
+     pub amount: U128,
+     pub outbid: Option<AccountId>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StockChangeData {
+     pub address: String,
+     pub old_stock: u8,
+     pub new_stock: u8,
+     pub reason: StockReason,
+     pub actor: AccountId,
+}
+
+// the NEP-297 event names, data is always a list as in NEP-171
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(tag = "event", content = "data", rename_all = "snake_case")]
+pub enum CommerceEvent {
+    Purchase(Vec<PurchaseData>),
+    Refund(Vec<RefundData>),
+    Offer(Vec<OfferData>),
+    Bid(Vec<BidData>),
+    StockChange(Vec<StockChangeData>),
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RoleEventData {
+     pub role: String,
+     pub account: AccountId,
+     pub changed_by: AccountId,
+}
+
+// logged by AccessControl itself, so no grant or revoke path can skip it
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(tag = "event", content = "data", rename_all = "snake_case")]
+pub enum AccessEvent {
+    RoleGranted(Vec<RoleEventData>),
+    RoleRevoked(Vec<RoleEventData>),
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct EventLog<'a, T: Serialize> {
+     standard: &'a str,
+     version: &'a str,
+     #[serde(flatten)]
+     event: &'a T,
+}
+
+pub(crate) fn log_event<T: Serialize>(event: &T) {
+    let log = EventLog { standard: EVENT_STANDARD, version: EVENT_VERSION, event };
+    env::log(format!("EVENT_JSON:{}", near_sdk::serde_json::to_string(&log).unwrap()).as_bytes());
+}
+
+// structured logs for indexers, next to the plain emit_event logs and the recent_events ring
+impl Product {
+    pub(crate) fn log_commerce_event(&self, event: CommerceEvent) {
+        log_event(&event);
+    }
+
+    pub(crate) fn log_purchase(&self, channel: &str, address: &str, buyer: &AccountId, quantity: u8, amount: Balance, order_id: Option<u64>) {
+        self.log_commerce_event(CommerceEvent::Purchase(vec![PurchaseData {
+            channel: channel.to_string(),
+            address: address.to_string(),
+            buyer: buyer.clone(),
+            quantity,
+            amount: U128(amount),
+            order_id,
+        }]));
+    }
+
+    pub(crate) fn log_refund(&self, order_id: u64, order: &Order, quantity: u8, amount: Balance) {
+        self.log_commerce_event(CommerceEvent::Refund(vec![RefundData {
+            order_id,
+            address: order.address.clone(),
+            buyer: order.buyer.clone(),
+            quantity,
+            amount: U128(amount),
+        }]));
+    }
+
+    pub(crate) fn log_offer(&self, action: &str, offer_id: u64, offer: &Offer) {
+        self.log_commerce_event(CommerceEvent::Offer(vec![OfferData {
+            action: action.to_string(),
+            offer_id,
+            address: offer.address.clone(),
+            buyer: offer.buyer.clone(),
+            quantity: offer.quantity,
+            amount: U128(offer.amount),
+        }]));
+    }
+
+    // a batch logs all of its stock changes as one event when it finishes
+    pub(crate) fn log_stock_change(&mut self, address: &str, old_stock: u8, new_stock: u8, reason: &StockReason) {
+        let change = StockChangeData {
+            address: address.to_string(),
+            old_stock,
+            new_stock,
+            reason: reason.clone(),
+            actor: env::signer_account_id(),
+        };
+        match self.batch_log.as_mut() {
+            Some(log) => log.stock_changes.push(change),
+            None => self.log_commerce_event(CommerceEvent::StockChange(vec![change])),
+        }
+    }
+}
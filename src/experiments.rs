@@ -0,0 +1,75 @@
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Serialize, Deserialize};
+use near_sdk::json_types::U128;
+use near_sdk::{env, near_bindgen, AccountId, Balance};
+
+use crate::Product;
+use crate::ProductContract;
+
+const MAX_EXPERIMENT_BUCKETS:usize = 4;
+
+#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ExperimentBucket {
+     pub price: Balance,
+     pub sales: u64,
+     pub units: u64,
+     pub revenue: Balance,
+}
+
+// every buyer of the product lands in the same bucket for as long as the experiment runs
+#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PriceExperiment {
+     pub buckets: Vec<ExperimentBucket>,
+     pub started_at: u64,
+}
+
+// price experiments
+#[near_bindgen]
+impl Product{
+    // one price per bucket, an empty list ends the experiment and its results with it
+    pub fn set_price_experiment(&mut self, address: String, prices: Vec<U128>) {
+        let item = self.load_product(&address).expect("Product does not exist");
+        self.assert_editable(&item);
+        self.assert_creator_or_override(&item, false);
+        if prices.is_empty() {
+            self.price_experiments.remove(&address);
+        } else {
+            assert!(prices.len() >= 2 && prices.len() <= MAX_EXPERIMENT_BUCKETS, "An experiment has 2 to 4 buckets");
+            let buckets = prices.iter().map(|price| ExperimentBucket { price: price.0, sales: 0, units: 0, revenue: 0 }).collect();
+            self.price_experiments.insert(&address, &PriceExperiment { buckets, started_at: env::block_timestamp() });
+        }
+        self.emit_event("set_price_experiment", format!("{} {:?}", address, prices));
+    }
+
+    pub fn get_price_experiment(&self, address: String) -> Option<PriceExperiment> {
+        self.price_experiments.get(&address)
+    }
+
+    pub fn get_price_bucket(&self, address: String, account: AccountId) -> Option<u8> {
+        self.price_bucket(&address, &account).map(|(bucket, _)| bucket)
+    }
+
+    // the bucket comes from the hash of the account and the product, so it is stable but differs across products
+    pub(crate) fn price_bucket(&self, address: &String, account: &AccountId) -> Option<(u8, Balance)> {
+        let experiment = self.price_experiments.get(address)?;
+        let hash = env::sha256(format!("{}:{}", account, address).as_bytes());
+        let mut head = [0u8; 8];
+        head.copy_from_slice(&hash[..8]);
+        let bucket = (u64::from_le_bytes(head) % experiment.buckets.len() as u64) as u8;
+        Some((bucket, experiment.buckets[bucket as usize].price))
+    }
+
+    pub(crate) fn record_experiment_sale(&mut self, address: &String, buyer: &AccountId, bucket: u8, quantity: u8, total: Balance) {
+        let mut experiment = self.price_experiments.get(address).unwrap();
+        let results = &mut experiment.buckets[bucket as usize];
+        results.sales += 1;
+        results.units += quantity as u64;
+        results.revenue += total;
+        self.price_experiments.insert(address, &experiment);
+        self.emit_event("experiment_sale", format!("{} {} {} {} {}", address, buyer, bucket, quantity, total));
+    }
+}
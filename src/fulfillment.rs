@@ -0,0 +1,48 @@
+use near_sdk::{env, ext_contract, near_bindgen, AccountId};
+use near_contract_standards::upgrade::Ownable;
+
+use crate::Product;
+use crate::ProductContract;
+
+// interface of the contract automating restocks
+#[ext_contract(ext_fulfillment)]
+pub trait Fulfillment {
+    fn on_stock_changed(&mut self, address: String, stock: u8, event: String);
+}
+
+// stock-change notifications to a fulfillment contract
+#[near_bindgen]
+impl Product{
+    pub fn set_fulfillment(&mut self, account: Option<AccountId>, reorder_threshold: u8) {
+        //validate if owner
+        self.assert_owner();
+        self.touch();
+        if let Some(account) = &account {
+            assert!(env::is_valid_account_id(account.as_bytes()), "Invalid fulfillment account");
+        }
+        self.fulfillment = account;
+        self.reorder_threshold = reorder_threshold;
+    }
+
+    pub fn get_fulfillment(&self) -> (Option<AccountId>, u8) {
+        (self.fulfillment.clone(), self.reorder_threshold)
+    }
+
+    // fires only when the stock crosses zero or drops to the reorder threshold
+    pub(crate) fn check_stock_thresholds(&self, address: &str, old_stock: u8, new_stock: u8) {
+        let fulfillment = match &self.fulfillment {
+            Some(account) => account,
+            None => return,
+        };
+        let event = if old_stock > 0 && new_stock == 0 {
+            "out_of_stock"
+        } else if old_stock == 0 && new_stock > 0 {
+            "back_in_stock"
+        } else if old_stock > self.reorder_threshold && new_stock <= self.reorder_threshold {
+            "reorder"
+        } else {
+            return;
+        };
+        ext_fulfillment::on_stock_changed(address.to_string(), new_stock, event.to_string(), fulfillment, 0, self.listener_gas);
+    }
+}
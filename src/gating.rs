@@ -0,0 +1,160 @@
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Serialize, Deserialize};
+use near_sdk::json_types::U128;
+use near_sdk::{env, ext_contract, near_bindgen, AccountId, Balance, Gas, Promise, PromiseResult};
+
+use crate::Product;
+use crate::ProductContract;
+
+// 10 TGas, for the NFT and FT lookups
+const VIEW_GAS:Gas = 10_000_000_000_000;
+// 60 TGas, the callback runs the whole purchase
+pub(crate) const PURCHASE_CALLBACK_GAS:Gas = 60_000_000_000_000;
+
+// members-only listings, the buyer must hold at least min_balance of the token
+#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenGate {
+     pub token: AccountId,
+     pub min_balance: Balance,
+}
+
+// how a purchase entered the pipeline, the plain NEAR entry points use the default
+#[derive(Default)]
+pub(crate) struct Checkout {
+    // comes off the listed price
+    pub discount_bps: u16,
+    // the token gate was checked by the caller
+    pub gate_cleared: bool,
+    // credited with a commission once the sale settles
+    pub affiliate: Option<String>,
+}
+
+// the part of a NEP-171 token the discount looks at
+#[derive(Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+struct HeldToken {
+     owner_id: AccountId,
+}
+
+#[ext_contract(ext_nft)]
+pub trait NonFungibleToken {
+    fn nft_token(&self, token_id: String);
+}
+
+#[ext_contract(ext_ft)]
+pub trait FungibleToken {
+    fn ft_balance_of(&self, account_id: AccountId);
+}
+
+#[ext_contract(ext_gating)]
+pub trait GatingCallbacks {
+    fn on_holder_checked(&mut self, buyer: AccountId, address: String, quantity: u8, payment: U128) -> Option<u64>;
+    fn on_gate_checked(&mut self, buyer: AccountId, address: String, quantity: u8, payment: U128) -> Option<u64>;
+}
+
+// holder discounts
+#[near_bindgen]
+impl Product{
+    // the sale settles in the callback, at the discounted price if the buyer owns token_id
+    #[payable]
+    pub fn buy_product_as_holder(&mut self, address: String, quantity: u8, token_id: String) -> Promise {
+        let nft_contract = self.config().holder_nft_contract.expect("No holder discount is configured");
+        let buyer = env::predecessor_account_id();
+        // checked up front as well, so a purchase bound to fail does not pay for the lookup
+        let item = self.load_product(&address).expect("Product does not exist");
+        self.assert_can_buy(&buyer, &address, &item);
+
+        ext_nft::nft_token(token_id, &nft_contract, 0, VIEW_GAS)
+            .then(ext_gating::on_holder_checked(buyer, address, quantity, U128(env::attached_deposit()), &env::current_account_id(), 0, PURCHASE_CALLBACK_GAS))
+    }
+
+    // a missing token or a failed lookup settles at the full price
+    #[private]
+    pub fn on_holder_checked(&mut self, buyer: AccountId, address: String, quantity: u8, payment: U128) -> Option<u64> {
+        let holder = match env::promise_result(0) {
+            PromiseResult::Successful(value) => near_sdk::serde_json::from_slice::<Option<HeldToken>>(&value)
+                .ok().flatten().map(|token| token.owner_id == buyer).unwrap_or(false),
+            _ => false,
+        };
+        let discount_bps = if holder { self.config().holder_discount_bps } else { 0 };
+        self.settle_checked_purchase(buyer, address, quantity, payment.0, Checkout { discount_bps, ..Default::default() })
+    }
+
+    // the state may have changed since the up-front checks, a refused purchase is refunded in full
+    pub(crate) fn settle_checked_purchase(&mut self, buyer: AccountId, address: String, quantity: u8, payment: Balance, checkout: Checkout) -> Option<u64> {
+        match self.try_buy(&buyer, address.clone(), quantity, 0, payment, checkout) {
+            Ok((order_id, spent)) => {
+                if payment > spent {
+                    self.safe_transfer(buyer, payment - spent);
+                }
+                Some(order_id)
+            },
+            Err(error) => {
+                self.emit_event("purchase_refused", format!("{} {} {}", address, buyer, error));
+                if payment > 0 {
+                    self.safe_transfer(buyer, payment);
+                }
+                None
+            },
+        }
+    }
+}
+
+// token gates
+#[near_bindgen]
+impl Product{
+    // None opens the listing to everyone again
+    pub fn set_token_gate(&mut self, address: String, gate: Option<TokenGate>) {
+        let item = self.load_product(&address).expect("Product does not exist");
+        self.assert_editable(&item);
+        self.assert_creator_or_override(&item, false);
+        match &gate {
+            Some(gate) => {
+                assert!(env::is_valid_account_id(gate.token.as_bytes()), "Invalid token account");
+                self.token_gates.insert(&address, gate);
+            },
+            None => {
+                self.token_gates.remove(&address);
+            },
+        }
+        self.emit_event("set_token_gate", format!("{} {:?}", address, gate));
+    }
+
+    pub fn get_token_gate(&self, address: String) -> Option<TokenGate> {
+        self.token_gates.get(&address)
+    }
+
+    // the balance is read with ft_balance_of and the sale settles in the callback
+    #[payable]
+    pub fn buy_gated_product(&mut self, address: String, quantity: u8) -> Promise {
+        let gate = self.token_gates.get(&address).expect("Product is not token gated");
+        let buyer = env::predecessor_account_id();
+        // checked up front as well, so a purchase bound to fail does not pay for the lookup
+        let item = self.load_product(&address).expect("Product does not exist");
+        self.assert_open_to(&buyer, &address, &item);
+
+        ext_ft::ft_balance_of(buyer.clone(), &gate.token, 0, VIEW_GAS)
+            .then(ext_gating::on_gate_checked(buyer, address, quantity, U128(env::attached_deposit()), &env::current_account_id(), 0, PURCHASE_CALLBACK_GAS))
+    }
+
+    // buyers below the minimum get the deposit back instead of a failed receipt
+    #[private]
+    pub fn on_gate_checked(&mut self, buyer: AccountId, address: String, quantity: u8, payment: U128) -> Option<u64> {
+        let balance = match env::promise_result(0) {
+            PromiseResult::Successful(value) => near_sdk::serde_json::from_slice::<U128>(&value).map(|balance| balance.0).unwrap_or(0),
+            _ => 0,
+        };
+        // the gate may have been lifted while the balance was read
+        let min_balance = self.token_gates.get(&address).map(|gate| gate.min_balance).unwrap_or(0);
+        if balance < min_balance {
+            self.emit_event("token_gate_refused", format!("{} {} {}", address, buyer, balance));
+            if payment.0 > 0 {
+                self.safe_transfer(buyer, payment.0);
+            }
+            return None;
+        }
+        self.settle_checked_purchase(buyer, address, quantity, payment.0, Checkout { gate_cleared: true, ..Default::default() })
+    }
+}
@@ -0,0 +1,257 @@
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Serialize, Deserialize};
+use near_sdk::{env, near_bindgen, AccountId};
+
+use crate::Product;
+use crate::ProductContract;
+
+const PRICE_HISTORY_CAPACITY:usize = 20;
+const STOCK_LEDGER_CAPACITY:usize = 50;
+// sales are counted in hourly buckets over a rolling day, in nanoseconds
+const SALES_BUCKET:u64 = 60 * 60 * 1_000_000_000;
+const SALES_WINDOW:u64 = 24 * SALES_BUCKET;
+
+#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PricePoint {
+     pub price: u128,
+     pub changed_at: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub enum StockReason {
+    Sale,
+    Restock,
+    Correction,
+    Reservation,
+    StockTake,
+    Damage,
+    Theft,
+    Rental,
+    // goods received against a purchase order
+    Receiving,
+    // units of a fulfilled order refunded and put back on the shelf
+    Refund,
+}
+
+impl StockReason {
+    // reason codes accepted from warehouse reconciliation
+    pub(crate) fn from_code(code: &str) -> Option<Self> {
+        match code {
+            "stock-take" => Some(StockReason::StockTake),
+            "damage" => Some(StockReason::Damage),
+            "theft" => Some(StockReason::Theft),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StockMovement {
+     pub delta: i32,
+     pub reason: StockReason,
+     pub stock_after: u8,
+     pub actor: AccountId,
+     pub moved_at: u64,
+}
+
+// price a product switches to once effective_at, a block timestamp in nanoseconds, is reached
+#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ScheduledPrice {
+     pub price: u128,
+     pub effective_at: u64,
+}
+
+// dutch auction pricing, falls from start_price to floor_price between starts_at and ends_at;
+// a step of 0 falls linearly, otherwise the price only moves every step nanoseconds
+#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PriceDecay {
+     pub start_price: u128,
+     pub floor_price: u128,
+     pub starts_at: u64,
+     pub ends_at: u64,
+     pub step: u64,
+}
+
+impl PriceDecay {
+    pub(crate) fn price_at(&self, now: u64) -> u128 {
+        if now <= self.starts_at {
+            return self.start_price;
+        }
+        if now >= self.ends_at {
+            return self.floor_price;
+        }
+        let mut elapsed = now - self.starts_at;
+        if self.step > 0 {
+            elapsed -= elapsed % self.step;
+        }
+        // drop * elapsed / duration, split so the product never leaves u128: the remainder is below
+        // duration and elapsed is too, both fit in u64
+        let drop = self.start_price - self.floor_price;
+        let duration = (self.ends_at - self.starts_at) as u128;
+        let elapsed = elapsed as u128;
+        let span = drop / duration * elapsed + drop % duration * elapsed / duration;
+        self.start_price - span
+    }
+}
+
+// units sold per hourly bucket, oldest first
+#[derive(Debug, Clone, Default)]
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct SalesBuckets {
+     buckets: Vec<(u64, u32)>,
+}
+
+#[derive(Debug)]
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SalesVelocity {
+     pub units: u32,
+     pub window: u64,
+}
+
+// per-product price history
+#[near_bindgen]
+impl Product{
+    // newest first
+    pub fn get_price_history(&self, address: String, limit: u64) -> Vec<PricePoint> {
+        let history = self.price_history_of(&address);
+        history.into_iter().rev().take(limit as usize).collect()
+    }
+
+    // a scheduled price is only written to the history by the next write after it took effect,
+    // reads before that see its point all the same
+    pub(crate) fn price_history_of(&self, address: &String) -> Vec<PricePoint> {
+        let mut history = self.price_history.get(address).unwrap_or_default();
+        if let Some(scheduled) = self.due_scheduled_price(address) {
+            history.push(PricePoint { price: scheduled.price, changed_at: scheduled.effective_at });
+        }
+        history
+    }
+
+    pub(crate) fn due_scheduled_price(&self, address: &String) -> Option<ScheduledPrice> {
+        self.records.get(address)
+            .and_then(|stored| stored.scheduled_price)
+            .filter(|scheduled| scheduled.effective_at <= env::block_timestamp())
+    }
+
+    pub(crate) fn record_price(&mut self, address: &String, price: u128) {
+        self.record_price_at(address, price, env::block_timestamp());
+    }
+
+    // kept in time order, a scheduled price is recorded after the fact at the time it took effect
+    pub(crate) fn record_price_at(&mut self, address: &String, price: u128, changed_at: u64) {
+        let mut history = self.price_history.get(address).unwrap_or_default();
+        if history.len() >= PRICE_HISTORY_CAPACITY {
+            history.remove(0);
+        }
+        let position = history.iter().rposition(|point| point.changed_at <= changed_at).map(|index| index + 1).unwrap_or(0);
+        history.insert(position, PricePoint { price, changed_at });
+        self.price_history.insert(address, &history);
+    }
+}
+
+// future-effective prices
+#[near_bindgen]
+impl Product{
+    // replaces any earlier schedule, the history point is stamped with the time the price takes effect
+    pub fn set_price_effective_at(&mut self, address: String, price: u128, at: u64) {
+        //validate sender has permition of ROLE_SET_PRODUCT
+        self.assert_permission("set_price_effective_at");
+        let mut item = self.load_product(&address).expect("Product does not exist");
+        self.assert_editable(&item);
+        self.assert_creator_or_override(&item, false);
+        assert!(at > env::block_timestamp(), "Effective time must be in the future");
+
+        self.audit(&format!("schedule price {} -> {} at {}", item.price, price, at), &address);
+        item.scheduled_price = Some(ScheduledPrice { price, effective_at: at });
+        item.updated_by = env::signer_account_id();
+        self.save_product(&address, &mut item);
+        self.emit_event("set_price_effective_at", format!("{} {} {}", address, price, at));
+    }
+}
+
+// dutch auction price decay
+#[near_bindgen]
+impl Product{
+    // None goes back to the fixed price, which is left at the last decayed value
+    pub fn set_price_decay(&mut self, address: String, decay: Option<PriceDecay>) {
+        //validate sender has permition of ROLE_SET_PRODUCT
+        self.assert_permission("set_price_decay");
+        let mut item = self.load_product(&address).expect("Product does not exist");
+        self.assert_editable(&item);
+        self.assert_creator_or_override(&item, false);
+        if let Some(decay) = &decay {
+            assert!(decay.floor_price <= decay.start_price, "Floor price must not exceed the start price");
+            assert!(decay.starts_at < decay.ends_at, "Decay must end after it starts");
+        }
+        self.emit_event("set_price_decay", format!("{} {:?}", address, decay));
+        item.price_decay = decay;
+        item.updated_by = env::signer_account_id();
+        self.save_product(&address, &mut item);
+    }
+}
+
+// per-product stock movement ledger
+#[near_bindgen]
+impl Product{
+    // newest first
+    pub fn get_stock_ledger(&self, address: String, limit: u64) -> Vec<StockMovement> {
+        let ledger = self.stock_ledger.get(&address).unwrap_or_default();
+        ledger.into_iter().rev().take(limit as usize).collect()
+    }
+
+    pub(crate) fn record_stock_movement(&mut self, address: &String, old_stock: u8, new_stock: u8, reason: StockReason) {
+        if old_stock == new_stock {
+            return;
+        }
+        self.log_stock_change(address, old_stock, new_stock, &reason);
+        let mut ledger = self.stock_ledger.get(address).unwrap_or_default();
+        if ledger.len() >= STOCK_LEDGER_CAPACITY {
+            ledger.remove(0);
+        }
+        ledger.push(StockMovement {
+            delta: new_stock as i32 - old_stock as i32,
+            reason,
+            stock_after: new_stock,
+            actor: env::signer_account_id(),
+            moved_at: env::block_timestamp(),
+        });
+        self.stock_ledger.insert(address, &ledger);
+    }
+}
+
+// sales velocity metrics window
+#[near_bindgen]
+impl Product{
+    // units sold over the last day
+    pub fn get_sales_velocity(&self, address: String) -> SalesVelocity {
+        let since = env::block_timestamp().saturating_sub(SALES_WINDOW);
+        let units = self.sales_buckets.get(&address).unwrap_or_default().buckets.iter()
+            .filter(|(start, _)| start + SALES_BUCKET > since)
+            .map(|(_, units)| units)
+            .sum();
+        SalesVelocity { units, window: SALES_WINDOW }
+    }
+
+    pub(crate) fn record_sale(&mut self, address: &String, quantity: u8) {
+        let now = env::block_timestamp();
+        let bucket = now - now % SALES_BUCKET;
+        let mut sales = self.sales_buckets.get(address).unwrap_or_default();
+        sales.buckets.retain(|(start, _)| start + SALES_WINDOW > bucket);
+        match sales.buckets.last_mut() {
+            Some((start, units)) if *start == bucket => *units += quantity as u32,
+            _ => sales.buckets.push((bucket, quantity as u32)),
+        }
+        self.sales_buckets.insert(address, &sales);
+    }
+}
@@ -0,0 +1,345 @@
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::U128;
+use near_sdk::serde::{Serialize, Deserialize};
+use near_sdk::{env, near_bindgen, AccountId};
+
+use crate::{Item, Product};
+use crate::catalog::{normalize_name, LifecycleState, Visibility};
+use crate::moderation::Approval;
+use crate::ProductContract;
+use crate::batch::{BatchSummary, MAX_BATCH_SIZE};
+use crate::history::{PricePoint, StockMovement, StockReason};
+use crate::utils::pagination::scan;
+
+// same per-record overhead the protocol charges, see storage_num_extra_bytes_record
+const STORAGE_BYTES_PER_RECORD:u64 = 40;
+const MAX_ACCOUNT_ID_LEN:usize = 64;
+// tree node besides its key: id, left and right links, height
+const TREE_NODE_BYTES:u64 = 8 + 9 + 9 + 8;
+// a vendor tree prefix, b"q" and the sha256 of the account
+const VENDOR_PREFIX_LEN:u64 = 33;
+
+const MAX_OPEN_RESTOCK_REQUESTS:usize = 50;
+const MAX_LOCATIONS:usize = 20;
+
+// stock asked for by the warehouse, read by the supplier side and closed by increase_stock
+#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RestockRequest {
+     pub address: String,
+     pub quantity: u32,
+     pub requested_by: AccountId,
+     pub requested_at: u64,
+     pub fulfilled_at: Option<u64>,
+}
+
+// partial sum over one page of the catalog, cursor is None once the catalog is exhausted
+#[derive(Debug)]
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct InventoryValue {
+     pub value: U128,
+     pub cursor: Option<String>,
+}
+
+// inventory valuation
+#[near_bindgen]
+impl Product{
+    pub fn get_inventory_value(&self, cursor: Option<String>, limit: u64) -> InventoryValue {
+        let page = scan(&self.ordered_keys, &self.cursor_secret, cursor, limit, |address| {
+            self.load_product(&address).map(|item| item.price.saturating_mul(item.stock as u128))
+        });
+        let value = page.items.iter().fold(0u128, |sum, value| sum.saturating_add(*value));
+        InventoryValue { value: value.into(), cursor: page.cursor }
+    }
+}
+
+// compare-and-swap stock updates
+#[near_bindgen]
+impl Product{
+    // for warehouse reconciliation, never clobbers a sale that landed in between
+    pub fn set_stock_if(&mut self, address: String, expected_stock: u8, new_stock: u8) {
+        //validate sender has permition of ROLE_SET_PRODUCT
+        self.assert_permission("set_stock_if");
+        let item = self.load_product(&address).expect("Product does not exist");
+        self.assert_editable(&item);
+        self.assert_creator_or_override(&item, false);
+        assert_eq!(item.stock, expected_stock, "Stock was modified concurrently");
+
+        let reason = if new_stock > item.stock { StockReason::Restock } else { StockReason::Correction };
+        let old_stock = item.stock;
+        self.internal_set_stock(&address, item, new_stock, reason);
+        self.emit_event("set_stock", format!("{} {} -> {}", address, old_stock, new_stock));
+    }
+
+    // signed deltas, each with a reason code of stock-take, damage or theft
+    pub fn adjust_stock_batch(&mut self, entries: Vec<(String, i32, String)>) -> BatchSummary {
+        //validate sender has permition of ROLE_SET_PRODUCT
+        self.assert_permission("adjust_stock_batch");
+        assert!(entries.len() <= MAX_BATCH_SIZE, "Too many entries in one batch");
+
+        let mut summary = BatchSummary::default();
+        self.begin_batch();
+        for (address, delta, code) in entries {
+            let reason = match StockReason::from_code(&code) {
+                Some(reason) => reason,
+                None => {
+                    summary.failed.push((address, format!("Unknown reason code {}", code)));
+                    continue;
+                },
+            };
+            let item = match self.load_product(&address) {
+                Some(item) => item,
+                None => {
+                    summary.failed.push((address, "Product does not exist".to_string()));
+                    continue;
+                },
+            };
+            if let Err(error) = self.check_editable(&item) {
+                summary.failed.push((address, error));
+                continue;
+            }
+            if !self.is_creator_or_override(&item, false) {
+                summary.failed.push((address, "401".to_string()));
+                continue;
+            }
+            let new_stock = item.stock as i32 + delta;
+            if new_stock < 0 || new_stock > u8::MAX as i32 {
+                summary.failed.push((address, "Stock out of range".to_string()));
+                continue;
+            }
+            self.internal_set_stock(&address, item, new_stock as u8, reason);
+            summary.updated.push(address);
+        }
+        self.finish_batch("adjust_stock_batch", &summary);
+        summary
+    }
+
+    // callers emit their own event, batches log a single summary
+    pub(crate) fn internal_set_stock(&mut self, address: &String, mut item: Item, new_stock: u8, reason: StockReason) {
+        let old_stock = item.stock;
+        item.stock = new_stock;
+        item.updated_by = env::signer_account_id();
+        self.save_product(address, &mut item);
+        self.notify_listeners(address, "set_stock");
+        self.check_stock_thresholds(address, old_stock, new_stock);
+        self.record_stock_movement(address, old_stock, new_stock, reason);
+    }
+}
+
+// per-product storage footprint
+#[near_bindgen]
+impl Product{
+    // estimated bytes of the record, its index entries and its histories, multiply by the storage byte cost
+    // for the deposit; unknown addresses are estimated as a fresh listing with the worst case account id length
+    pub fn get_storage_usage(&self, address: String, name: Option<String>) -> u64 {
+        let mut item = self.records.get(&address).unwrap_or_else(|| Item {
+            name: String::new(),
+            price: 0,
+            stock: 0,
+            frozen: false,
+            creator: "a".repeat(MAX_ACCOUNT_ID_LEN),
+            updated_by: "a".repeat(MAX_ACCOUNT_ID_LEN),
+            approval: Approval::Approved,
+            report_count: 0,
+            listing_deposit: 0,
+            version: 0,
+            search_key: String::new(),
+            scheduled_price: None,
+            max_per_buyer: 0,
+            preorder_until: None,
+            backorders: false,
+            rental: None,
+            price_decay: None,
+            visibility: Visibility::Public,
+            state: LifecycleState::Active,
+            pay_what_you_want: false,
+            usd_price: None,
+        });
+        if let Some(name) = name {
+            item.search_key = normalize_name(&name);
+            item.name = name;
+        }
+        let key = address.try_to_vec().unwrap().len() as u64;
+        let value = item.try_to_vec().unwrap().len() as u64;
+        let search_key = item.search_key.try_to_vec().unwrap().len() as u64;
+        // a fresh listing starts with one price point and one stock movement
+        let prices = self.price_history.get(&address)
+            .unwrap_or_else(|| vec![PricePoint { price: item.price, changed_at: 0 }]);
+        let movements = self.stock_ledger.get(&address)
+            .unwrap_or_else(|| vec![StockMovement { delta: 0, reason: StockReason::Restock, stock_after: 0, actor: item.creator.clone(), moved_at: 0 }]);
+
+        // records entry: prefix + key -> item
+        let record = 1 + key + value + STORAGE_BYTES_PER_RECORD;
+        // key index entries: element (prefix + u64 -> key) and position (prefix + key -> u64)
+        let index = (2 + 8 + key + STORAGE_BYTES_PER_RECORD) + (2 + key + 8 + STORAGE_BYTES_PER_RECORD);
+        // ordered_keys and the vendor tree: value (prefix + key -> ()) and node (prefix + u64 -> node)
+        let tree_entry = |prefix: u64| (prefix + 1 + key + STORAGE_BYTES_PER_RECORD) + (prefix + 1 + 8 + TREE_NODE_BYTES + key + STORAGE_BYTES_PER_RECORD);
+        let trees = tree_entry(1) + tree_entry(VENDOR_PREFIX_LEN);
+        // name index entry: prefix + search key -> list of keys, counted as if the name were not shared
+        let names = 1 + search_key + 4 + key + STORAGE_BYTES_PER_RECORD;
+        // price history, stock ledger and sales buckets: prefix + key -> list
+        let list_entry = |bytes: usize| 1 + key + bytes as u64 + STORAGE_BYTES_PER_RECORD;
+        let histories = list_entry(prices.try_to_vec().unwrap().len())
+            + list_entry(movements.try_to_vec().unwrap().len())
+            + self.sales_buckets.get(&address).map(|sales| list_entry(sales.try_to_vec().unwrap().len())).unwrap_or(0);
+        record + index + trees + names + histories
+    }
+}
+
+// restock requests
+#[near_bindgen]
+impl Product{
+    pub fn request_restock(&mut self, address: String, quantity: u32) -> u64 {
+        //validate sender has permition of ROLE_WAREHOUSE
+        self.assert_permission("request_restock");
+        assert!(self.records.contains_key(&address), "Product does not exist");
+        assert!(quantity > 0, "Quantity must be positive");
+        let mut open = self.open_restock_requests.get(&address).unwrap_or_default();
+        assert!(open.len() < MAX_OPEN_RESTOCK_REQUESTS, "Too many open restock requests");
+
+        let request_id = self.next_restock_id;
+        self.next_restock_id += 1;
+        let request = RestockRequest {
+            address: address.clone(),
+            quantity,
+            requested_by: env::signer_account_id(),
+            requested_at: env::block_timestamp(),
+            fulfilled_at: None,
+        };
+        self.emit_event("request_restock", format!("{} {:?}", request_id, request));
+        self.restock_requests.insert(&request_id, &request);
+        open.push(request_id);
+        self.open_restock_requests.insert(&address, &open);
+        request_id
+    }
+
+    pub fn get_restock_request(&self, request_id: u64) -> Option<RestockRequest> {
+        self.restock_requests.get(&request_id)
+    }
+
+    // oldest first
+    pub fn get_open_restock_requests(&self, address: String) -> Vec<(u64, RestockRequest)> {
+        self.open_restock_requests.get(&address).unwrap_or_default().into_iter()
+            .filter_map(|request_id| self.restock_requests.get(&request_id).map(|request| (request_id, request)))
+            .collect()
+    }
+
+    // the restocked quantity closes open requests oldest first, stopping at the first one it does not cover
+    pub(crate) fn fulfill_restock_requests(&mut self, address: &String, quantity: u32) {
+        let mut open = self.open_restock_requests.get(address).unwrap_or_default();
+        let mut remaining = quantity;
+        let mut fulfilled = 0;
+        for request_id in open.iter() {
+            let mut request = self.restock_requests.get(request_id).unwrap();
+            if request.quantity > remaining {
+                break;
+            }
+            remaining -= request.quantity;
+            request.fulfilled_at = Some(env::block_timestamp());
+            self.restock_requests.insert(request_id, &request);
+            self.emit_event("fulfill_restock_request", request_id.to_string());
+            fulfilled += 1;
+        }
+        if fulfilled == 0 {
+            return;
+        }
+        open.drain(..fulfilled);
+        if open.is_empty() {
+            self.open_restock_requests.remove(address);
+        } else {
+            self.open_restock_requests.insert(address, &open);
+        }
+    }
+}
+
+// stock locations
+#[near_bindgen]
+impl Product{
+    // counts a location and moves the product total by the same delta
+    pub fn set_location_stock(&mut self, address: String, location: String, quantity: u32) {
+        //validate sender has permition of ROLE_WAREHOUSE
+        self.assert_permission("set_location_stock");
+        let item = self.load_product(&address).expect("Product does not exist");
+        self.assert_editable(&item);
+        let key = (address.clone(), location.clone());
+        let old_quantity = self.location_stock.get(&key).unwrap_or(0);
+        let new_stock = (item.stock as u32 + quantity).checked_sub(old_quantity).expect("Stock is below the counted locations");
+        assert!(new_stock <= u8::MAX as u32, "Stock out of range");
+        assert!(self.counted_stock(&address) - old_quantity + quantity <= new_stock, "Locations would exceed the product stock");
+
+        let mut locations = self.product_locations.get(&address).unwrap_or_default();
+        if !locations.contains(&location) {
+            assert!(locations.len() < MAX_LOCATIONS, "Too many stock locations");
+            locations.push(location.clone());
+            self.product_locations.insert(&address, &locations);
+        }
+        self.location_stock.insert(&key, &quantity);
+        let reason = if quantity > old_quantity { StockReason::Restock } else { StockReason::Correction };
+        self.internal_set_stock(&address, item, new_stock as u8, reason);
+        self.emit_event("set_location_stock", format!("{} {} {} -> {}", address, location, old_quantity, quantity));
+    }
+
+    // moves units between depots, the product total does not change
+    pub fn transfer_stock(&mut self, address: String, from: String, to: String, quantity: u32) {
+        //validate sender has permition of ROLE_WAREHOUSE
+        self.assert_permission("transfer_stock");
+        assert!(self.records.contains_key(&address), "Product does not exist");
+        assert!(from != to, "Transfer needs two different locations");
+        let from_key = (address.clone(), from.clone());
+        let available = self.location_stock.get(&from_key).unwrap_or(0);
+        assert!(available >= quantity, "Not enough stock at {}", from);
+
+        let mut locations = self.product_locations.get(&address).unwrap_or_default();
+        if !locations.contains(&to) {
+            assert!(locations.len() < MAX_LOCATIONS, "Too many stock locations");
+            locations.push(to.clone());
+            self.product_locations.insert(&address, &locations);
+        }
+        let to_key = (address.clone(), to.clone());
+        let received = self.location_stock.get(&to_key).unwrap_or(0) + quantity;
+        self.location_stock.insert(&from_key, &(available - quantity));
+        self.location_stock.insert(&to_key, &received);
+        self.emit_event("transfer_stock", format!("{} {} -> {} {}", address, from, to, quantity));
+    }
+
+    pub fn get_location_stock(&self, address: String) -> Vec<(String, u32)> {
+        self.product_locations.get(&address).unwrap_or_default().into_iter()
+            .map(|location| {
+                let quantity = self.location_stock.get(&(address.clone(), location.clone())).unwrap_or(0);
+                (location, quantity)
+            })
+            .collect()
+    }
+
+    pub fn get_available_at(&self, address: String, location: String) -> u32 {
+        self.location_stock.get(&(address, location)).unwrap_or(0)
+    }
+
+    // sales and corrections name no location, they use up units not counted at any location first and
+    // then draw the locations in the order they were added, so the locations never add up to more than the total
+    pub(crate) fn draw_location_stock(&mut self, address: &String, total: u8) {
+        let locations = match self.product_locations.get(address) {
+            Some(locations) => locations,
+            None => return,
+        };
+        let mut counted = self.counted_stock(address);
+        for location in locations {
+            if counted <= total as u32 {
+                break;
+            }
+            let key = (address.clone(), location);
+            let quantity = self.location_stock.get(&key).unwrap_or(0);
+            let drawn = std::cmp::min(quantity, counted - total as u32);
+            self.location_stock.insert(&key, &(quantity - drawn));
+            counted -= drawn;
+        }
+    }
+
+    fn counted_stock(&self, address: &String) -> u32 {
+        self.product_locations.get(address).unwrap_or_default().into_iter()
+            .map(|location| self.location_stock.get(&(address.clone(), location)).unwrap_or(0))
+            .sum()
+    }
+}
@@ -1,12 +1,65 @@
+mod affiliates;
+mod auctions;
+mod batch;
+mod breaker;
+mod campaigns;
+mod catalog;
+mod config;
+mod events;
+mod experiments;
+mod fulfillment;
+mod gating;
+mod history;
+mod inventory;
+mod listeners;
+mod maintenance;
+mod moderation;
+mod offers;
+mod oracle;
+mod orders;
+mod pause;
+mod procurement;
+mod purchase;
+mod revenue;
+mod revisions;
+mod staking;
+mod subscriptions;
+mod trash;
 mod utils;
 
 use near_contract_standards::upgrade::Ownable;
 // To conserve gas, efficient serialization is achieved through Borsh (http://borsh.io/)
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::collections::LookupMap;
+use near_sdk::collections::{LazyOption, LookupMap, TreeMap, UnorderedSet, Vector};
 use near_sdk::serde::{Serialize, Deserialize};
 use near_sdk::{env, near_bindgen, setup_alloc, AccountId};
-use utils::access_control::AccessControl;
+use affiliates::{AffiliateCode, AffiliateStats};
+use auctions::Auction;
+use batch::{BatchLog, MAX_BATCH_SIZE};
+use breaker::CircuitBreaker;
+use campaigns::Campaign;
+use catalog::{normalize_name, LifecycleState, Visibility};
+use config::Config;
+use maintenance::{PrefixMigration, RECORDS_PREFIX, ROLES_PREFIX};
+use inventory::RestockRequest;
+use experiments::PriceExperiment;
+use gating::TokenGate;
+use history::{PriceDecay, PricePoint, ScheduledPrice, SalesBuckets, StockMovement, StockReason};
+use listeners::DEFAULT_LISTENER_GAS;
+use moderation::{Approval, Report};
+use offers::{Offer, Quote};
+use oracle::{DisplayRate, UsdRate};
+use orders::{Order, RentalTerms};
+use procurement::PurchaseOrder;
+use purchase::{AccountActivity, PurchaseWindow};
+use revenue::Payout;
+use revisions::Revision;
+use staking::{Stake, StakeTier};
+use subscriptions::{Subscription, SubscriptionPlan};
+use trash::TrashedProduct;
+use utils::access_control::{AccessControl, RoleChange};
+use utils::pagination::{scan, Page};
+use utils::ring_log::RingLog;
 
 setup_alloc!();
 
@@ -16,10 +69,146 @@ pub struct Product {
     records: LookupMap<String, Item>,
     owner: AccountId,
     access: AccessControl,
+    two_person_delete: bool,
+    delete_confirm_window: u64,
+    pending_deletes: LookupMap<String, PendingDelete>,
+    timelock_delay: u64,
+    pending_actions: LookupMap<u64, PendingAction>,
+    next_action_id: u64,
+    audit_log: RingLog<AuditEntry>,
+    recent_events: RingLog<Event>,
+    listeners: Vec<AccountId>,
+    listener_gas: u64,
+    fulfillment: Option<AccountId>,
+    reorder_threshold: u8,
+    metadata: LazyOption<StoreMetadata>,
+    purchase_windows: Vec<PurchaseWindow>,
+    moderation_required: bool,
+    product_keys: UnorderedSet<String>,
+    reports: LookupMap<String, Vec<Report>>,
+    reported_products: TreeMap<String, ()>,
+    quarantine_threshold: u32,
+    listing_deposit: u128,
+    treasury: AccountId,
+    price_history: LookupMap<String, Vec<PricePoint>>,
+    stock_ledger: LookupMap<String, Vec<StockMovement>>,
+    sales_buckets: LookupMap<String, SalesBuckets>,
+    state_nonce: u64,
+    // block of the last touch, what the state_nonce was reached at
+    last_mutation_block: u64,
+    prefix_migration: PrefixMigration,
+    config: LazyOption<Config>,
+    vendor_products: LookupMap<AccountId, TreeMap<String, ()>>,
+    name_index: LookupMap<String, Vec<String>>,
+    ordered_keys: TreeMap<String, ()>,
+    // salts cursor tags against accidental edits, public like the rest of the state
+    cursor_secret: Vec<u8>,
+    units_bought: LookupMap<(String, AccountId), u32>,
+    account_activity: LookupMap<AccountId, AccountActivity>,
+    orders: LookupMap<u64, Order>,
+    next_order_id: u64,
+    product_orders: LookupMap<String, Vec<u64>>,
+    subscription_plans: LookupMap<u64, SubscriptionPlan>,
+    next_plan_id: u64,
+    subscriptions: Vector<Subscription>,
+    charge_cursor: u64,
+    auctions: LookupMap<u64, Auction>,
+    next_auction_id: u64,
+    offers: LookupMap<u64, Offer>,
+    next_offer_id: u64,
+    product_offers: LookupMap<String, Vec<u64>>,
+    quotes: LookupMap<u64, Quote>,
+    next_quote_id: u64,
+    invitees: LookupMap<String, Vec<AccountId>>,
+    revisions: LookupMap<String, Revision>,
+    previous_revisions: LookupMap<String, Revision>,
+    restock_requests: LookupMap<u64, RestockRequest>,
+    next_restock_id: u64,
+    open_restock_requests: LookupMap<String, Vec<u64>>,
+    suppliers: UnorderedSet<AccountId>,
+    purchase_orders: LookupMap<u64, PurchaseOrder>,
+    next_purchase_order_id: u64,
+    location_stock: LookupMap<(String, String), u32>,
+    product_locations: LookupMap<String, Vec<String>>,
+    payment_splits: LookupMap<String, Vec<(AccountId, u16)>>,
+    vendor_tips: LookupMap<AccountId, u128>,
+    fee_exemptions: UnorderedSet<AccountId>,
+    revenue: LookupMap<AccountId, u128>,
+    auto_payout_vendors: UnorderedSet<AccountId>,
+    payout_thresholds: LookupMap<AccountId, u128>,
+    payout_cursor: u64,
+    payout_history: LookupMap<AccountId, Vec<Payout>>,
+    claimable: LookupMap<AccountId, u128>,
+    wnear_vendors: UnorderedSet<AccountId>,
+    usd_rate: Option<UsdRate>,
+    display_rates: Vec<DisplayRate>,
+    token_gates: LookupMap<String, TokenGate>,
+    stakes: LookupMap<AccountId, Stake>,
+    stake_tiers: Vec<StakeTier>,
+    affiliate_codes: LookupMap<String, AffiliateCode>,
+    affiliate_stats: LookupMap<String, AffiliateStats>,
+    campaigns: LookupMap<u64, Campaign>,
+    next_campaign_id: u64,
+    product_campaigns: LookupMap<String, Vec<u64>>,
+    price_experiments: LookupMap<String, PriceExperiment>,
+    tombstones: LookupMap<String, Tombstone>,
+    trash: LookupMap<String, TrashedProduct>,
+    trash_keys: TreeMap<String, ()>,
+    role_managers: LookupMap<String, Vec<AccountId>>,
+    paused: bool,
+    breaker: CircuitBreaker,
+    #[borsh_skip]
+    batch_log: Option<BatchLog>,
 }
 
 const ROLE_SET_PRODUCT:&str = "ROLE_SET_PRODUCT";
 const ROLE_DELETE_PRODUCT:&str = "ROLE_DELETE_PRODUCT";
+const ROLE_MODERATOR:&str = "ROLE_MODERATOR";
+const ROLE_WAREHOUSE:&str = "ROLE_WAREHOUSE";
+const ROLE_SHIPPER:&str = "ROLE_SHIPPER";
+const ROLE_KEEPER:&str = "ROLE_KEEPER";
+const ROLE_PURGE:&str = "ROLE_PURGE";
+const ROLE_GUARDIAN:&str = "ROLE_GUARDIAN";
+const ALL_ROLES:[&str; 8] = [ROLE_SET_PRODUCT, ROLE_DELETE_PRODUCT, ROLE_MODERATOR, ROLE_WAREHOUSE, ROLE_SHIPPER, ROLE_KEEPER, ROLE_PURGE, ROLE_GUARDIAN];
+// which role may call which method; the guards read it through assert_permission, so get_permissions cannot drift from them
+const PERMISSIONS:[(&str, &str); 32] = [
+    ("set_products", ROLE_SET_PRODUCT),
+    ("set_products_batch", ROLE_SET_PRODUCT),
+    ("save_draft", ROLE_SET_PRODUCT),
+    ("clone_product", ROLE_SET_PRODUCT),
+    ("set_stock_if", ROLE_SET_PRODUCT),
+    ("adjust_stock_batch", ROLE_SET_PRODUCT),
+    ("increase_stock", ROLE_SET_PRODUCT),
+    ("adjust_prices", ROLE_SET_PRODUCT),
+    ("set_price_effective_at", ROLE_SET_PRODUCT),
+    ("set_price_decay", ROLE_SET_PRODUCT),
+    ("stage_revision", ROLE_SET_PRODUCT),
+    ("publish_revision", ROLE_SET_PRODUCT),
+    ("rollback", ROLE_SET_PRODUCT),
+    ("delete_products", ROLE_DELETE_PRODUCT),
+    ("confirm_delete", ROLE_DELETE_PRODUCT),
+    ("restore_product", ROLE_DELETE_PRODUCT),
+    ("set_approval", ROLE_MODERATOR),
+    ("dismiss_reports", ROLE_MODERATOR),
+    ("freeze_product", ROLE_MODERATOR),
+    ("unfreeze_product", ROLE_MODERATOR),
+    ("resolve_dispute", ROLE_MODERATOR),
+    ("request_restock", ROLE_WAREHOUSE),
+    ("set_location_stock", ROLE_WAREHOUSE),
+    ("transfer_stock", ROLE_WAREHOUSE),
+    ("create_purchase_order", ROLE_WAREHOUSE),
+    ("receive_shipment", ROLE_WAREHOUSE),
+    ("mark_shipped", ROLE_SHIPPER),
+    ("mark_delivered", ROLE_SHIPPER),
+    ("update_usd_rate", ROLE_KEEPER),
+    ("update_display_rate", ROLE_KEEPER),
+    ("purge_product", ROLE_PURGE),
+    ("pause", ROLE_GUARDIAN),
+];
+// one day, in nanoseconds
+const DEFAULT_DELETE_CONFIRM_WINDOW:u64 = 24 * 60 * 60 * 1_000_000_000;
+const AUDIT_LOG_CAPACITY:u64 = 1000;
+const RECENT_EVENTS_CAPACITY:u64 = 100;
 
 #[derive(Debug)]
 #[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
@@ -27,7 +216,149 @@ const ROLE_DELETE_PRODUCT:&str = "ROLE_DELETE_PRODUCT";
 pub struct Item {
      name: String,
      price: u128,
-     stock: u8
+     stock: u8,
+     frozen: bool,
+     creator: AccountId,
+     updated_by: AccountId,
+     approval: Approval,
+     report_count: u32,
+     listing_deposit: u128,
+     version: u64,
+     // lowercased, trimmed name, what name lookups match against
+     search_key: String,
+     scheduled_price: Option<ScheduledPrice>,
+     // 0 means no cap on how many units one account may buy in total
+     max_per_buyer: u32,
+     // expected fulfillment date while purchases are taken as pre-orders
+     preorder_until: Option<u64>,
+     // purchases beyond the stock are queued instead of refused
+     backorders: bool,
+     rental: Option<RentalTerms>,
+     price_decay: Option<PriceDecay>,
+     visibility: Visibility,
+     state: LifecycleState,
+     // price is the minimum, the buyer pays whatever they attach above it
+     pay_what_you_want: bool,
+     // in US cents, converted to yoctoNEAR at the cached rate on every read
+     usd_price: Option<u64>,
+}
+
+impl Item {
+    // a scheduled price replaces the stored one as soon as it is due, a decaying price is
+    // computed for the current block, reads never see a stale price
+    fn resolve_price(&mut self, now: u64) {
+        if let Some(scheduled) = &self.scheduled_price {
+            if scheduled.effective_at <= now {
+                self.price = scheduled.price;
+                self.scheduled_price = None;
+            }
+        }
+        if let Some(decay) = &self.price_decay {
+            self.price = decay.price_at(now);
+        }
+    }
+}
+
+// get_products answer, NotModified spares polling clients from downloading an unchanged item;
+// Deleted tells a dead link from a key that never existed
+#[derive(Debug)]
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub enum ProductLookup {
+    Found(Box<Item>),
+    NotModified { version: u64 },
+    Deleted { deleted_at: u64, deleted_by: AccountId },
+    NotFound,
+}
+
+// what is left of a deleted listing, cleared when the key is listed again
+#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Tombstone {
+     deleted_at: u64,
+     deleted_by: AccountId,
+}
+
+// a deletion waiting for a second ROLE_DELETE_PRODUCT holder
+#[derive(Debug)]
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PendingDelete {
+     requested_by: AccountId,
+     requested_at: u64,
+     forfeit_deposit: bool,
+}
+
+// destructive operations that are held back by the timelock
+#[derive(Debug)]
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub enum ActionKind {
+    DeleteProduct { address: String, forfeit_deposit: bool },
+    SetOwner { owner: AccountId },
+    SetTimelockDelay { delay: u64 },
+}
+
+#[derive(Debug)]
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PendingAction {
+     kind: ActionKind,
+     queued_by: AccountId,
+     executable_at: u64,
+}
+
+// a privileged action, kept on chain for auditors
+#[derive(Debug)]
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AuditEntry {
+     actor: AccountId,
+     action: String,
+     target: String,
+     timestamp: u64,
+}
+
+// an emitted log line, also kept in storage so clients can catch up with view calls
+#[derive(Debug)]
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Event {
+     seq: u64,
+     event: String,
+     data: String,
+     block_index: u64,
+     timestamp: u64,
+}
+
+// branding of the whole store, rendered by aggregators
+#[derive(Debug)]
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StoreMetadata {
+     name: String,
+     logo_cid: Option<String>,
+     contact: Option<String>,
+     policy_hash: Option<String>,
+}
+
+// indexers compare the nonce with the one from their last sync
+#[derive(Debug)]
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Checkpoint {
+     state_nonce: u64,
+     block_height: u64,
+}
+
+// where the last mutation landed, a cache holding the same nonce is up to date
+#[derive(Debug)]
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SyncHead {
+     state_nonce: u64,
+     block_height: u64,
 }
 
 impl Default for Product {
@@ -43,6 +374,7 @@ impl Ownable for Product{
 
     fn set_owner(&mut self, owner: AccountId) {
         self.assert_owner();
+        self.audit("set_owner", &owner);
         self.owner = owner;
     }
 }
@@ -53,13 +385,191 @@ impl Product{
     pub fn add_role_set_product(&mut self, account: AccountId ){
         //validate if owner
         self.assert_owner();
-        self.access.setup_role(ROLE_SET_PRODUCT.to_string(), account);
+        self.grant_role(ROLE_SET_PRODUCT, account);
     }
 
     pub fn add_role_delete_product(&mut self, account: AccountId ){
         //validate if owner
         self.assert_owner();
-        self.access.setup_role(ROLE_DELETE_PRODUCT.to_string(), account);
+        self.grant_role(ROLE_DELETE_PRODUCT, account);
+    }
+
+    pub fn add_role_moderator(&mut self, account: AccountId ){
+        //validate if owner
+        self.assert_owner();
+        self.grant_role(ROLE_MODERATOR, account);
+    }
+
+    pub fn add_role_warehouse(&mut self, account: AccountId ){
+        //validate if owner
+        self.assert_owner();
+        self.grant_role(ROLE_WAREHOUSE, account);
+    }
+
+    pub fn add_role_shipper(&mut self, account: AccountId ){
+        //validate if owner
+        self.assert_owner();
+        self.grant_role(ROLE_SHIPPER, account);
+    }
+
+    pub fn add_role_keeper(&mut self, account: AccountId ){
+        //validate if owner
+        self.assert_owner();
+        self.grant_role(ROLE_KEEPER, account);
+    }
+
+    pub fn add_role_purge(&mut self, account: AccountId ){
+        //validate if owner
+        self.assert_owner();
+        self.grant_role(ROLE_PURGE, account);
+    }
+
+    pub fn add_role_guardian(&mut self, account: AccountId ){
+        //validate if owner
+        self.assert_owner();
+        self.grant_role(ROLE_GUARDIAN, account);
+    }
+
+    // role -> methods it unlocks, for wallets greying out what the user cannot call
+    pub fn get_permissions(&self) -> Vec<(String, Vec<String>)> {
+        ALL_ROLES.iter()
+            .map(|role| (role.to_string(), PERMISSIONS.iter().filter(|(_, r)| r == role).map(|(method, _)| method.to_string()).collect()))
+            .collect()
+    }
+
+    pub fn can_call(&self, method: String, account: AccountId) -> bool {
+        PERMISSIONS.iter().any(|(name, role)| *name == method && self.access.has_role(role, &account))
+    }
+
+    fn assert_permission(&self, method: &str) {
+        let role = PERMISSIONS.iter().find(|(name, _)| *name == method).map(|(_, role)| *role).expect("Unknown permission");
+        assert!(self.access.has_role(role, &env::signer_account_id()), "401");
+    }
+
+    // pushes a grant out by another config.role_term from now, lapsed grants included
+    pub fn renew_role(&mut self, role: String, account: AccountId) -> u64 {
+        //validate if owner
+        self.assert_owner();
+        assert!(self.access.is_member(&role, &account), "Account does not hold this role");
+        let role_term = self.config().role_term;
+        assert!(role_term > 0, "Role grants do not expire");
+        let expires_at = env::block_timestamp() + role_term;
+        self.audit(&format!("renew {}", role), &account);
+        self.access.set_expiry(&role, &account, Some(expires_at));
+        expires_at
+    }
+
+    // for contractors on a fixed engagement, None makes the grant permanent
+    pub fn set_role_expiry(&mut self, role: String, account: AccountId, expires_at: Option<u64>) {
+        //validate if owner
+        self.assert_owner();
+        assert!(self.access.is_member(&role, &account), "Account does not hold this role");
+        self.audit(&format!("expire {} at {:?}", role, expires_at), &account);
+        self.access.set_expiry(&role, &account, expires_at);
+    }
+
+    pub fn get_role_history(&self, role: String, from_index: u64, limit: u64) -> Vec<RoleChange> {
+        self.access.history(&role, from_index, limit)
+    }
+
+    pub fn get_role_expiry(&self, role: String, account: AccountId) -> Option<u64> {
+        self.access.expiry(&role, &account)
+    }
+
+    // grants lapsing within `within` nanoseconds, and those that already have, soonest first
+    pub fn get_expiring_roles(&self, within: u64) -> Vec<(String, AccountId, u64)> {
+        let horizon = env::block_timestamp().saturating_add(within);
+        let mut expiring: Vec<(String, AccountId, u64)> = ALL_ROLES.iter()
+            .flat_map(|role| self.access.roles.get(&role.to_string()).unwrap_or_default().into_iter().map(move |account| (role.to_string(), account)))
+            .filter_map(|(role, account)| self.access.expiry(&role, &account).map(|expires_at| (role, account, expires_at)))
+            .filter(|(_, _, expires_at)| *expires_at <= horizon)
+            .collect();
+        expiring.sort_by_key(|(_, _, expires_at)| *expires_at);
+        expiring
+    }
+
+    // a manager can grant and revoke that one role and nothing else, e.g. HR onboarding catalog editors
+    pub fn add_role_manager(&mut self, role: String, account: AccountId) {
+        //validate if owner
+        self.assert_owner();
+        assert!(ALL_ROLES.contains(&role.as_str()), "Unknown role");
+        let mut managers = self.role_managers.get(&role).unwrap_or_default();
+        if !managers.contains(&account) {
+            self.audit(&format!("add manager of {}", role), &account);
+            managers.push(account);
+            self.role_managers.insert(&role, &managers);
+        }
+    }
+
+    pub fn remove_role_manager(&mut self, role: String, account: AccountId) {
+        //validate if owner
+        self.assert_owner();
+        let mut managers = self.role_managers.get(&role).unwrap_or_default();
+        managers.retain(|manager| *manager != account);
+        self.audit(&format!("remove manager of {}", role), &account);
+        self.role_managers.insert(&role, &managers);
+    }
+
+    pub fn get_role_managers(&self, role: String) -> Vec<AccountId> {
+        self.role_managers.get(&role).unwrap_or_default()
+    }
+
+    // open to the owner and the role's managers
+    pub fn grant_role_to(&mut self, role: String, account: AccountId) {
+        self.assert_role_manager(&role);
+        self.grant_role(&role, account);
+    }
+
+    pub fn revoke_role_from(&mut self, role: String, account: AccountId) {
+        self.assert_role_manager(&role);
+        assert!(self.access.is_member(&role, &account), "Account does not hold this role");
+        self.revoke_role(&role, &account);
+    }
+
+    // for moving a whole team onto the contract, every entry is checked as if granted one by one
+    pub fn grant_roles(&mut self, entries: Vec<(String, AccountId)>) {
+        assert!(entries.len() <= MAX_BATCH_SIZE, "Too many entries in one batch");
+        for (role, account) in entries {
+            self.assert_role_manager(&role);
+            self.grant_role(&role, account);
+        }
+    }
+
+    // entries the account does not hold are skipped
+    pub fn revoke_roles(&mut self, entries: Vec<(String, AccountId)>) {
+        assert!(entries.len() <= MAX_BATCH_SIZE, "Too many entries in one batch");
+        for (role, account) in entries {
+            self.assert_role_manager(&role);
+            if self.access.is_member(&role, &account) {
+                self.revoke_role(&role, &account);
+            }
+        }
+    }
+
+    fn assert_role_manager(&self, role: &str) {
+        assert!(ALL_ROLES.contains(&role), "Unknown role");
+        let is_manager = self.role_managers.get(&role.to_string()).unwrap_or_default().contains(&env::signer_account_id());
+        assert!(env::predecessor_account_id() == self.owner || is_manager, "401");
+    }
+
+    // for staff leaving, no owner needed to drop one's own access
+    pub fn renounce_role(&mut self, role: String) {
+        let account = env::signer_account_id();
+        assert!(self.access.is_member(&role, &account), "Account does not hold this role");
+        self.revoke_role(&role, &account);
+    }
+
+    fn grant_role(&mut self, role: &str, account: AccountId) {
+        self.audit(&format!("grant {}", role), &account);
+        let role_term = self.config().role_term;
+        let expires_at = if role_term > 0 { Some(env::block_timestamp() + role_term) } else { None };
+        self.access.set_expiry(role, &account, expires_at);
+        self.access.setup_role(role.to_string(), account);
+    }
+
+    fn revoke_role(&mut self, role: &str, account: &AccountId) {
+        self.audit(&format!("revoke {}", role), account);
+        self.access.revoke_role(role, account);
     }
 
 }
@@ -70,177 +580,786 @@ impl Product{
 
     #[init]
     pub fn new()-> Self{
+        Self::init_store(env::signer_account_id())
+    }
+
+    // what the factory calls, it names the owner instead of relying on who signed the transaction
+    #[init]
+    pub fn new_with_owner(owner_id: AccountId)-> Self{
+        assert!(env::is_valid_account_id(owner_id.as_bytes()), "Invalid owner account");
+        Self::init_store(owner_id)
+    }
+
+    fn init_store(owner_id: AccountId)-> Self{
         assert!(!env::state_exists(), "The contract is already initialized");
-      
 
-       let mut this = Self{
-            records: LookupMap::new(b"a".to_vec()),
-            owner: env::signer_account_id(),
-            access: AccessControl { roles: LookupMap::new(b"a".to_vec()) },
-        };
+       let mut this = Self::empty_state(owner_id.clone());
 
-        this.add_role_set_product(env::signer_account_id());
+        // granted directly, a store deployed by the factory is initialized by the factory account, not the owner
+        this.grant_role(ROLE_SET_PRODUCT, owner_id.clone());
 
-        this.add_role_delete_product(env::signer_account_id());
+        this.grant_role(ROLE_DELETE_PRODUCT, owner_id);
         
         this
     }
 
+    // a store with no products or roles yet, shared by new and migrate
+    fn empty_state(owner: AccountId) -> Self {
+        Self{
+            records: LookupMap::new(RECORDS_PREFIX.to_vec()),
+            treasury: owner.clone(),
+            owner,
+            access: AccessControl {
+                roles: LookupMap::new(ROLES_PREFIX.to_vec()),
+                expiries: LookupMap::new(b"6".to_vec()),
+                history: LookupMap::new(b"8".to_vec()),
+                history_len: LookupMap::new(b"9".to_vec()),
+            },
+            two_person_delete: false,
+            delete_confirm_window: DEFAULT_DELETE_CONFIRM_WINDOW,
+            pending_deletes: LookupMap::new(b"b".to_vec()),
+            timelock_delay: 0,
+            pending_actions: LookupMap::new(b"c".to_vec()),
+            next_action_id: 0,
+            audit_log: RingLog::new(b"d".to_vec(), AUDIT_LOG_CAPACITY),
+            recent_events: RingLog::new(b"e".to_vec(), RECENT_EVENTS_CAPACITY),
+            listeners: Vec::new(),
+            listener_gas: DEFAULT_LISTENER_GAS,
+            fulfillment: None,
+            reorder_threshold: 0,
+            metadata: LazyOption::new(b"f".to_vec(), None),
+            purchase_windows: Vec::new(),
+            moderation_required: false,
+            product_keys: UnorderedSet::new(b"g".to_vec()),
+            reports: LookupMap::new(b"h".to_vec()),
+            reported_products: TreeMap::new(b"i".to_vec()),
+            quarantine_threshold: 0,
+            listing_deposit: 0,
+            price_history: LookupMap::new(b"j".to_vec()),
+            stock_ledger: LookupMap::new(b"k".to_vec()),
+            sales_buckets: LookupMap::new(b"l".to_vec()),
+            state_nonce: 0,
+            last_mutation_block: env::block_index(),
+            prefix_migration: PrefixMigration::Done,
+            config: LazyOption::new(b"o".to_vec(), Some(&Config::default())),
+            vendor_products: LookupMap::new(b"p".to_vec()),
+            name_index: LookupMap::new(b"r".to_vec()),
+            ordered_keys: TreeMap::new(b"s".to_vec()),
+            cursor_secret: env::random_seed(),
+            units_bought: LookupMap::new(b"u".to_vec()),
+            account_activity: LookupMap::new(b"v".to_vec()),
+            orders: LookupMap::new(b"w".to_vec()),
+            next_order_id: 0,
+            product_orders: LookupMap::new(b"x".to_vec()),
+            subscription_plans: LookupMap::new(b"y".to_vec()),
+            next_plan_id: 0,
+            subscriptions: Vector::new(b"z".to_vec()),
+            charge_cursor: 0,
+            auctions: LookupMap::new(b"A".to_vec()),
+            next_auction_id: 0,
+            offers: LookupMap::new(b"B".to_vec()),
+            next_offer_id: 0,
+            product_offers: LookupMap::new(b"C".to_vec()),
+            quotes: LookupMap::new(b"D".to_vec()),
+            next_quote_id: 0,
+            invitees: LookupMap::new(b"E".to_vec()),
+            revisions: LookupMap::new(b"F".to_vec()),
+            previous_revisions: LookupMap::new(b"G".to_vec()),
+            restock_requests: LookupMap::new(b"H".to_vec()),
+            next_restock_id: 0,
+            open_restock_requests: LookupMap::new(b"I".to_vec()),
+            suppliers: UnorderedSet::new(b"J".to_vec()),
+            purchase_orders: LookupMap::new(b"K".to_vec()),
+            next_purchase_order_id: 0,
+            location_stock: LookupMap::new(b"L".to_vec()),
+            product_locations: LookupMap::new(b"M".to_vec()),
+            payment_splits: LookupMap::new(b"N".to_vec()),
+            vendor_tips: LookupMap::new(b"O".to_vec()),
+            fee_exemptions: UnorderedSet::new(b"P".to_vec()),
+            revenue: LookupMap::new(b"Q".to_vec()),
+            auto_payout_vendors: UnorderedSet::new(b"R".to_vec()),
+            payout_thresholds: LookupMap::new(b"S".to_vec()),
+            payout_cursor: 0,
+            payout_history: LookupMap::new(b"T".to_vec()),
+            claimable: LookupMap::new(b"U".to_vec()),
+            wnear_vendors: UnorderedSet::new(b"V".to_vec()),
+            usd_rate: None,
+            display_rates: Vec::new(),
+            token_gates: LookupMap::new(b"W".to_vec()),
+            stakes: LookupMap::new(b"X".to_vec()),
+            stake_tiers: Vec::new(),
+            affiliate_codes: LookupMap::new(b"Y".to_vec()),
+            affiliate_stats: LookupMap::new(b"Z".to_vec()),
+            campaigns: LookupMap::new(b"0".to_vec()),
+            next_campaign_id: 0,
+            product_campaigns: LookupMap::new(b"1".to_vec()),
+            price_experiments: LookupMap::new(b"2".to_vec()),
+            tombstones: LookupMap::new(b"3".to_vec()),
+            trash: LookupMap::new(b"4".to_vec()),
+            trash_keys: TreeMap::new(b"5".to_vec()),
+            role_managers: LookupMap::new(b"7".to_vec()),
+            paused: false,
+            breaker: CircuitBreaker { window_start: env::block_index(), ..Default::default() },
+            batch_log: None,
+        }
+    }
+
 
+    #[payable]
     pub fn set_products(&mut self, address:String, name:String, price: u128, stock:u8){
         
         //validate sender has permition of ROLE_SET_PRODUCT
-        assert_eq!(self.access.has_role(&ROLE_SET_PRODUCT.to_string(), &env::signer_account_id()), true, "401");
-        let item = Item {name, price, stock};
-        // Use env::log to record logs permanently to the blockchain!
-        env::log(format!("set_product '{:?}' ", item).as_bytes());
-        self.records.insert(&address, &item);
+        self.assert_permission("set_products");
+        if let Err(error) = self.check_product_write(&address, &name, env::attached_deposit()) {
+            env::panic(error.as_bytes());
+        }
+        let deposit = if self.records.contains_key(&address) { 0 } else { self.listing_deposit };
+        let item = self.apply_product_write(&address, name, price, stock, deposit, LifecycleState::Active);
+        self.emit_event("set_product", format!("{:?}", item));
+        self.refund_surplus(deposit);
     }
 
-    pub fn get_products(&self, address:String) -> Option<Item>{
-         self.records.get(&address)
+    // checks shared by single and batch writes, a batch records the error instead of panicking
+    fn check_product_write(&self, address: &String, name: &str, deposit: u128) -> Result<(), String> {
+        if name.len() > self.config().max_name_len as usize {
+            return Err("Product name is too long".to_string());
+        }
+        match self.load_product(address) {
+            Some(existing) => {
+                self.check_editable(&existing)?;
+                if !self.is_creator_or_override(&existing, false) {
+                    return Err("401".to_string());
+                }
+            },
+            // the key is held for a restore until the record is purged
+            None if self.trash.contains_key(address) => {
+                return Err("Product is in the trash".to_string());
+            },
+            // bounds what the store account has to pay storage for
+            None if self.product_keys.len() >= self.config().max_products => {
+                return Err(format!("Catalog is full, at most {} products", self.config().max_products));
+            },
+            // held until the listing is deleted, refunded unless moderation removes it
+            None if deposit < self.listing_deposit => {
+                return Err(format!("Attach a listing deposit of {} yoctoNEAR", self.listing_deposit));
+            },
+            None => {},
+        }
+        Ok(())
     }
 
-    pub fn delete_products(&mut self, address:String) {
-       
-         //validate sender has permition of ROLE_DELETE_PRODUCT
-        assert_eq!(self.access.has_role(&ROLE_DELETE_PRODUCT.to_string(), &env::signer_account_id()), true, "401");
-        
-        // Use env::log to record logs permanently to the blockchain!
-        env::log(format!("delete_products '{}' ", address).as_bytes());
-        self.records.remove(&address);
+    // writes an already checked product without logging it, callers emit their own event
+    // state only applies to a new listing, an existing one keeps its own
+    fn apply_product_write(&mut self, address: &String, name: String, price: u128, stock: u8, deposit: u128, state: LifecycleState) -> Item {
+        match self.load_product(address) {
+            Some(mut existing) => {
+                if existing.price != price {
+                    match self.batch_log.as_mut() {
+                        Some(log) => log.price_changes.push((address.clone(), existing.price, price)),
+                        None => self.audit(&format!("set price {} -> {}", existing.price, price), address),
+                    }
+                    self.record_price(address, price);
+                }
+                let old_stock = existing.stock;
+                let search_key = normalize_name(&name);
+                if existing.search_key != search_key {
+                    self.unindex_name(&existing.search_key, address);
+                    existing.search_key = search_key;
+                }
+                existing.approval = self.next_approval(Some(&existing.approval));
+                existing.name = name;
+                existing.price = price;
+                existing.stock = stock;
+                existing.updated_by = env::signer_account_id();
+                self.store_product_write(address, existing, old_stock)
+            },
+            None => {
+                let item = self.new_item(name, price, stock, deposit, state);
+                self.insert_new_product(address, item)
+            },
+        }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use near_sdk::MockedBlockchain;
-    use near_sdk::{testing_env, VMContext};
-    // mock the context for testing, notice "signer_account_id" that was accessed above from env::
-    fn get_context(input: Vec<u8>, is_view: bool) -> VMContext {
-        VMContext {
-            current_account_id: "Paul".to_string(),
-            signer_account_id: "Paul".to_string(),
-            signer_account_pk: vec![0, 1, 2],
-            predecessor_account_id: "Paul".to_string(),
-            input,
-            block_index: 0,
-            block_timestamp: 0,
-            account_balance: 0,
-            account_locked_balance: 0,
-            storage_usage: 0,
-            attached_deposit: 0,
-            prepaid_gas: 10u64.pow(18),
-            random_seed: vec![0, 1, 2],
-            is_view,
-            output_data_receivers: vec![],
-            epoch_height: 19,
+    // a listing owned by the signer with every optional setting off
+    fn new_item(&self, name: String, price: u128, stock: u8, deposit: u128, state: LifecycleState) -> Item {
+        Item {
+            search_key: normalize_name(&name),
+            name,
+            price,
+            stock,
+            frozen: false,
+            creator: env::signer_account_id(),
+            updated_by: env::signer_account_id(),
+            approval: self.next_approval(None),
+            report_count: 0,
+            listing_deposit: deposit,
+            version: 0,
+            scheduled_price: None,
+            max_per_buyer: 0,
+            preorder_until: None,
+            backorders: false,
+            rental: None,
+            price_decay: None,
+            visibility: Visibility::Public,
+            state,
+            pay_what_you_want: false,
+            usd_price: None,
         }
     }
 
-    #[test]
-    fn set_then_get_product() {
-        let context = get_context(vec![], false);
-        testing_env!(context);
-        let mut contract = Product::new();
-         
-        contract.set_products("0x1".to_string(), "PS4 x".to_string(), 800, 100);
-       
-       let result = contract.get_products("0x1".to_string());
-       
-       let val = match result {
-            // The division was valid
-            Some(x) => {
-                println!("Result: name:{}, price:{}, stock:{}", x.name, x.price, x.stock);
-                x.price
-            },
-            // The division was invalid
-            None    => {
-                println!("Not Exists");
-                0
-            },
-          
-        };
+    // for an item built up front, the key must be free
+    fn insert_new_product(&mut self, address: &String, item: Item) -> Item {
+        self.record_price(address, item.price);
+        self.tombstones.remove(address);
+        self.store_product_write(address, item, 0)
+    }
 
-        assert_eq!(800, val );
-       
+    fn store_product_write(&mut self, address: &String, mut item: Item, old_stock: u8) -> Item {
+        let stock = item.stock;
+        self.save_product(address, &mut item);
+        self.notify_listeners(address, "set_product");
+        self.check_stock_thresholds(address, old_stock, stock);
+        let reason = if stock > old_stock { StockReason::Restock } else { StockReason::Correction };
+        self.record_stock_movement(address, old_stock, stock, reason);
+        if self.product_keys.insert(address) {
+            self.ordered_keys.insert(address, &());
+        }
+        self.index_vendor(&item.creator, address);
+        self.index_name(&item.search_key, address);
+        item
     }
 
-    #[test]
-    fn get_default_product() {
-        let context = get_context(vec![], false);
-        testing_env!(context);
-        let  contract = Product::new();
-        
-        let result = contract.get_products("0x1".to_string());
-       
-        let val = match result {
-             Some(x) => {
-                println!("Result: name:{}, price:{}, stock:{}", x.name, x.price, x.stock);
-                 x.price
-             },
-             None    => {
-                 println!("Not Exists");
-                 0
+    pub fn get_products(&self, address:String, known_version: Option<u64>) -> ProductLookup{
+         match self.load_product(&address) {
+             Some(item) if Some(item.version) == known_version => ProductLookup::NotModified { version: item.version },
+             Some(item) => ProductLookup::Found(Box::new(item)),
+             None => match self.tombstones.get(&address) {
+                 Some(tombstone) => ProductLookup::Deleted { deleted_at: tombstone.deleted_at, deleted_by: tombstone.deleted_by },
+                 None => ProductLookup::NotFound,
              },
-           
-         };
+         }
+    }
 
-        assert_eq!(
-           0,
-           val
-        );
+    // optimistic concurrency: expected_version 0 means the product must not exist yet
+    #[payable]
+    pub fn set_products_if_version(&mut self, address:String, expected_version: u64, name:String, price: u128, stock:u8){
+        let current_version = self.load_product(&address).map(|item| item.version).unwrap_or(0);
+        assert_eq!(current_version, expected_version, "Product was modified concurrently");
+        self.set_products(address, name, price, stock);
     }
 
-    #[test]
-    fn set_delete_product() {
-        let context = get_context(vec![], false);
-        testing_env!(context);
-        let mut contract = Product::new();
-       
-        contract.set_products("0x11".to_string(), "PS5".to_string(),12345, 12);
-       
-       
-        contract.delete_products("0x11".to_string());
-       
-       let result = contract.get_products("0x11".to_string());
-       
-       let val = match result {
-            // The division was valid
-            Some(x) => {
-                println!("Result: name:{}, price:{}, stock:{}", x.name, x.price, x.stock);
-                x.price
-            },
-            // The division was invalid
-            None    => {
-                println!("Not Exists");
-                0
-            },
-          
-        };
+    // every read of an item goes through here so a due scheduled price is applied
+    fn load_product(&self, address: &String) -> Option<Item> {
+        self.records.get(address).map(|mut item| {
+            item.resolve_price(env::block_timestamp());
+            if let (Some(cents), Some(rate)) = (item.usd_price, &self.usd_rate) {
+                item.price = rate.to_yocto(cents);
+            }
+            item
+        })
+    }
 
-        assert_eq!(0, val );
-       
+    // every write goes through here so the version always moves
+    fn save_product(&mut self, address: &String, item: &mut Item) {
+        self.assert_not_paused();
+        self.assert_not_migrating();
+        if self.load_product(address).map(|existing| existing.price != item.price).unwrap_or(false) {
+            self.record_price_change();
+        }
+        if let Some(scheduled) = self.due_scheduled_price(address) {
+            self.record_price_at(address, scheduled.price, scheduled.effective_at);
+        }
+        item.version += 1;
+        self.records.insert(address, item);
+        self.draw_location_stock(address, item.stock);
     }
 
-    #[test]
-    fn update_get_product() {
+    // public enumeration, private listings and listings still waiting for (or refused by) a moderator are left out
+    pub fn get_products_list(&self, cursor: Option<String>, limit: u64) -> Page<(String, Item)> {
+        scan(&self.ordered_keys, &self.cursor_secret, cursor, limit, |address| {
+            self.load_product(&address)
+                .filter(|item| self.is_listed(item))
+                .map(|item| (address, item))
+        })
+    }
+
+    pub fn delete_products(&mut self, address:String) {
+       
+         //validate sender has permition of ROLE_DELETE_PRODUCT
+        self.assert_permission("delete_products");
+        self.assert_not_frozen(&address);
+        // only a moderator taking a listing down keeps its deposit, the vendor is refunded when they
+        // or the owner remove it
+        let forfeit_deposit = match self.load_product(&address) {
+            Some(existing) => {
+                self.assert_creator_or_override(&existing, true);
+                !self.is_creator_or_override(&existing, false)
+            },
+            None => false,
+        };
+
+        if self.two_person_delete {
+            if let Some(pending) = self.pending_deletes.get(&address) {
+                assert!(env::block_timestamp() > pending.requested_at + self.delete_confirm_window, "Deletion is already waiting for confirmation");
+            }
+            let pending = PendingDelete { requested_by: env::signer_account_id(), requested_at: env::block_timestamp(), forfeit_deposit };
+            self.emit_event("stage_delete", address.clone());
+            self.pending_deletes.insert(&address, &pending);
+            return;
+        }
+
+        self.queue_or_delete_product(&address, forfeit_deposit);
+    }
+
+    // only the creating vendor may touch a listing, unless the owner (or, for takedowns, a moderator) steps in
+    fn assert_creator_or_override(&self, item: &Item, allow_moderator: bool) {
+        assert!(self.is_creator_or_override(item, allow_moderator), "401");
+    }
+
+    fn is_creator_or_override(&self, item: &Item, allow_moderator: bool) -> bool {
+        let caller = env::signer_account_id();
+        caller == item.creator
+            || caller == self.owner
+            || (allow_moderator && self.access.has_role(ROLE_MODERATOR, &caller))
+    }
+
+    fn queue_or_delete_product(&mut self, address: &str, forfeit_deposit: bool) {
+        if self.timelock_delay > 0 {
+            self.queue_action(ActionKind::DeleteProduct { address: address.to_string(), forfeit_deposit });
+        } else {
+            self.internal_delete_product(address, forfeit_deposit, &env::signer_account_id());
+        }
+    }
+
+    // actor is who asked for the deletion, the queueing account when a timelocked deletion is executed
+    fn internal_delete_product(&mut self, address: &str, forfeit_deposit: bool, actor: &AccountId) {
+        self.assert_not_paused();
+        self.assert_not_migrating();
+        // escrowed funds must be settled or refunded first
+        assert!(!self.product_orders.contains_key(&address.to_string()), "Product has open orders");
+        assert!(!self.product_offers.contains_key(&address.to_string()), "Product has open offers");
+        let existing = self.records.get(&address.to_string());
+        if let Some(item) = &existing {
+            self.record_deletion();
+            if item.listing_deposit > 0 {
+                let receiver = if forfeit_deposit { self.treasury.clone() } else { item.creator.clone() };
+                self.safe_transfer(receiver, item.listing_deposit);
+            }
+            self.unindex_vendor(&item.creator, &address.to_string());
+            self.unindex_name(&item.search_key, &address.to_string());
+            self.tombstones.insert(&address.to_string(), &Tombstone { deleted_at: env::block_timestamp(), deleted_by: actor.clone() });
+        }
+        self.emit_event("delete_products", address.to_string());
+        self.notify_listeners(address, "delete_products");
+        self.audit_as(actor, "delete", address);
+        self.records.remove(&address.to_string());
+        match existing {
+            Some(item) if self.config().trash_grace_period > 0 => self.move_to_trash(&address.to_string(), item, actor),
+            _ => self.remove_secondary_entries(&address.to_string()),
+        }
+    }
+}
+
+// listing deposits
+#[near_bindgen]
+impl Product{
+    pub fn set_listing_deposit(&mut self, deposit: u128) {
+        //validate if owner
+        self.assert_owner();
+        self.touch();
+        self.listing_deposit = deposit;
+    }
+
+    // receives forfeited listing deposits
+    pub fn set_treasury(&mut self, treasury: AccountId) {
+        //validate if owner
+        self.assert_owner();
+        self.touch();
+        assert!(env::is_valid_account_id(treasury.as_bytes()), "Invalid treasury account");
+        self.treasury = treasury;
+    }
+
+    pub fn get_treasury(&self) -> AccountId {
+        self.treasury.clone()
+    }
+
+    // every payable method charges exactly what it needs and sends back the rest of the attachment
+    fn refund_surplus(&self, required: u128) {
+        let surplus = env::attached_deposit().saturating_sub(required);
+        if surplus > 0 {
+            self.safe_transfer(env::predecessor_account_id(), surplus);
+        }
+    }
+}
+
+// freeze single listings
+#[near_bindgen]
+impl Product{
+    pub fn freeze_product(&mut self, address: String) {
+        self.set_frozen(address, true);
+    }
+
+    pub fn unfreeze_product(&mut self, address: String) {
+        self.set_frozen(address, false);
+    }
+
+    fn set_frozen(&mut self, address: String, frozen: bool) {
+        //validate sender has permition of ROLE_MODERATOR
+        self.assert_permission(if frozen { "freeze_product" } else { "unfreeze_product" });
+
+        let mut item = self.load_product(&address).expect("Product does not exist");
+        item.frozen = frozen;
+        item.updated_by = env::signer_account_id();
+        self.save_product(&address, &mut item);
+        self.emit_event("set_frozen", format!("{} {}", address, frozen));
+        self.notify_listeners(&address, "set_frozen");
+    }
+
+    fn assert_not_frozen(&self, address: &String) {
+        if let Some(item) = self.load_product(address) {
+            assert!(!item.frozen, "Product is frozen");
+        }
+    }
+
+    // every vendor-side setter goes through this, a frozen listing waits for a moderator and an
+    // archived one is kept for the record
+    fn check_editable(&self, item: &Item) -> Result<(), String> {
+        if item.frozen {
+            return Err("Product is frozen".to_string());
+        }
+        if item.state == LifecycleState::Archived {
+            return Err("Product is archived".to_string());
+        }
+        Ok(())
+    }
+
+    fn assert_editable(&self, item: &Item) {
+        if let Err(error) = self.check_editable(item) {
+            env::panic(error.as_bytes());
+        }
+    }
+}
+
+// two-person approval for deletions
+#[near_bindgen]
+impl Product{
+    pub fn set_two_person_delete(&mut self, enabled: bool, confirm_window: u64) {
+        //validate if owner
+        self.assert_owner();
+        self.touch();
+        self.two_person_delete = enabled;
+        self.delete_confirm_window = confirm_window;
+    }
+
+    pub fn confirm_delete(&mut self, address: String) {
+        //validate sender has permition of ROLE_DELETE_PRODUCT
+        self.assert_permission("confirm_delete");
+
+        let pending = self.pending_deletes.get(&address).expect("No deletion is pending for this product");
+        assert_ne!(pending.requested_by, env::signer_account_id(), "Deletion must be confirmed by a second account");
+        assert!(env::block_timestamp() <= pending.requested_at + self.delete_confirm_window, "Deletion request has expired");
+
+        self.pending_deletes.remove(&address);
+        self.queue_or_delete_product(&address, pending.forfeit_deposit);
+    }
+
+    pub fn get_pending_delete(&self, address: String) -> Option<PendingDelete> {
+        self.pending_deletes.get(&address)
+    }
+}
+
+// store-level metadata
+#[near_bindgen]
+impl Product{
+    pub fn set_store_metadata(&mut self, metadata: StoreMetadata) {
+        //validate if owner
+        self.assert_owner();
+        self.emit_event("set_store_metadata", format!("{:?}", metadata));
+        self.metadata.set(&metadata);
+    }
+
+    pub fn get_store_metadata(&self) -> Option<StoreMetadata> {
+        self.metadata.get()
+    }
+}
+
+// on-chain audit log
+#[near_bindgen]
+impl Product{
+    pub fn get_audit_log(&self, from_index: u64, limit: u64) -> Vec<AuditEntry> {
+        self.audit_log.page(from_index, limit)
+    }
+
+    fn audit(&mut self, action: &str, target: &str) {
+        self.audit_as(&env::signer_account_id(), action, target);
+    }
+
+    fn audit_as(&mut self, actor: &AccountId, action: &str, target: &str) {
+        self.touch();
+        let entry = AuditEntry {
+            actor: actor.clone(),
+            action: action.to_string(),
+            target: target.to_string(),
+            timestamp: env::block_timestamp(),
+        };
+        self.audit_log.push(&entry);
+    }
+}
+
+// recent events
+#[near_bindgen]
+impl Product{
+    pub fn get_recent_events(&self, from_seq: u64, limit: u64) -> Vec<Event> {
+        self.recent_events.page(from_seq, limit)
+    }
+
+    fn emit_event(&mut self, event: &str, data: String) {
+        self.touch();
+        // Use env::log to record logs permanently to the blockchain!
+        env::log(format!("{} '{}' ", event, data).as_bytes());
+        let entry = Event {
+            seq: self.recent_events.total(),
+            event: event.to_string(),
+            data,
+            block_index: env::block_index(),
+            timestamp: env::block_timestamp(),
+        };
+        self.recent_events.push(&entry);
+    }
+}
+
+// global mutation counter
+#[near_bindgen]
+impl Product{
+    pub fn get_checkpoint(&self) -> Checkpoint {
+        Checkpoint { state_nonce: self.state_nonce, block_height: env::block_index() }
+    }
+
+    pub fn get_sync_head(&self) -> SyncHead {
+        SyncHead { state_nonce: self.state_nonce, block_height: self.last_mutation_block }
+    }
+
+    // called by every mutation, directly or through emit_event / audit
+    fn touch(&mut self) {
+        self.state_nonce += 1;
+        self.last_mutation_block = env::block_index();
+    }
+}
+
+// timelocked destructive operations
+#[near_bindgen]
+impl Product{
+    // held back by the current delay like any other timelocked action, so it cannot be dropped to 0 in one block
+    pub fn set_timelock_delay(&mut self, delay: u64) {
+        //validate if owner
+        self.assert_owner();
+        if self.timelock_delay > 0 {
+            self.queue_action(ActionKind::SetTimelockDelay { delay });
+        } else {
+            self.audit("set_timelock_delay", &delay.to_string());
+            self.timelock_delay = delay;
+        }
+    }
+
+    pub fn change_owner(&mut self, owner: AccountId) {
+        //validate if owner
+        self.assert_owner();
+        if self.timelock_delay > 0 {
+            self.queue_action(ActionKind::SetOwner { owner });
+        } else {
+            self.set_owner(owner);
+        }
+    }
+
+    pub fn execute_pending_action(&mut self, id: u64) {
+        let action = self.pending_actions.get(&id).expect("No pending action with this id");
+        assert!(env::block_timestamp() >= action.executable_at, "The timelock has not expired yet");
+
+        self.pending_actions.remove(&id);
+        self.emit_event("execute_action", id.to_string());
+        // attributed to whoever queued the action, not to the account that happened to execute it
+        match action.kind {
+            ActionKind::DeleteProduct { address, forfeit_deposit } => self.internal_delete_product(&address, forfeit_deposit, &action.queued_by),
+            ActionKind::SetOwner { owner } => {
+                self.audit_as(&action.queued_by, "set_owner", &owner);
+                self.owner = owner;
+            },
+            ActionKind::SetTimelockDelay { delay } => {
+                self.audit_as(&action.queued_by, "set_timelock_delay", &delay.to_string());
+                self.timelock_delay = delay;
+            },
+        }
+    }
+
+    pub fn cancel_pending_action(&mut self, id: u64) {
+        let action = self.pending_actions.get(&id).expect("No pending action with this id");
+        //the owner may cancel anything, delete role holders may cancel deletions
+        let caller = env::signer_account_id();
+        let allowed = match action.kind {
+            ActionKind::DeleteProduct { .. } => caller == self.owner || self.access.has_role(ROLE_DELETE_PRODUCT, &caller),
+            ActionKind::SetOwner { .. } | ActionKind::SetTimelockDelay { .. } => caller == self.owner,
+        };
+        assert!(allowed, "401");
+
+        self.pending_actions.remove(&id);
+        self.emit_event("cancel_action", id.to_string());
+    }
+
+    pub fn get_pending_action(&self, id: u64) -> Option<PendingAction> {
+        self.pending_actions.get(&id)
+    }
+
+    fn queue_action(&mut self, kind: ActionKind) -> u64 {
+        let id = self.next_action_id;
+        self.next_action_id += 1;
+        let action = PendingAction { kind, queued_by: env::signer_account_id(), executable_at: env::block_timestamp() + self.timelock_delay };
+        self.emit_event("queue_action", format!("{} {:?}", id, action));
+        self.pending_actions.insert(&id, &action);
+        id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::batch::ProductEntry;
+    use crate::catalog::CloneOverrides;
+    use crate::config::ConfigPatch;
+    use crate::maintenance::{LegacyAccessControl, LegacyItem, LegacyProduct};
+    use crate::orders::OrderStatus;
+    use crate::procurement::{Discrepancy, PurchaseOrderLine, PurchaseOrderStatus};
+    use crate::purchase::PurchaseIntent;
+    use near_sdk::MockedBlockchain;
+    use near_sdk::{testing_env, VMContext};
+    use near_sdk::json_types::Base64VecU8;
+    // mock the context for testing, notice "signer_account_id" that was accessed above from env::
+    fn get_context(input: Vec<u8>, is_view: bool) -> VMContext {
+        VMContext {
+            current_account_id: "Paul".to_string(),
+            signer_account_id: "Paul".to_string(),
+            signer_account_pk: vec![0, 1, 2],
+            predecessor_account_id: "Paul".to_string(),
+            input,
+            block_index: 0,
+            block_timestamp: 0,
+            account_balance: 0,
+            account_locked_balance: 0,
+            storage_usage: 0,
+            attached_deposit: 0,
+            prepaid_gas: 10u64.pow(18),
+            random_seed: vec![0, 1, 2],
+            is_view,
+            output_data_receivers: vec![],
+            epoch_height: 19,
+        }
+    }
+
+    // switch the caller of an already running test, keeping the storage usage in sync
+    // and giving the contract enough balance to pay out transfers
+    fn get_context_for(account: &str) -> VMContext {
+        let mut context = get_context(vec![], false);
+        context.signer_account_id = account.to_string();
+        context.predecessor_account_id = account.to_string();
+        context.storage_usage = env::storage_usage();
+        context.account_balance = 10u128.pow(26);
+        context
+    }
+
+    // every transfer is followed by its settlement callback, only the transfers are counted
+    fn transfer_count() -> usize {
+        near_sdk::test_utils::get_created_receipts().iter()
+            .filter(|receipt| format!("{:?}", receipt).contains("Transfer("))
+            .count()
+    }
+
+    fn get_item(contract: &Product, address: &str) -> Option<Item> {
+        match contract.get_products(address.to_string(), None) {
+            ProductLookup::Found(item) => Some(*item),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn new_with_owner_ignores_the_signer() {
+        testing_env!(get_context(vec![], false));
+        let contract = Product::new_with_owner("carol.near".to_string());
+        assert_eq!("carol.near", contract.owner);
+        assert!(contract.access.has_role(ROLE_SET_PRODUCT, &"carol.near".to_string()));
+        assert!(!contract.access.has_role(ROLE_SET_PRODUCT, &"Paul".to_string()));
+    }
+
+    #[test]
+    fn set_then_get_product() {
         let context = get_context(vec![], false);
         testing_env!(context);
         let mut contract = Product::new();
+         
+        contract.set_products("0x1".to_string(), "PS4 x".to_string(), 800, 100);
        
-        contract.set_products("0x1".to_string(), "PS5".to_string(),500, 12);
+       let result = get_item(&contract, "0x1");
+       
+       let val = match result {
+            // The division was valid
+            Some(x) => {
+                println!("Result: name:{}, price:{}, stock:{}", x.name, x.price, x.stock);
+                x.price
+            },
+            // The division was invalid
+            None    => {
+                println!("Not Exists");
+                0
+            },
+          
+        };
+
+        assert_eq!(800, val );
+       
+    }
+
+    #[test]
+    fn get_default_product() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let  contract = Product::new();
+        
+        let result = get_item(&contract, "0x1");
+       
+        let val = match result {
+             Some(x) => {
+                println!("Result: name:{}, price:{}, stock:{}", x.name, x.price, x.stock);
+                 x.price
+             },
+             None    => {
+                 println!("Not Exists");
+                 0
+             },
+           
+         };
+
+        assert_eq!(
+           0,
+           val
+        );
+    }
+
+    #[test]
+    fn set_delete_product() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Product::new();
+       
+        contract.set_products("0x11".to_string(), "PS5".to_string(),12345, 12);
        
-        contract.set_products("0x1".to_string(), "PS5".to_string(),1200, 7);
        
-       let result = contract.get_products("0x1".to_string());
+        contract.delete_products("0x11".to_string());
+       
+       let result = get_item(&contract, "0x11");
        
        let val = match result {
             // The division was valid
             Some(x) => {
                 println!("Result: name:{}, price:{}, stock:{}", x.name, x.price, x.stock);
-                x.stock
+                x.price
             },
             // The division was invalid
             None    => {
@@ -250,7 +1369,3095 @@ mod tests {
           
         };
 
-        assert_eq!(7, val );
+        assert_eq!(0, val );
+       
+    }
+
+    #[test]
+    fn update_get_product() {
+        let context = get_context(vec![], false);
+        testing_env!(context);
+        let mut contract = Product::new();
+       
+        contract.set_products("0x1".to_string(), "PS5".to_string(),500, 12);
        
+        contract.set_products("0x1".to_string(), "PS5".to_string(),1200, 7);
+       
+       let result = get_item(&contract, "0x1");
+       
+       let val = match result {
+            // The division was valid
+            Some(x) => {
+                println!("Result: name:{}, price:{}, stock:{}", x.name, x.price, x.stock);
+                x.stock
+            },
+            // The division was invalid
+            None    => {
+                println!("Not Exists");
+                0
+            },
+          
+        };
+
+        assert_eq!(7, val );
+       
+    }
+
+    #[test]
+    fn two_person_delete_requires_confirmation() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.add_role_delete_product("Bob".to_string());
+        contract.set_two_person_delete(true, DEFAULT_DELETE_CONFIRM_WINDOW);
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+
+        contract.delete_products("0x1".to_string());
+        assert!(get_item(&contract, "0x1").is_some());
+        assert!(contract.get_pending_delete("0x1".to_string()).is_some());
+
+        testing_env!(get_context_for("Bob"));
+        contract.confirm_delete("0x1".to_string());
+        assert!(get_item(&contract, "0x1").is_none());
+        assert!(contract.get_pending_delete("0x1".to_string()).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "Deletion must be confirmed by a second account")]
+    fn two_person_delete_rejects_self_confirmation() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_two_person_delete(true, DEFAULT_DELETE_CONFIRM_WINDOW);
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+
+        contract.delete_products("0x1".to_string());
+        contract.confirm_delete("0x1".to_string());
+    }
+
+    #[test]
+    fn timelocked_delete_runs_after_delay() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_timelock_delay(1_000);
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+
+        contract.delete_products("0x1".to_string());
+        assert!(get_item(&contract, "0x1").is_some());
+        assert!(contract.get_pending_action(0).is_some());
+
+        let mut context = get_context_for("Paul");
+        context.block_timestamp = 1_000;
+        testing_env!(context);
+        contract.execute_pending_action(0);
+        assert!(get_item(&contract, "0x1").is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "No pending action with this id")]
+    fn cancelled_owner_change_cannot_execute() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_timelock_delay(1_000);
+
+        contract.change_owner("Mallory".to_string());
+        contract.cancel_pending_action(0);
+        assert_eq!("Paul".to_string(), contract.get_owner());
+
+        let mut context = get_context_for("Paul");
+        context.block_timestamp = 1_000;
+        testing_env!(context);
+        contract.execute_pending_action(0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Product is frozen")]
+    fn frozen_product_rejects_edits() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.add_role_moderator("Paul".to_string());
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+
+        contract.freeze_product("0x1".to_string());
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 1, 12);
+    }
+
+    #[test]
+    fn unfrozen_product_accepts_edits() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.add_role_moderator("Paul".to_string());
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+
+        contract.freeze_product("0x1".to_string());
+        contract.unfreeze_product("0x1".to_string());
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 1, 12);
+        assert_eq!(1, get_item(&contract, "0x1").unwrap().price);
+    }
+
+    #[test]
+    #[should_panic(expected = "401")]
+    fn vendor_cannot_overwrite_other_vendor_listing() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.add_role_set_product("Alice".to_string());
+        contract.add_role_set_product("Bob".to_string());
+
+        testing_env!(get_context_for("Alice"));
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+        assert_eq!("Alice".to_string(), get_item(&contract, "0x1").unwrap().creator);
+
+        testing_env!(get_context_for("Bob"));
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 1, 12);
+    }
+
+    #[test]
+    fn owner_can_override_vendor_listing() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.add_role_set_product("Alice".to_string());
+
+        testing_env!(get_context_for("Alice"));
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+
+        testing_env!(get_context_for("Paul"));
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 1, 12);
+        let item = get_item(&contract, "0x1").unwrap();
+        assert_eq!(1, item.price);
+        assert_eq!("Alice".to_string(), item.creator);
+    }
+
+    #[test]
+    fn update_records_last_editor() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.add_role_set_product("Alice".to_string());
+
+        testing_env!(get_context_for("Alice"));
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+        assert_eq!("Alice".to_string(), get_item(&contract, "0x1").unwrap().updated_by);
+
+        testing_env!(get_context_for("Paul"));
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 450, 12);
+        assert_eq!("Paul".to_string(), get_item(&contract, "0x1").unwrap().updated_by);
+    }
+
+    #[test]
+    fn privileged_actions_are_audited() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 450, 12);
+        contract.delete_products("0x1".to_string());
+
+        // the two grants made by new() come first
+        let log = contract.get_audit_log(2, 10);
+        assert_eq!(2, log.len());
+        assert_eq!("set price 500 -> 450", log[0].action);
+        assert_eq!("delete", log[1].action);
+        assert_eq!("0x1", log[1].target);
+    }
+
+    #[test]
+    fn audit_log_drops_oldest_entries_when_full() {
+        testing_env!(get_context(vec![], false));
+        let mut log: RingLog<u64> = RingLog::new(b"t".to_vec(), 3);
+        for i in 0..5 {
+            log.push(&i);
+        }
+
+        assert_eq!(5, log.total());
+        assert_eq!(vec![2, 3, 4], log.page(0, 10));
+        assert_eq!(vec![3], log.page(3, 1));
+    }
+
+    #[test]
+    fn recent_events_can_be_replayed_from_sequence() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+        contract.delete_products("0x1".to_string());
+
+        let events = contract.get_recent_events(1, 10);
+        assert_eq!(1, events.len());
+        assert_eq!(1, events[0].seq);
+        assert_eq!("delete_products", events[0].event);
+        assert_eq!("0x1", events[0].data);
+    }
+
+    #[test]
+    fn product_changes_notify_listeners() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.add_listener("loyalty.near".to_string());
+        contract.add_listener("analytics.near".to_string());
+        assert_eq!(2, contract.get_listeners().len());
+
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+        assert_eq!(2, near_sdk::test_utils::get_created_receipts().len());
+    }
+
+    #[test]
+    fn stock_crossing_thresholds_notifies_fulfillment() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_fulfillment(Some("warehouse.near".to_string()), 5);
+
+        // 0 -> 12 is back in stock, 12 -> 8 crosses nothing, 8 -> 3 needs a reorder
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 8);
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 3);
+        assert_eq!(2, near_sdk::test_utils::get_created_receipts().len());
+    }
+
+    #[test]
+    fn owner_sets_store_metadata() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        assert!(contract.get_store_metadata().is_none());
+
+        contract.set_store_metadata(StoreMetadata {
+            name: "Paul's Games".to_string(),
+            logo_cid: Some("bafybeigdyrzt".to_string()),
+            contact: None,
+            policy_hash: None,
+        });
+        assert_eq!("Paul's Games", contract.get_store_metadata().unwrap().name);
+    }
+
+    fn get_context_with_deposit(account: &str, deposit: u128) -> VMContext {
+        let mut context = get_context_for(account);
+        context.attached_deposit = deposit;
+        context
+    }
+
+    #[test]
+    fn buy_product_decrements_stock() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+
+        testing_env!(get_context_with_deposit("Bob", 1000));
+        contract.buy_product("0x1".to_string(), 2);
+        assert_eq!(10, get_item(&contract, "0x1").unwrap().stock);
+    }
+
+    #[test]
+    #[should_panic(expected = "Store is closed for purchases")]
+    fn purchases_outside_window_are_rejected() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+        contract.set_purchase_windows(vec![PurchaseWindow { start: 1_000, end: 2_000 }]);
+        assert!(!contract.is_store_open());
+        // catalog reads keep working while closed
+        assert!(get_item(&contract, "0x1").is_some());
+
+        testing_env!(get_context_with_deposit("Bob", 500));
+        contract.buy_product("0x1".to_string(), 1);
+    }
+
+    #[test]
+    fn pending_listings_are_hidden_until_approved() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.add_role_moderator("Paul".to_string());
+        contract.set_moderation_required(true);
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+        assert_eq!(Approval::Pending, get_item(&contract, "0x1").unwrap().approval);
+        assert!(contract.get_products_list(None, 10).items.is_empty());
+
+        contract.approve_listing("0x1".to_string());
+        assert_eq!(1, contract.get_products_list(None, 10).items.len());
+    }
+
+    #[test]
+    #[should_panic(expected = "Product is not approved")]
+    fn rejected_listing_cannot_be_bought() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.add_role_moderator("Paul".to_string());
+        contract.set_moderation_required(true);
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+        contract.reject_listing("0x1".to_string(), "counterfeit".to_string());
+
+        testing_env!(get_context_with_deposit("Bob", 500));
+        contract.buy_product("0x1".to_string(), 1);
+    }
+
+    #[test]
+    fn reports_are_counted_and_listed() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+
+        testing_env!(get_context_with_deposit("Bob", 10u128.pow(22)));
+        contract.report_product("0x1".to_string(), "counterfeit".to_string());
+        testing_env!(get_context_with_deposit("Carol", 10u128.pow(22)));
+        contract.report_product("0x1".to_string(), "wrong price".to_string());
+
+        assert_eq!(2, get_item(&contract, "0x1").unwrap().report_count);
+        assert_eq!(vec![("0x1".to_string(), 2)], contract.get_reported_products(None, 10).items);
+        assert_eq!(2, contract.get_reports("0x1".to_string()).len());
+    }
+
+    #[test]
+    #[should_panic(expected = "to report a product")]
+    fn report_requires_deposit() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+
+        testing_env!(get_context_for("Bob"));
+        contract.report_product("0x1".to_string(), "spam".to_string());
+    }
+
+    #[test]
+    fn distinct_reporters_quarantine_product() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_quarantine_threshold(2);
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+
+        // the same reporter twice does not count as two
+        testing_env!(get_context_with_deposit("Bob", 10u128.pow(22)));
+        contract.report_product("0x1".to_string(), "counterfeit".to_string());
+        contract.report_product("0x1".to_string(), "counterfeit".to_string());
+        assert_eq!(Approval::Approved, get_item(&contract, "0x1").unwrap().approval);
+
+        testing_env!(get_context_with_deposit("Carol", 10u128.pow(22)));
+        contract.report_product("0x1".to_string(), "counterfeit".to_string());
+        assert_eq!(Approval::Quarantined, get_item(&contract, "0x1").unwrap().approval);
+        assert!(contract.get_products_list(None, 10).items.is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "Attach a listing deposit")]
+    fn new_listing_requires_deposit() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_listing_deposit(100);
+
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+    }
+
+    #[test]
+    fn listing_deposit_is_held_and_released_on_delete() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_listing_deposit(100);
+
+        testing_env!(get_context_with_deposit("Paul", 100));
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+        assert_eq!(100, get_item(&contract, "0x1").unwrap().listing_deposit);
+
+        // edits do not ask for a second deposit
+        testing_env!(get_context_for("Paul"));
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 450, 12);
+        contract.delete_products("0x1".to_string());
+        assert_eq!(1, transfer_count());
+    }
+
+    #[test]
+    fn owner_removals_refund_the_listing_deposit() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_listing_deposit(100);
+        contract.set_treasury("treasury.near".to_string());
+        contract.add_role_set_product("vendor.near".to_string());
+        testing_env!(get_context_with_deposit("vendor.near", 100));
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+
+        testing_env!(get_context_for("Paul"));
+        contract.delete_products("0x1".to_string());
+        assert_eq!(1, near_sdk::test_utils::get_created_receipts().iter().filter(|receipt| format!("{:?}", receipt).contains(r#"receiver_id: "vendor.near""#)).count());
+    }
+
+    #[test]
+    fn moderator_takedowns_forfeit_the_listing_deposit() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_listing_deposit(100);
+        contract.set_treasury("treasury.near".to_string());
+        contract.add_role_set_product("vendor.near".to_string());
+        contract.add_role_moderator("mod.near".to_string());
+        contract.add_role_delete_product("mod.near".to_string());
+        testing_env!(get_context_with_deposit("vendor.near", 100));
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+
+        testing_env!(get_context_for("mod.near"));
+        contract.delete_products("0x1".to_string());
+        assert_eq!(1, near_sdk::test_utils::get_created_receipts().iter().filter(|receipt| format!("{:?}", receipt).contains(r#"receiver_id: "treasury.near""#)).count());
+    }
+
+    #[test]
+    fn price_changes_are_kept_in_history() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 10);
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 450, 10);
+
+        let prices: Vec<u128> = contract.get_price_history("0x1".to_string(), 10).iter().map(|point| point.price).collect();
+        assert_eq!(vec![450, 500], prices);
+        assert_eq!(1, contract.get_price_history("0x1".to_string(), 1).len());
+    }
+
+    #[test]
+    fn stock_movements_are_recorded_with_reasons() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 10);
+
+        testing_env!(get_context_with_deposit("Bob", 500));
+        contract.buy_product("0x1".to_string(), 1);
+
+        let ledger = contract.get_stock_ledger("0x1".to_string(), 10);
+        let reasons: Vec<StockReason> = ledger.iter().map(|movement| movement.reason.clone()).collect();
+        assert_eq!(vec![StockReason::Sale, StockReason::Correction, StockReason::Restock], reasons);
+        assert_eq!(-1, ledger[0].delta);
+        assert_eq!(9, ledger[0].stock_after);
+    }
+
+    #[test]
+    fn sales_velocity_counts_the_last_day() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+
+        let hour = 60 * 60 * 1_000_000_000;
+        let mut context = get_context_with_deposit("Bob", 1000);
+        context.block_timestamp = hour;
+        testing_env!(context);
+        contract.buy_product("0x1".to_string(), 2);
+
+        let mut context = get_context_with_deposit("Bob", 500);
+        context.block_timestamp = 20 * hour;
+        testing_env!(context);
+        contract.buy_product("0x1".to_string(), 1);
+        assert_eq!(3, contract.get_sales_velocity("0x1".to_string()).units);
+
+        // the first sale falls out of the rolling day
+        let mut context = get_context_for("Bob");
+        context.block_timestamp = 26 * hour;
+        testing_env!(context);
+        assert_eq!(1, contract.get_sales_velocity("0x1".to_string()).units);
+    }
+
+    #[test]
+    fn inventory_value_is_summed_page_by_page() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 2);
+        contract.set_products("0x2".to_string(), "PS4".to_string(), 300, 3);
+        contract.set_products("0x3".to_string(), "Switch".to_string(), 100, 1);
+
+        let first = contract.get_inventory_value(None, 2);
+        assert_eq!(1900, first.value.0);
+        assert!(first.cursor.is_some());
+
+        let second = contract.get_inventory_value(first.cursor, 2);
+        assert_eq!(100, second.value.0);
+        assert_eq!(None, second.cursor);
+    }
+
+    #[test]
+    fn filtered_enumeration_resumes_from_cursor() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.add_role_moderator("Paul".to_string());
+        for i in 0..5 {
+            contract.set_products(format!("0x{}", i), "PS5".to_string(), 500, 1);
+        }
+        contract.reject_listing("0x1".to_string(), "duplicate".to_string());
+
+        let first = contract.get_products_list(None, 2);
+        let keys: Vec<String> = first.items.iter().map(|(address, _)| address.clone()).collect();
+        assert_eq!(vec!["0x0".to_string(), "0x2".to_string()], keys);
+
+        let second = contract.get_products_list(first.cursor, 10);
+        assert_eq!(2, second.items.len());
+        assert!(second.cursor.is_none());
+    }
+
+    #[test]
+    fn unchanged_version_is_not_modified() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+        let version = get_item(&contract, "0x1").unwrap().version;
+        assert_eq!(1, version);
+
+        match contract.get_products("0x1".to_string(), Some(version)) {
+            ProductLookup::NotModified { version: current } => assert_eq!(version, current),
+            other => panic!("expected NotModified, got {:?}", other),
+        }
+
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 450, 12);
+        match contract.get_products("0x1".to_string(), Some(version)) {
+            ProductLookup::Found(item) => assert_eq!(2, item.version),
+            other => panic!("expected Found, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn mutations_move_the_checkpoint() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        let before = contract.get_checkpoint().state_nonce;
+
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+        let after_write = contract.get_checkpoint().state_nonce;
+        assert!(after_write > before);
+
+        contract.set_timelock_delay(10);
+        assert!(contract.get_checkpoint().state_nonce > after_write);
+
+        get_item(&contract, "0x1");
+        assert_eq!(after_write + 1, contract.get_checkpoint().state_nonce);
+    }
+
+    #[test]
+    #[should_panic(expected = "Product was modified concurrently")]
+    fn stale_version_write_is_rejected() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products_if_version("0x1".to_string(), 0, "PS5".to_string(), 500, 12);
+        contract.set_products_if_version("0x1".to_string(), 1, "PS5".to_string(), 450, 12);
+        assert_eq!(450, get_item(&contract, "0x1").unwrap().price);
+
+        // a second operator still holding version 1
+        contract.set_products_if_version("0x1".to_string(), 1, "PS5".to_string(), 400, 12);
+    }
+
+    #[test]
+    fn stock_compare_and_swap() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+
+        contract.set_stock_if("0x1".to_string(), 12, 20);
+        assert_eq!(20, get_item(&contract, "0x1").unwrap().stock);
+        assert_eq!(StockReason::Restock, contract.get_stock_ledger("0x1".to_string(), 1)[0].reason);
+    }
+
+    #[test]
+    #[should_panic(expected = "Stock was modified concurrently")]
+    fn stock_compare_and_swap_rejects_stale_stock() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+
+        testing_env!(get_context_with_deposit("Bob", 500));
+        contract.buy_product("0x1".to_string(), 1);
+
+        testing_env!(get_context_for("Paul"));
+        contract.set_stock_if("0x1".to_string(), 12, 20);
+    }
+
+    #[test]
+    fn batch_import_reports_a_single_summary() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.add_role_moderator("Paul".to_string());
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+        contract.set_products("0x2".to_string(), "PS4".to_string(), 300, 1);
+        contract.freeze_product("0x2".to_string());
+
+        let entry = |address: &str, price| ProductEntry { address: address.to_string(), name: "Console".to_string(), price, stock: 5 };
+        let summary = contract.set_products_batch(vec![entry("0x1", 450), entry("0x2", 250), entry("0x3", 100)]);
+        assert_eq!(vec!["0x3".to_string()], summary.created);
+        assert_eq!(vec!["0x1".to_string()], summary.updated);
+        assert_eq!(vec![("0x2".to_string(), "Product is frozen".to_string())], summary.failed);
+
+        let logs = near_sdk::test_utils::get_logs();
+        assert_eq!(1, logs.iter().filter(|log| log.starts_with("set_products_batch")).count());
+        // only the two single writes above logged per product
+        assert_eq!(2, logs.iter().filter(|log| log.starts_with("set_product '")).count());
+    }
+
+    #[test]
+    fn overpayment_is_refunded() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+
+        testing_env!(get_context_with_deposit("Bob", 800));
+        contract.buy_product("0x1".to_string(), 1);
+        // the 300 surplus back to the buyer, the vendor's share accrues as revenue
+        assert_eq!(1, transfer_count());
+    }
+
+    #[test]
+    fn listing_keeps_only_the_required_deposit() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_listing_deposit(100);
+
+        testing_env!(get_context_with_deposit("Paul", 250));
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+        assert_eq!(100, get_item(&contract, "0x1").unwrap().listing_deposit);
+        assert_eq!(1, transfer_count());
+    }
+
+    #[test]
+    fn storage_usage_grows_with_the_name() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+
+        let stored = contract.get_storage_usage("0x1".to_string(), None);
+        let longer = contract.get_storage_usage("0x1".to_string(), Some("PS5 Digital Edition".to_string()));
+        // the name is stored three times, once more as the search key and once as the name index key
+        assert_eq!(48, longer - stored);
+        assert!(contract.get_storage_usage("0x2".to_string(), None) > stored);
+
+        // a sale adds a ledger entry and the first sales bucket
+        testing_env!(get_context_with_deposit("Bob", 500));
+        contract.buy_product("0x1".to_string(), 1);
+        assert!(contract.get_storage_usage("0x1".to_string(), None) > stored);
+    }
+
+    #[test]
+    fn cleanup_removes_dangling_index_entries() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+        // an index entry left behind without its record
+        contract.product_keys.insert(&"0x9".to_string());
+        contract.price_history.insert(&"0x9".to_string(), &vec![]);
+
+        let summary = contract.cleanup_orphans(vec!["0x1".to_string(), "0x9".to_string()]);
+        assert_eq!(vec!["0x9".to_string()], summary.updated);
+        assert_eq!(1, summary.failed.len());
+        assert_eq!(1, contract.product_keys.len());
+        assert!(contract.get_price_history("0x9".to_string(), 10).is_empty());
+    }
+
+    // lay the store out the way the first release wrote it, records and roles both under b"a"
+    fn write_legacy_state(products: Vec<(&str, &str, u128, u8)>) {
+        let mut legacy = LegacyProduct {
+            records: LookupMap::new(b"a".to_vec()),
+            owner: "Paul".to_string(),
+            access: LegacyAccessControl { roles: LookupMap::new(b"a".to_vec()) },
+        };
+        for (address, name, price, stock) in products {
+            legacy.records.insert(&address.to_string(), &LegacyItem { name: name.to_string(), price, stock });
+        }
+        legacy.access.roles.insert(&ROLE_SET_PRODUCT.to_string(), &vec!["Paul".to_string()]);
+        legacy.access.roles.insert(&ROLE_DELETE_PRODUCT.to_string(), &vec!["Paul".to_string()]);
+        env::state_write(&legacy);
+    }
+
+    #[test]
+    fn migrate_prefixes_moves_legacy_records() {
+        testing_env!(get_context(vec![], false));
+        write_legacy_state(vec![("0x1", "PS5", 500, 12), ("0x2", "Xbox", 400, 3), ("0x3", "Switch", 300, 7)]);
+        let mut contract = Product::migrate();
+        assert!(contract.access.has_role(ROLE_DELETE_PRODUCT, &"Paul".to_string()));
+        let summary = contract.index_legacy_products(vec!["0x1".to_string(), "0x2".to_string(), "0x3".to_string(), ROLE_SET_PRODUCT.to_string()]);
+        assert_eq!(3, summary.updated.len());
+        assert_eq!(1, summary.failed.len());
+
+        assert!(!contract.migrate_prefixes(2));
+        assert!(!contract.migrate_prefixes(2));
+        assert_eq!(3, get_item(&contract, "0x2").unwrap().stock);
+        assert!(!contract.migrate_prefixes(2));
+        assert!(contract.migrate_prefixes(2));
+
+        let legacy: LookupMap<String, LegacyItem> = LookupMap::new(b"a".to_vec());
+        assert!(!legacy.contains_key(&"0x1".to_string()));
+        let item = get_item(&contract, "0x3").unwrap();
+        assert_eq!(("Switch".to_string(), "Paul".to_string()), (item.name, item.creator));
+        assert_eq!(1, contract.find_products_by_name("switch".to_string()).len());
+        contract.set_products("0x4".to_string(), "Deck".to_string(), 600, 1);
+        assert!(contract.migrate_prefixes(2));
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner can migrate")]
+    fn migrate_requires_the_legacy_owner() {
+        testing_env!(get_context(vec![], false));
+        write_legacy_state(vec![("0x1", "PS5", 500, 12)]);
+        testing_env!(get_context_for("mallory.near"));
+        Product::migrate();
+    }
+
+    #[test]
+    #[should_panic(expected = "Storage migration in progress")]
+    fn writes_are_refused_while_copying() {
+        testing_env!(get_context(vec![], false));
+        write_legacy_state(vec![("0x1", "PS5", 500, 12), ("0x2", "Xbox", 400, 3)]);
+        let mut contract = Product::migrate();
+        contract.index_legacy_products(vec!["0x1".to_string(), "0x2".to_string()]);
+        contract.migrate_prefixes(1);
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 450, 12);
+    }
+
+    #[test]
+    fn update_config_only_changes_patched_fields() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        let config = contract.update_config(ConfigPatch { max_name_len: Some(3), fee_bps: Some(250), ..Default::default() });
+        assert_eq!(3, config.max_name_len);
+        assert_eq!(250, contract.get_config().fee_bps);
+        assert!(contract.get_config().purchase_enabled);
+
+        let entries = vec![
+            ProductEntry { address: "0x1".to_string(), name: "PS5".to_string(), price: 500, stock: 12 },
+            ProductEntry { address: "0x2".to_string(), name: "Xbox".to_string(), price: 400, stock: 3 },
+        ];
+        let summary = contract.set_products_batch(entries);
+        assert_eq!(vec![("0x2".to_string(), "Product name is too long".to_string())], summary.failed);
+    }
+
+    #[test]
+    #[should_panic(expected = "Purchases are disabled")]
+    fn purchases_can_be_switched_off() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+        contract.update_config(ConfigPatch { purchase_enabled: Some(false), ..Default::default() });
+
+        testing_env!(get_context_with_deposit("Bob", 500));
+        contract.buy_product("0x1".to_string(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "Catalog is full, at most 1 products")]
+    fn new_listings_stop_at_the_catalog_cap() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.update_config(ConfigPatch { max_products: Some(1), ..Default::default() });
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+        // updates to existing products are still accepted
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 450, 12);
+        contract.set_products("0x2".to_string(), "Xbox".to_string(), 400, 3);
+    }
+
+    #[test]
+    #[should_panic(expected = "Cap is below the current catalog size")]
+    fn catalog_cap_cannot_drop_below_the_catalog() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+        contract.set_products("0x2".to_string(), "Xbox".to_string(), 400, 3);
+        contract.update_config(ConfigPatch { max_products: Some(1), ..Default::default() });
+    }
+
+    #[test]
+    fn export_state_streams_records_in_chunks() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+        contract.set_products("0x2".to_string(), "Xbox".to_string(), 400, 3);
+        contract.set_products("0x3".to_string(), "Switch".to_string(), 300, 7);
+
+        let first = contract.export_state(None, 2);
+        let rest = contract.export_state(first.cursor.clone(), 2);
+        let first_items = Vec::<(String, Item)>::try_from_slice(&first.data.0).unwrap();
+        let rest_items = Vec::<(String, Item)>::try_from_slice(&rest.data.0).unwrap();
+        assert_eq!(vec!["0x1".to_string(), "0x2".to_string()], first_items.iter().map(|(address, _)| address.clone()).collect::<Vec<_>>());
+        assert_eq!(1, rest_items.len());
+        assert_eq!("Switch", rest_items[0].1.name);
+        assert!(first.cursor.is_some());
+        assert!(rest.cursor.is_none());
+    }
+
+    #[test]
+    fn import_state_restores_deleted_records_from_an_export() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.update_config(ConfigPatch { trash_grace_period: Some(0), ..Default::default() });
+        contract.add_role_set_product("Bob".to_string());
+        testing_env!(get_context_for("Bob"));
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+        testing_env!(get_context_for("Paul"));
+        contract.set_products("0x2".to_string(), "Xbox".to_string(), 400, 3);
+        let export = contract.export_state(None, 10);
+        contract.delete_products("0x1".to_string());
+        assert!(get_item(&contract, "0x1").is_none());
+
+        let summary = contract.import_state(export.data);
+        assert_eq!(vec!["0x1".to_string()], summary.updated);
+        assert_eq!(vec![("0x2".to_string(), "Product exists".to_string())], summary.failed);
+        assert_eq!(12, get_item(&contract, "0x1").unwrap().stock);
+        assert_eq!(1, contract.get_vendor_catalog("Bob".to_string(), None, 10).items.len());
+        assert_eq!(1, contract.find_products_by_name("ps5".to_string()).len());
+    }
+
+    #[test]
+    fn import_state_does_not_carry_over_deposits_or_approvals() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.update_config(ConfigPatch { trash_grace_period: Some(0), ..Default::default() });
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+        let mut export = Vec::<(String, Item)>::try_from_slice(&contract.export_state(None, 10).data.0).unwrap();
+        export[0].1.listing_deposit = 10_000;
+        export[0].1.report_count = 7;
+        contract.delete_products("0x1".to_string());
+        contract.set_moderation_required(true);
+
+        contract.import_state(Base64VecU8(export.try_to_vec().unwrap()));
+        let item = get_item(&contract, "0x1").unwrap();
+        assert_eq!(0, item.listing_deposit);
+        assert_eq!(0, item.report_count);
+        assert_eq!(Approval::Pending, item.approval);
+    }
+
+    #[test]
+    fn import_state_refuses_trashed_keys_and_unknown_creators() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+        let data = contract.export_state(None, 10).data;
+        let mut export = Vec::<(String, Item)>::try_from_slice(&data.0).unwrap();
+        contract.delete_products("0x1".to_string());
+        let (_, mut forged) = Vec::<(String, Item)>::try_from_slice(&data.0).unwrap().remove(0);
+        forged.creator = "mallory.near".to_string();
+        export.push(("0x2".to_string(), forged));
+
+        let summary = contract.import_state(Base64VecU8(export.try_to_vec().unwrap()));
+        assert!(summary.updated.is_empty());
+        assert_eq!(vec![
+            ("0x1".to_string(), "Product is in the trash".to_string()),
+            ("0x2".to_string(), "Creator cannot list here".to_string()),
+        ], summary.failed);
+        assert!(get_item(&contract, "0x2").is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "left == right")]
+    fn only_the_owner_can_import_state() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        let export = contract.export_state(None, 10);
+        testing_env!(get_context_for("mallory.near"));
+        contract.import_state(export.data);
+    }
+
+    #[test]
+    fn product_detail_bundles_price_and_sales() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 450, 12);
+
+        testing_env!(get_context_with_deposit("Bob", 900));
+        contract.buy_product("0x1".to_string(), 2);
+
+        let detail = contract.get_product_detail("0x1".to_string()).unwrap();
+        assert_eq!("Paul", detail.vendor);
+        assert_eq!(10, detail.item.stock);
+        assert_eq!(2, detail.sales.units);
+        assert_eq!(450, detail.price_summary.lowest);
+        assert_eq!(500, detail.price_summary.highest);
+        assert!(contract.get_product_detail("0x2".to_string()).is_none());
+    }
+
+    #[test]
+    fn vendor_catalog_follows_writes_and_deletes() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.add_role_set_product("Bob".to_string());
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+        testing_env!(get_context_for("Bob"));
+        contract.set_products("0x2".to_string(), "Xbox".to_string(), 400, 3);
+        contract.set_products("0x3".to_string(), "Switch".to_string(), 300, 7);
+
+        let bob = contract.get_vendor_catalog("Bob".to_string(), None, 10);
+        assert_eq!(vec!["0x2".to_string(), "0x3".to_string()], bob.items.iter().map(|(address, _)| address.clone()).collect::<Vec<_>>());
+        let first = contract.get_vendor_catalog("Bob".to_string(), None, 1);
+        assert_eq!("0x3", contract.get_vendor_catalog("Bob".to_string(), first.cursor, 10).items[0].0);
+
+        testing_env!(get_context_for("Paul"));
+        contract.delete_products("0x2".to_string());
+        assert_eq!(1, contract.get_vendor_catalog("Bob".to_string(), None, 10).items.len());
+        assert_eq!(1, contract.get_vendor_catalog("Paul".to_string(), None, 10).items.len());
+    }
+
+    #[test]
+    fn find_products_by_name_tracks_renames() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+        contract.set_products("0x2".to_string(), "PS5".to_string(), 520, 1);
+        assert_eq!(2, contract.find_products_by_name(" PS5 ".to_string()).len());
+
+        contract.set_products("0x2".to_string(), "PS5 Pro".to_string(), 700, 1);
+        assert_eq!("0x1", contract.find_products_by_name("PS5".to_string())[0].0);
+        assert_eq!(1, contract.find_products_by_name("PS5 Pro".to_string()).len());
+
+        contract.delete_products("0x1".to_string());
+        assert!(contract.find_products_by_name("PS5".to_string()).is_empty());
+    }
+
+    #[test]
+    fn name_lookups_ignore_case() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+        contract.set_products("0x2".to_string(), "ps5 ".to_string(), 520, 1);
+
+        let found = contract.find_products_by_name("Ps5".to_string());
+        assert_eq!(2, found.len());
+        assert_eq!("PS5", found[0].1.name);
+        assert_eq!("ps5", found[1].1.search_key);
+    }
+
+    #[test]
+    fn prefix_scan_browses_hierarchical_keys() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("vendorA/shoes/2".to_string(), "Boot".to_string(), 500, 12);
+        contract.set_products("vendorB/shoes/1".to_string(), "Sandal".to_string(), 100, 4);
+        contract.set_products("vendorA/shoes/1".to_string(), "Sneaker".to_string(), 300, 7);
+        contract.set_products("vendorA/hats/1".to_string(), "Cap".to_string(), 50, 9);
+
+        let shoes = contract.get_products_with_prefix("vendorA/shoes/".to_string(), 10);
+        assert_eq!(vec!["vendorA/shoes/1".to_string(), "vendorA/shoes/2".to_string()], shoes.iter().map(|(address, _)| address.clone()).collect::<Vec<_>>());
+        assert_eq!(1, contract.get_products_with_prefix("vendorA/".to_string(), 1).len());
+
+        contract.delete_products("vendorA/shoes/1".to_string());
+        assert_eq!(1, contract.get_products_with_prefix("vendorA/shoes/".to_string(), 10).len());
+    }
+
+    #[test]
+    fn pages_do_not_shift_when_the_catalog_changes() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        for i in 0..6 {
+            contract.set_products(format!("0x{}", i), "PS5".to_string(), 500, 1);
+        }
+        let first = contract.get_products_list(None, 3);
+
+        // removing a key already handed out used to move the last key into its slot
+        contract.delete_products("0x0".to_string());
+        contract.set_products("0x00".to_string(), "PS5".to_string(), 500, 1);
+        let second = contract.get_products_list(first.cursor, 10);
+        let keys: Vec<String> = second.items.iter().map(|(address, _)| address.clone()).collect();
+        assert_eq!(vec!["0x3".to_string(), "0x4".to_string(), "0x5".to_string()], keys);
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid cursor")]
+    fn edited_cursors_are_rejected() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        for i in 0..4 {
+            contract.set_products(format!("0x{}", i), "PS5".to_string(), 500, 1);
+        }
+        assert!(contract.get_products_list(None, 2).cursor.is_some());
+
+        let edited = near_sdk::base64::encode((Some("0x2".to_string()), vec![0u8; 8]).try_to_vec().unwrap());
+        contract.get_products_list(Some(edited), 2);
+    }
+
+    #[test]
+    fn clone_product_copies_with_overrides() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+        contract.clone_product("0x1".to_string(), "0x2".to_string(), CloneOverrides { price: Some(450), ..Default::default() });
+
+        let copy = get_item(&contract, "0x2").unwrap();
+        assert_eq!("PS5", copy.name);
+        assert_eq!(450, copy.price);
+        assert_eq!(12, copy.stock);
+        assert_eq!(500, get_item(&contract, "0x1").unwrap().price);
+        assert_eq!(2, contract.find_products_by_name("ps5".to_string()).len());
+    }
+
+    #[test]
+    fn clone_product_carries_the_selling_settings() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+        contract.set_visibility("0x1".to_string(), Visibility::Private);
+        contract.add_invitees("0x1".to_string(), vec!["Bob".to_string()]);
+        contract.set_max_per_buyer("0x1".to_string(), 2);
+        contract.set_pay_what_you_want("0x1".to_string(), true);
+        contract.set_payment_split("0x1".to_string(), vec![("Bob".to_string(), 2_000), ("Paul".to_string(), 8_000)]);
+        contract.set_token_gate("0x1".to_string(), Some(TokenGate { token: "token.near".to_string(), min_balance: 10 }));
+        contract.clone_product("0x1".to_string(), "0x2".to_string(), CloneOverrides::default());
+
+        let copy = get_item(&contract, "0x2").unwrap();
+        // saved once, with the settings already on it
+        assert_eq!(1, copy.version);
+        assert_eq!(Visibility::Private, copy.visibility);
+        assert_eq!(2, copy.max_per_buyer);
+        assert!(copy.pay_what_you_want);
+        assert_eq!(vec!["Bob".to_string()], contract.get_invitees("0x2".to_string()));
+        assert_eq!(contract.get_payment_split("0x1".to_string()), contract.get_payment_split("0x2".to_string()));
+        assert!(contract.token_gates.get(&"0x2".to_string()).is_some());
+    }
+
+    #[test]
+    #[should_panic(expected = "Product does not exist")]
+    fn clone_product_needs_read_access_to_the_source() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+        contract.set_visibility("0x1".to_string(), Visibility::Private);
+        contract.add_role_set_product("Bob".to_string());
+
+        testing_env!(get_context_for("Bob"));
+        contract.clone_product("0x1".to_string(), "0x2".to_string(), CloneOverrides::default());
+    }
+
+    #[test]
+    #[should_panic(expected = "Product already exists")]
+    fn clone_product_never_overwrites() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+        contract.set_products("0x2".to_string(), "Xbox".to_string(), 400, 3);
+        contract.clone_product("0x1".to_string(), "0x2".to_string(), CloneOverrides::default());
+    }
+
+    #[test]
+    fn adjust_prices_applies_a_percentage() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+        contract.set_products("0x2".to_string(), "Xbox".to_string(), 400, 3);
+
+        let summary = contract.adjust_prices(vec!["0x1".to_string(), "0x2".to_string(), "0x9".to_string()], -1000);
+        assert_eq!(2, summary.updated.len());
+        assert_eq!(vec![("0x9".to_string(), "Product does not exist".to_string())], summary.failed);
+        assert_eq!(450, get_item(&contract, "0x1").unwrap().price);
+        assert_eq!(360, get_item(&contract, "0x2").unwrap().price);
+        assert_eq!(450, contract.get_price_history("0x1".to_string(), 1)[0].price);
+    }
+
+    #[test]
+    fn stock_corrections_are_ledgered_with_reasons() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+        contract.set_products("0x2".to_string(), "Xbox".to_string(), 400, 3);
+
+        let summary = contract.adjust_stock_batch(vec![
+            ("0x1".to_string(), -2, "damage".to_string()),
+            ("0x2".to_string(), -5, "theft".to_string()),
+            ("0x2".to_string(), 1, "lost".to_string()),
+        ]);
+        assert_eq!(vec!["0x1".to_string()], summary.updated);
+        assert_eq!(2, summary.failed.len());
+        assert_eq!(10, get_item(&contract, "0x1").unwrap().stock);
+
+        let movement = &contract.get_stock_ledger("0x1".to_string(), 1)[0];
+        assert_eq!(-2, movement.delta);
+        assert_eq!(StockReason::Damage, movement.reason);
+    }
+
+    #[test]
+    fn scheduled_price_takes_effect_at_its_time() {
+        let mut context = get_context(vec![], false);
+        context.block_timestamp = 1_000;
+        testing_env!(context);
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+        contract.set_price_effective_at("0x1".to_string(), 400, 2_000);
+        assert_eq!(500, get_item(&contract, "0x1").unwrap().price);
+
+        let mut context = get_context_with_deposit("Bob", 500);
+        context.block_timestamp = 2_000;
+        testing_env!(context);
+        assert_eq!(400, get_item(&contract, "0x1").unwrap().price);
+        contract.buy_product("0x1".to_string(), 1);
+        // only the effective price is charged, the rest comes back with the refund
+        assert_eq!(1, transfer_count());
+        assert_eq!(400, contract.get_revenue("Paul".to_string()).0);
+        assert!(contract.records.get(&"0x1".to_string()).unwrap().scheduled_price.is_none());
+    }
+
+    #[test]
+    fn scheduled_prices_enter_the_history_when_they_take_effect() {
+        let mut context = get_context(vec![], false);
+        context.block_timestamp = 1_000;
+        testing_env!(context);
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+        contract.set_price_effective_at("0x1".to_string(), 400, 2_000);
+        assert_eq!(vec![500], contract.get_price_history("0x1".to_string(), 10).iter().map(|point| point.price).collect::<Vec<u128>>());
+
+        // due but not written yet, the view shows it already
+        let mut context = get_context_for("Paul");
+        context.block_timestamp = 2_500;
+        testing_env!(context);
+        assert_eq!(400, contract.get_price_history("0x1".to_string(), 1)[0].price);
+
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 450, 12);
+        let history = contract.get_price_history("0x1".to_string(), 10);
+        assert_eq!(vec![(450, 2_500), (400, 2_000), (500, 1_000)], history.iter().map(|point| (point.price, point.changed_at)).collect::<Vec<(u128, u64)>>());
+    }
+
+    #[test]
+    #[should_panic(expected = "Purchase exceeds the per-buyer limit of 2")]
+    fn buyers_are_capped_across_purchases() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+        contract.set_max_per_buyer("0x1".to_string(), 2);
+
+        testing_env!(get_context_with_deposit("Bob", 500));
+        contract.buy_product("0x1".to_string(), 1);
+        testing_env!(get_context_with_deposit("Carol", 1000));
+        contract.buy_product("0x1".to_string(), 2);
+        testing_env!(get_context_with_deposit("Bob", 1000));
+        contract.buy_product("0x1".to_string(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "Product is frozen")]
+    fn frozen_products_keep_their_per_buyer_cap() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.add_role_moderator("Paul".to_string());
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+        contract.freeze_product("0x1".to_string());
+        contract.set_max_per_buyer("0x1".to_string(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "SPEND_LIMIT_EXCEEDED")]
+    fn account_spending_is_limited_per_window() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+        contract.set_products("0x2".to_string(), "Xbox".to_string(), 400, 3);
+        contract.update_config(ConfigPatch { account_spend_limit: Some(1000), ..Default::default() });
+
+        testing_env!(get_context_with_deposit("Bob", 500));
+        contract.buy_product("0x1".to_string(), 1);
+        testing_env!(get_context_with_deposit("Bob", 400));
+        contract.buy_product("0x2".to_string(), 1);
+        let (spent, units) = contract.get_account_activity("Bob".to_string());
+        assert_eq!((900, 2), (spent.0, units));
+        testing_env!(get_context_with_deposit("Bob", 400));
+        contract.buy_product("0x2".to_string(), 1);
+    }
+
+    #[test]
+    fn account_limits_roll_with_the_window() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+        contract.update_config(ConfigPatch { account_quantity_limit: Some(1), limit_window: Some(2_400), ..Default::default() });
+
+        testing_env!(get_context_with_deposit("Bob", 500));
+        contract.buy_product("0x1".to_string(), 1);
+        let mut context = get_context_with_deposit("Bob", 500);
+        context.block_timestamp = 2_500;
+        testing_env!(context);
+        contract.buy_product("0x1".to_string(), 1);
+        assert_eq!(10, get_item(&contract, "0x1").unwrap().stock);
+    }
+
+    #[test]
+    fn preorders_escrow_until_converted() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS6".to_string(), 500, 0);
+        contract.set_preorder("0x1".to_string(), Some(1_000));
+
+        testing_env!(get_context_with_deposit("Bob", 500));
+        let first = contract.buy_product("0x1".to_string(), 1);
+        testing_env!(get_context_with_deposit("Carol", 1000));
+        let second = contract.buy_product("0x1".to_string(), 2);
+        assert_eq!(0, get_item(&contract, "0x1").unwrap().stock);
+        assert_eq!(2, contract.get_product_orders("0x1".to_string()).len());
+
+        testing_env!(get_context_for("Paul"));
+        contract.set_products("0x1".to_string(), "PS6".to_string(), 500, 2);
+        assert_eq!(vec![first], contract.convert_preorders("0x1".to_string(), 10));
+        assert_eq!(1, get_item(&contract, "0x1").unwrap().stock);
+        assert_eq!(OrderStatus::Fulfilled, contract.get_order(first).unwrap().status);
+
+        assert_eq!(vec![second], contract.refund_preorders("0x1".to_string(), 10));
+        assert_eq!(OrderStatus::Refunded, contract.get_order(second).unwrap().status);
+        // the converted order still holds its escrow until the buyer confirms delivery
+        assert_eq!(vec![first], contract.get_product_orders("0x1".to_string()).into_iter().map(|(id, _)| id).collect::<Vec<u64>>());
+        assert_eq!(0, contract.get_revenue("Paul".to_string()).0);
+
+        testing_env!(get_context_for("Bob"));
+        contract.confirm_delivery(first);
+        assert_eq!(500, contract.get_revenue("Paul".to_string()).0);
+        assert!(contract.get_product_orders("0x1".to_string()).is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "Product has open orders")]
+    fn converted_preorders_block_deletion_until_released() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS6".to_string(), 500, 0);
+        contract.set_preorder("0x1".to_string(), Some(1_000));
+        testing_env!(get_context_with_deposit("Bob", 500));
+        contract.buy_product("0x1".to_string(), 1);
+
+        testing_env!(get_context_for("Paul"));
+        contract.set_products("0x1".to_string(), "PS6".to_string(), 500, 1);
+        contract.convert_preorders("0x1".to_string(), 10);
+        contract.delete_products("0x1".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "Product is frozen")]
+    fn frozen_products_cannot_open_preorders() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.add_role_moderator("Paul".to_string());
+        contract.set_products("0x1".to_string(), "PS6".to_string(), 500, 0);
+        contract.freeze_product("0x1".to_string());
+        contract.set_preorder("0x1".to_string(), Some(1_000));
+    }
+
+    #[test]
+    #[should_panic(expected = "Product has open orders")]
+    fn products_with_escrowed_orders_cannot_be_deleted() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS6".to_string(), 500, 0);
+        contract.set_preorder("0x1".to_string(), Some(1_000));
+        testing_env!(get_context_with_deposit("Bob", 500));
+        contract.buy_product("0x1".to_string(), 1);
+
+        testing_env!(get_context_for("Paul"));
+        contract.delete_products("0x1".to_string());
+    }
+
+    #[test]
+    fn backorders_fill_in_order_when_stock_arrives() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 1);
+        contract.set_backorders("0x1".to_string(), true);
+
+        testing_env!(get_context_with_deposit("Bob", 1000));
+        let bob = contract.buy_product("0x1".to_string(), 2);
+        testing_env!(get_context_with_deposit("Carol", 500));
+        let carol = contract.buy_product("0x1".to_string(), 1);
+        testing_env!(get_context_with_deposit("Dave", 500));
+        let dave = contract.buy_product("0x1".to_string(), 1);
+        assert_eq!(1, get_item(&contract, "0x1").unwrap().stock);
+
+        testing_env!(get_context_for("Dave"));
+        contract.cancel_order(dave);
+        assert_eq!(OrderStatus::Cancelled, contract.get_order(dave).unwrap().status);
+
+        testing_env!(get_context_for("Paul"));
+        assert_eq!(vec![bob, carol], contract.increase_stock("0x1".to_string(), 2));
+        assert_eq!(0, get_item(&contract, "0x1").unwrap().stock);
+        assert_eq!(OrderStatus::Fulfilled, contract.get_order(carol).unwrap().status);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only queued backorders can be cancelled")]
+    fn fulfilled_orders_cannot_be_cancelled() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 0);
+        contract.set_backorders("0x1".to_string(), true);
+        testing_env!(get_context_with_deposit("Bob", 500));
+        let order_id = contract.buy_product("0x1".to_string(), 1);
+
+        testing_env!(get_context_for("Paul"));
+        contract.increase_stock("0x1".to_string(), 1);
+        testing_env!(get_context_for("Bob"));
+        contract.cancel_order(order_id);
+    }
+
+    #[test]
+    #[should_panic(expected = "Product is archived")]
+    fn archived_products_cannot_take_backorders() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 0);
+        contract.set_lifecycle_state("0x1".to_string(), LifecycleState::Discontinued);
+        contract.set_lifecycle_state("0x1".to_string(), LifecycleState::Archived);
+        contract.set_backorders("0x1".to_string(), true);
+    }
+
+    #[test]
+    fn subscriptions_are_charged_until_the_balance_runs_dry() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "Magazine".to_string(), 0, 1);
+        let plan_id = contract.create_subscription_plan("0x1".to_string(), 100, 300, None);
+
+        testing_env!(get_context_with_deposit("Bob", 700));
+        let subscription_id = contract.subscribe(plan_id);
+
+        testing_env!(get_context_for("Cron"));
+        assert_eq!(1, contract.charge_subscriptions(10));
+        // not due again before the interval passed
+        assert_eq!(0, contract.charge_subscriptions(10));
+
+        let mut context = get_context_for("Cron");
+        context.block_timestamp = 100;
+        testing_env!(context);
+        assert_eq!(1, contract.charge_subscriptions(10));
+        assert_eq!(100, contract.get_subscription(subscription_id).unwrap().balance);
+
+        let mut context = get_context_for("Cron");
+        context.block_timestamp = 200;
+        testing_env!(context);
+        assert_eq!(0, contract.charge_subscriptions(10));
+        assert!(!contract.has_access(subscription_id));
+
+        let mut context = get_context_with_deposit("Bob", 300);
+        context.block_timestamp = 250;
+        testing_env!(context);
+        contract.top_up_subscription(subscription_id);
+        assert!(contract.has_access(subscription_id));
+    }
+
+    #[test]
+    #[should_panic(expected = "Stock out of range")]
+    fn returns_cannot_overflow_the_stock() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "Camera".to_string(), 5000, 2);
+        contract.set_rental_terms("0x1".to_string(), Some(RentalTerms { price: 100, security_deposit: 1000, duration: 1_000 }));
+        testing_env!(get_context_with_deposit("Bob", 1100));
+        let order_id = contract.rent_product("0x1".to_string());
+
+        testing_env!(get_context_for("Paul"));
+        contract.set_products("0x1".to_string(), "Camera".to_string(), 5000, 255);
+        contract.return_item(order_id, 0);
+    }
+
+    #[test]
+    fn rentals_hold_the_security_deposit_until_returned() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "Camera".to_string(), 5000, 2);
+        contract.set_rental_terms("0x1".to_string(), Some(RentalTerms { price: 100, security_deposit: 1000, duration: 1_000 }));
+
+        testing_env!(get_context_with_deposit("Bob", 1100));
+        let order_id = contract.rent_product("0x1".to_string());
+        assert_eq!(1, get_item(&contract, "0x1").unwrap().stock);
+        assert_eq!(OrderStatus::Rented, contract.get_order(order_id).unwrap().status);
+
+        testing_env!(get_context_for("Paul"));
+        contract.return_item(order_id, 250);
+        assert_eq!(2, get_item(&contract, "0x1").unwrap().stock);
+        assert_eq!(OrderStatus::Returned, contract.get_order(order_id).unwrap().status);
+        // damage charge to the vendor, the rest of the deposit back to the renter
+        assert_eq!(2, transfer_count());
+    }
+
+    #[test]
+    #[should_panic(expected = "Damage charge exceeds the security deposit")]
+    fn damage_charge_is_bounded_by_the_deposit() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "Camera".to_string(), 5000, 2);
+        contract.set_rental_terms("0x1".to_string(), Some(RentalTerms { price: 100, security_deposit: 1000, duration: 1_000 }));
+        testing_env!(get_context_with_deposit("Bob", 1100));
+        let order_id = contract.rent_product("0x1".to_string());
+
+        testing_env!(get_context_for("Paul"));
+        contract.return_item(order_id, 1001);
+    }
+
+    #[test]
+    fn renters_reclaim_the_deposit_once_the_vendor_misses_the_return() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.update_config(ConfigPatch { rental_grace_period: Some(500), ..Default::default() });
+        contract.set_products("0x1".to_string(), "Camera".to_string(), 5000, 2);
+        contract.set_rental_terms("0x1".to_string(), Some(RentalTerms { price: 100, security_deposit: 1000, duration: 1_000 }));
+        testing_env!(get_context_with_deposit("Bob", 1100));
+        let order_id = contract.rent_product("0x1".to_string());
+
+        let mut context = get_context_for("Bob");
+        context.block_timestamp = 1_500;
+        testing_env!(context);
+        contract.reclaim_rental_deposit(order_id);
+        assert_eq!(OrderStatus::Returned, contract.get_order(order_id).unwrap().status);
+        assert_eq!(2, get_item(&contract, "0x1").unwrap().stock);
+        // the whole deposit back to the renter
+        assert_eq!(1, transfer_count());
+    }
+
+    #[test]
+    #[should_panic(expected = "Rental deposit is still held")]
+    fn rental_deposits_are_held_through_the_grace_period() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.update_config(ConfigPatch { rental_grace_period: Some(500), ..Default::default() });
+        contract.set_products("0x1".to_string(), "Camera".to_string(), 5000, 2);
+        contract.set_rental_terms("0x1".to_string(), Some(RentalTerms { price: 100, security_deposit: 1000, duration: 1_000 }));
+        testing_env!(get_context_with_deposit("Bob", 1100));
+        let order_id = contract.rent_product("0x1".to_string());
+
+        let mut context = get_context_for("Bob");
+        context.block_timestamp = 1_499;
+        testing_env!(context);
+        contract.reclaim_rental_deposit(order_id);
+    }
+
+    #[test]
+    #[should_panic(expected = "Product is frozen")]
+    fn frozen_products_keep_their_rental_terms() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.add_role_moderator("Paul".to_string());
+        contract.set_products("0x1".to_string(), "Camera".to_string(), 5000, 1);
+        contract.freeze_product("0x1".to_string());
+        contract.set_rental_terms("0x1".to_string(), Some(RentalTerms { price: 100, security_deposit: 1000, duration: 1_000 }));
+    }
+
+    #[test]
+    fn auction_refunds_outbid_and_settles_to_the_winner() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "Guitar".to_string(), 0, 1);
+        let auction_id = contract.create_auction("0x1".to_string(), 100, 1_000);
+
+        testing_env!(get_context_with_deposit("Bob", 100));
+        contract.bid(auction_id);
+        testing_env!(get_context_with_deposit("Carol", 150));
+        contract.bid(auction_id);
+        // Bob's bid goes back as soon as it is beaten
+        assert_eq!(1, transfer_count());
+
+        let mut context = get_context_for("Dave");
+        context.block_timestamp = 1_000;
+        testing_env!(context);
+        assert_eq!(Some("Carol".to_string()), contract.finalize_auction(auction_id));
+        assert_eq!(0, get_item(&contract, "0x1").unwrap().stock);
+        assert!(contract.get_auction(auction_id).unwrap().settled);
+    }
+
+    #[test]
+    #[should_panic(expected = "Bid must beat the highest bid")]
+    fn lower_bids_are_refused() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "Guitar".to_string(), 0, 1);
+        let auction_id = contract.create_auction("0x1".to_string(), 100, 1_000);
+
+        testing_env!(get_context_with_deposit("Bob", 150));
+        contract.bid(auction_id);
+        testing_env!(get_context_with_deposit("Carol", 150));
+        contract.bid(auction_id);
+    }
+
+    #[test]
+    fn discontinued_products_refund_the_auction_winner() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "Guitar".to_string(), 0, 1);
+        let auction_id = contract.create_auction("0x1".to_string(), 100, 1_000);
+        testing_env!(get_context_with_deposit("Bob", 150));
+        contract.bid(auction_id);
+        testing_env!(get_context_for("Paul"));
+        contract.set_lifecycle_state("0x1".to_string(), LifecycleState::Discontinued);
+
+        let mut context = get_context_for("Dave");
+        context.block_timestamp = 1_000;
+        testing_env!(context);
+        assert_eq!(None, contract.finalize_auction(auction_id));
+        assert_eq!(1, get_item(&contract, "0x1").unwrap().stock);
+        assert_eq!(1, transfer_count());
+    }
+
+    #[test]
+    fn decaying_price_is_charged_at_the_current_block() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "Tulip".to_string(), 1000, 5);
+        contract.set_price_decay("0x1".to_string(), Some(PriceDecay {
+            start_price: 1000, floor_price: 200, starts_at: 0, ends_at: 800, step: 0,
+        }));
+
+        let mut context = get_context_with_deposit("Bob", 1000);
+        context.block_timestamp = 300;
+        testing_env!(context);
+        assert_eq!(700, get_item(&contract, "0x1").unwrap().price);
+        contract.buy_product("0x1".to_string(), 1);
+        // the 300 surplus refund, the vendor's share accrues as revenue
+        assert_eq!(1, transfer_count());
+        assert_eq!(700, contract.get_revenue("Paul".to_string()).0);
+
+        let mut context = get_context_for("Bob");
+        context.block_timestamp = 5_000;
+        testing_env!(context);
+        assert_eq!(200, get_item(&contract, "0x1").unwrap().price);
+    }
+
+    #[test]
+    fn stepped_decay_moves_in_whole_steps() {
+        let decay = PriceDecay { start_price: 1000, floor_price: 0, starts_at: 0, ends_at: 1000, step: 250 };
+        assert_eq!(1000, decay.price_at(249));
+        assert_eq!(750, decay.price_at(250));
+        assert_eq!(250, decay.price_at(999));
+    }
+
+    #[test]
+    fn decay_handles_yocto_scale_prices() {
+        // 10 NEAR down to 1 NEAR over one day, the full-width product would overflow past the nine hour mark
+        let day = 24 * 60 * 60 * 1_000_000_000u64;
+        let near = 1_000_000_000_000_000_000_000_000u128;
+        let decay = PriceDecay { start_price: 10 * near, floor_price: near, starts_at: 0, ends_at: day, step: 0 };
+        assert_eq!(10 * near - 9 * near / 2, decay.price_at(day / 2));
+        assert_eq!(10 * near - 9 * near / 4 * 3, decay.price_at(day / 4 * 3));
+        assert!(decay.price_at(day - 1) > near);
+    }
+
+    #[test]
+    fn accepted_offers_settle_and_rejected_ones_refund() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 5);
+
+        testing_env!(get_context_with_deposit("Bob", 800));
+        let bob = contract.make_offer("0x1".to_string(), 2, 1_000);
+        testing_env!(get_context_with_deposit("Carol", 300));
+        let carol = contract.make_offer("0x1".to_string(), 1, 1_000);
+        assert_eq!(2, contract.get_product_offers("0x1".to_string()).len());
+
+        testing_env!(get_context_for("Paul"));
+        contract.accept_offer(bob);
+        contract.reject_offer(carol);
+        assert_eq!(3, get_item(&contract, "0x1").unwrap().stock);
+        assert!(contract.get_product_offers("0x1".to_string()).is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "Offer has expired")]
+    fn expired_offers_cannot_be_accepted() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 5);
+        testing_env!(get_context_with_deposit("Bob", 400));
+        let offer_id = contract.make_offer("0x1".to_string(), 1, 1_000);
+
+        let mut context = get_context_for("Paul");
+        context.block_timestamp = 1_000;
+        testing_env!(context);
+        contract.accept_offer(offer_id);
+    }
+
+    #[test]
+    fn quotes_are_redeemed_once_at_the_quoted_price() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 10);
+        let quote_id = contract.create_quote("0x1".to_string(), "Bob".to_string(), 400, 5, 1_000);
+
+        testing_env!(get_context_with_deposit("Bob", 2000));
+        contract.buy_with_quote(quote_id);
+        assert_eq!(5, get_item(&contract, "0x1").unwrap().stock);
+        assert_eq!(500, get_item(&contract, "0x1").unwrap().price);
+        assert!(contract.get_quote(quote_id).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "401")]
+    fn quotes_are_bound_to_their_buyer() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 10);
+        let quote_id = contract.create_quote("0x1".to_string(), "Bob".to_string(), 400, 1, 1_000);
+
+        testing_env!(get_context_with_deposit("Carol", 400));
+        contract.buy_with_quote(quote_id);
+    }
+
+    #[test]
+    fn private_listings_are_hidden_and_invite_only() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "Wholesale PS5".to_string(), 400, 10);
+        contract.set_products("0x2".to_string(), "PS5".to_string(), 500, 10);
+        contract.set_visibility("0x1".to_string(), Visibility::Private);
+        contract.add_invitees("0x1".to_string(), vec!["Bob".to_string()]);
+
+        assert_eq!(1, contract.get_products_list(None, 10).items.len());
+        assert!(contract.find_products_by_name("wholesale ps5".to_string()).is_empty());
+        assert!(get_item(&contract, "0x1").is_some());
+
+        testing_env!(get_context_with_deposit("Bob", 400));
+        contract.buy_product("0x1".to_string(), 1);
+        assert_eq!(9, get_item(&contract, "0x1").unwrap().stock);
+    }
+
+    #[test]
+    #[should_panic(expected = "Product is frozen")]
+    fn frozen_products_keep_their_invitees() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.add_role_moderator("Paul".to_string());
+        contract.set_products("0x1".to_string(), "Wholesale PS5".to_string(), 400, 10);
+        contract.set_visibility("0x1".to_string(), Visibility::Private);
+        contract.freeze_product("0x1".to_string());
+        contract.add_invitees("0x1".to_string(), vec!["carol.near".to_string()]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Product is only sold to invitees")]
+    fn private_listings_refuse_other_buyers() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "Wholesale PS5".to_string(), 400, 10);
+        contract.set_visibility("0x1".to_string(), Visibility::Private);
+        contract.add_invitees("0x1".to_string(), vec!["Bob".to_string()]);
+
+        testing_env!(get_context_with_deposit("Carol", 400));
+        contract.buy_product("0x1".to_string(), 1);
+    }
+
+    #[test]
+    fn hidden_listings_are_sold_by_direct_link_only() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "Promo PS5".to_string(), 400, 10);
+        contract.set_visibility("0x1".to_string(), Visibility::Hidden);
+
+        assert!(contract.get_products_list(None, 10).items.is_empty());
+        assert!(contract.get_products_with_prefix("0x".to_string(), 10).is_empty());
+        assert!(contract.find_products_by_name("promo ps5".to_string()).is_empty());
+
+        testing_env!(get_context_with_deposit("Carol", 400));
+        contract.buy_product("0x1".to_string(), 1);
+        assert_eq!(9, get_item(&contract, "0x1").unwrap().stock);
+    }
+
+    #[test]
+    fn drafts_stay_hidden_until_published() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.save_draft("0x1".to_string(), "PS5".to_string(), 500, 10);
+        assert_eq!(LifecycleState::Draft, get_item(&contract, "0x1").unwrap().state);
+        assert!(contract.get_products_list(None, 10).items.is_empty());
+
+        // editing a draft keeps it a draft
+        contract.set_products("0x1".to_string(), "PS5 Slim".to_string(), 450, 10);
+        assert_eq!(LifecycleState::Draft, get_item(&contract, "0x1").unwrap().state);
+
+        contract.publish_product("0x1".to_string());
+        assert_eq!(1, contract.get_products_list(None, 10).items.len());
+    }
+
+    #[test]
+    #[should_panic(expected = "Product is not for sale")]
+    fn drafts_cannot_be_bought() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.save_draft("0x1".to_string(), "PS5".to_string(), 500, 10);
+
+        testing_env!(get_context_with_deposit("Carol", 500));
+        contract.buy_product("0x1".to_string(), 1);
+    }
+
+    #[test]
+    fn staged_revisions_publish_and_roll_back() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 10);
+        contract.stage_revision("0x1".to_string(), "PS5 Slim".to_string(), 450);
+        assert_eq!("PS5", get_item(&contract, "0x1").unwrap().name);
+
+        contract.publish_revision("0x1".to_string());
+        let item = get_item(&contract, "0x1").unwrap();
+        assert_eq!(("PS5 Slim".to_string(), 450, 10), (item.name, item.price, item.stock));
+        assert!(contract.get_revision("0x1".to_string()).is_none());
+
+        // units sold after the publish stay sold
+        testing_env!(get_context_with_deposit("Bob", 450));
+        contract.buy_product("0x1".to_string(), 1);
+        testing_env!(get_context_for("Paul"));
+        contract.rollback("0x1".to_string());
+        let item = get_item(&contract, "0x1").unwrap();
+        assert_eq!(("PS5".to_string(), 500, 9), (item.name, item.price, item.stock));
+        assert_eq!(1, contract.find_products_by_name("ps5".to_string()).len());
+        assert!(contract.find_products_by_name("ps5 slim".to_string()).is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "No staged revision")]
+    fn discarded_revisions_cannot_be_published() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 10);
+        contract.stage_revision("0x1".to_string(), "PS5 Slim".to_string(), 450);
+        contract.discard_revision("0x1".to_string());
+        contract.publish_revision("0x1".to_string());
+    }
+
+    #[test]
+    fn lifecycle_states_gate_listing_and_sales() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 10);
+
+        // paused listings stay visible but cannot be bought
+        contract.set_lifecycle_state("0x1".to_string(), LifecycleState::Paused);
+        assert_eq!(1, contract.get_products_list(None, 10).items.len());
+        contract.set_lifecycle_state("0x1".to_string(), LifecycleState::Active);
+
+        contract.set_lifecycle_state("0x1".to_string(), LifecycleState::Discontinued);
+        assert!(contract.get_products_list(None, 10).items.is_empty());
+        contract.set_lifecycle_state("0x1".to_string(), LifecycleState::Archived);
+        assert!(get_item(&contract, "0x1").is_some());
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot move a product from Discontinued to Active")]
+    fn lifecycle_rejects_invalid_transitions() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 10);
+        contract.set_lifecycle_state("0x1".to_string(), LifecycleState::Discontinued);
+        contract.set_lifecycle_state("0x1".to_string(), LifecycleState::Active);
+    }
+
+    #[test]
+    #[should_panic(expected = "Product is frozen")]
+    fn frozen_products_keep_their_lifecycle_state() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.add_role_moderator("Paul".to_string());
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 10);
+        contract.freeze_product("0x1".to_string());
+        contract.set_lifecycle_state("0x1".to_string(), LifecycleState::Paused);
+    }
+
+    #[test]
+    #[should_panic(expected = "Product is not for sale")]
+    fn paused_products_cannot_be_bought() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 10);
+        contract.set_lifecycle_state("0x1".to_string(), LifecycleState::Paused);
+
+        testing_env!(get_context_with_deposit("Carol", 500));
+        contract.buy_product("0x1".to_string(), 1);
+    }
+
+    #[test]
+    fn restocking_fulfills_queued_requests() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 1);
+        contract.add_role_warehouse("Paul".to_string());
+        let first = contract.request_restock("0x1".to_string(), 5);
+        let second = contract.request_restock("0x1".to_string(), 10);
+        assert_eq!(2, contract.get_open_restock_requests("0x1".to_string()).len());
+
+        contract.increase_stock("0x1".to_string(), 5);
+        assert!(contract.get_restock_request(first).unwrap().fulfilled_at.is_some());
+        assert!(contract.get_restock_request(second).unwrap().fulfilled_at.is_none());
+        assert_eq!(vec![second], contract.get_open_restock_requests("0x1".to_string()).into_iter().map(|(id, _)| id).collect::<Vec<u64>>());
+    }
+
+    #[test]
+    fn purchase_orders_flow_into_stock() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 1);
+        contract.add_role_warehouse("Paul".to_string());
+        contract.add_supplier("Sony".to_string());
+        let line = PurchaseOrderLine { address: "0x1".to_string(), quantity: 20, unit_cost: 300 };
+        let po_id = contract.create_purchase_order("Sony".to_string(), vec![line]);
+
+        testing_env!(get_context_for("Sony"));
+        contract.acknowledge_purchase_order(po_id);
+
+        testing_env!(get_context_for("Paul"));
+        contract.fulfill_purchase_order(po_id);
+        assert_eq!(21, get_item(&contract, "0x1").unwrap().stock);
+        let order = contract.get_purchase_order(po_id).unwrap();
+        assert_eq!(PurchaseOrderStatus::Fulfilled, order.status);
+        assert_eq!(6000, order.total_cost());
+        assert!(matches!(contract.get_stock_ledger("0x1".to_string(), 1)[0].reason, StockReason::Receiving));
+    }
+
+    #[test]
+    fn short_shipments_record_discrepancies() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 1);
+        contract.set_products("0x2".to_string(), "Controller".to_string(), 50, 0);
+        contract.add_role_warehouse("Paul".to_string());
+        contract.add_supplier("Sony".to_string());
+        let line = PurchaseOrderLine { address: "0x1".to_string(), quantity: 20, unit_cost: 300 };
+        let po_id = contract.create_purchase_order("Sony".to_string(), vec![line]);
+
+        testing_env!(get_context_for("Sony"));
+        contract.acknowledge_purchase_order(po_id);
+
+        testing_env!(get_context_for("Paul"));
+        let discrepancies = contract.receive_shipment(po_id, vec![("0x1".to_string(), 15), ("0x2".to_string(), 4)]);
+        assert_eq!(16, get_item(&contract, "0x1").unwrap().stock);
+        assert_eq!(4, get_item(&contract, "0x2").unwrap().stock);
+        assert_eq!(vec![
+            Discrepancy { address: "0x1".to_string(), expected: 20, received: 15 },
+            Discrepancy { address: "0x2".to_string(), expected: 0, received: 4 },
+        ], discrepancies);
+        assert_eq!(discrepancies, contract.get_purchase_order(po_id).unwrap().discrepancies);
+    }
+
+    #[test]
+    fn received_shipments_fill_queued_backorders() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 0);
+        contract.set_backorders("0x1".to_string(), true);
+        contract.add_role_warehouse("Paul".to_string());
+        contract.add_supplier("Sony".to_string());
+        testing_env!(get_context_with_deposit("Bob", 1000));
+        let order_id = contract.buy_product("0x1".to_string(), 2);
+
+        testing_env!(get_context_for("Paul"));
+        let line = PurchaseOrderLine { address: "0x1".to_string(), quantity: 5, unit_cost: 300 };
+        let po_id = contract.create_purchase_order("Sony".to_string(), vec![line]);
+        testing_env!(get_context_for("Sony"));
+        contract.acknowledge_purchase_order(po_id);
+        testing_env!(get_context_for("Paul"));
+        contract.receive_shipment(po_id, vec![("0x1".to_string(), 5)]);
+
+        assert_eq!(3, get_item(&contract, "0x1").unwrap().stock);
+        assert_eq!(OrderStatus::Fulfilled, contract.get_order(order_id).unwrap().status);
+    }
+
+    #[test]
+    fn stock_is_tracked_per_location() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 0);
+        contract.add_role_warehouse("Paul".to_string());
+        contract.set_location_stock("0x1".to_string(), "berlin".to_string(), 10);
+        contract.set_location_stock("0x1".to_string(), "lisbon".to_string(), 4);
+        assert_eq!(14, get_item(&contract, "0x1").unwrap().stock);
+
+        contract.transfer_stock("0x1".to_string(), "berlin".to_string(), "lisbon".to_string(), 3);
+        assert_eq!(14, get_item(&contract, "0x1").unwrap().stock);
+        assert_eq!(vec![("berlin".to_string(), 7), ("lisbon".to_string(), 7)], contract.get_location_stock("0x1".to_string()));
+        assert_eq!(0, contract.get_available_at("0x1".to_string(), "madrid".to_string()));
+    }
+
+    #[test]
+    fn sales_draw_down_location_stock() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 2);
+        contract.add_role_warehouse("Paul".to_string());
+        contract.set_location_stock("0x1".to_string(), "berlin".to_string(), 3);
+        contract.set_location_stock("0x1".to_string(), "lisbon".to_string(), 4);
+        assert_eq!(9, get_item(&contract, "0x1").unwrap().stock);
+
+        // the two units counted nowhere go first, then berlin
+        testing_env!(get_context_with_deposit("Bob", 2_000));
+        contract.buy_product("0x1".to_string(), 4);
+        assert_eq!(vec![("berlin".to_string(), 1), ("lisbon".to_string(), 4)], contract.get_location_stock("0x1".to_string()));
+        let counted: u32 = contract.get_location_stock("0x1".to_string()).iter().map(|(_, quantity)| quantity).sum();
+        assert_eq!(get_item(&contract, "0x1").unwrap().stock as u32, counted);
+    }
+
+    #[test]
+    fn shippers_track_fulfilled_orders() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS6".to_string(), 500, 0);
+        contract.set_preorder("0x1".to_string(), Some(1_000));
+        contract.add_role_shipper("Paul".to_string());
+
+        testing_env!(get_context_with_deposit("Bob", 500));
+        let order_id = contract.buy_product("0x1".to_string(), 1);
+
+        testing_env!(get_context_for("Paul"));
+        contract.set_products("0x1".to_string(), "PS6".to_string(), 500, 1);
+        contract.convert_preorders("0x1".to_string(), 10);
+        contract.mark_shipped(order_id, "dhl".to_string(), "9f2c".to_string());
+        contract.mark_delivered(order_id);
+
+        let shipping = contract.get_order(order_id).unwrap().shipping.unwrap();
+        assert_eq!("dhl", shipping.carrier);
+        assert!(shipping.delivered_at.is_some());
+    }
+
+    #[test]
+    #[should_panic(expected = "Only fulfilled orders can be shipped")]
+    fn open_orders_cannot_be_shipped() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS6".to_string(), 500, 0);
+        contract.set_preorder("0x1".to_string(), Some(1_000));
+        contract.add_role_shipper("Paul".to_string());
+
+        testing_env!(get_context_with_deposit("Bob", 500));
+        let order_id = contract.buy_product("0x1".to_string(), 1);
+
+        testing_env!(get_context_for("Paul"));
+        contract.mark_shipped(order_id, "dhl".to_string(), "9f2c".to_string());
+    }
+
+    fn delivered_order(contract: &mut Product) -> u64 {
+        contract.set_products("0x1".to_string(), "PS6".to_string(), 500, 0);
+        contract.set_preorder("0x1".to_string(), Some(1_000));
+        contract.add_role_shipper("Paul".to_string());
+
+        testing_env!(get_context_with_deposit("Bob", 500));
+        let order_id = contract.buy_product("0x1".to_string(), 1);
+
+        testing_env!(get_context_for("Paul"));
+        contract.set_products("0x1".to_string(), "PS6".to_string(), 500, 1);
+        contract.convert_preorders("0x1".to_string(), 10);
+        contract.mark_shipped(order_id, "dhl".to_string(), "9f2c".to_string());
+        contract.mark_delivered(order_id);
+        order_id
+    }
+
+    #[test]
+    fn escrow_is_released_on_buyer_confirmation() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        let order_id = delivered_order(&mut contract);
+        assert!(!contract.get_order(order_id).unwrap().released);
+
+        testing_env!(get_context_for("Bob"));
+        contract.confirm_delivery(order_id);
+        assert!(contract.get_order(order_id).unwrap().released);
+        assert_eq!(500, contract.get_revenue("Paul".to_string()).0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Order is disputed")]
+    fn disputes_block_the_automatic_release() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        let order_id = delivered_order(&mut contract);
+        contract.update_config(ConfigPatch { escrow_release_delay: Some(0), ..Default::default() });
+
+        testing_env!(get_context_for("Bob"));
+        contract.dispute_order(order_id);
+        contract.release_escrow(order_id);
+    }
+
+    #[test]
+    fn partial_refunds_restore_stock() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS6".to_string(), 100, 0);
+        contract.set_preorder("0x1".to_string(), Some(1_000));
+
+        testing_env!(get_context_with_deposit("Bob", 500));
+        let order_id = contract.buy_product("0x1".to_string(), 5);
+
+        testing_env!(get_context_for("Paul"));
+        contract.set_products("0x1".to_string(), "PS6".to_string(), 100, 5);
+        contract.convert_preorders("0x1".to_string(), 10);
+        contract.refund_order_units(order_id, 1);
+
+        let order = contract.get_order(order_id).unwrap();
+        assert_eq!((4, 400), (order.quantity, order.amount));
+        assert_eq!(OrderStatus::Fulfilled, order.status);
+        assert_eq!(1, get_item(&contract, "0x1").unwrap().stock);
+
+        contract.refund_order_units(order_id, 4);
+        assert_eq!(OrderStatus::Refunded, contract.get_order(order_id).unwrap().status);
+        assert_eq!(5, get_item(&contract, "0x1").unwrap().stock);
+    }
+
+    #[test]
+    fn sales_are_split_between_beneficiaries() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 1000, 10);
+        contract.set_payment_split("0x1".to_string(), vec![
+            ("Paul".to_string(), 7000),
+            ("sony_near".to_string(), 2000),
+            ("store_near".to_string(), 1000),
+        ]);
+
+        testing_env!(get_context_with_deposit("Bob", 1000));
+        contract.buy_product("0x1".to_string(), 1);
+        assert_eq!(700, contract.get_revenue("Paul".to_string()).0);
+        assert_eq!(200, contract.get_revenue("sony_near".to_string()).0);
+        assert_eq!(100, contract.get_revenue("store_near".to_string()).0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Split must add up to 10000 basis points")]
+    fn payment_splits_must_cover_the_whole_amount() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 1000, 10);
+        contract.set_payment_split("0x1".to_string(), vec![("Paul".to_string(), 7000)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Product is frozen")]
+    fn frozen_products_keep_their_payment_split() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.add_role_moderator("Paul".to_string());
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 1000, 10);
+        contract.freeze_product("0x1".to_string());
+        contract.set_payment_split("0x1".to_string(), vec![("carol.near".to_string(), 10000)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Product is archived")]
+    fn archived_products_keep_their_payment_split() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 1000, 10);
+        contract.set_lifecycle_state("0x1".to_string(), LifecycleState::Discontinued);
+        contract.set_lifecycle_state("0x1".to_string(), LifecycleState::Archived);
+        contract.set_payment_split("0x1".to_string(), vec![("carol.near".to_string(), 10000)]);
+    }
+
+    #[test]
+    fn tips_are_forwarded_and_recorded() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS6".to_string(), 500, 0);
+        contract.set_preorder("0x1".to_string(), Some(1_000));
+
+        testing_env!(get_context_with_deposit("Bob", 550));
+        let order_id = contract.buy_product_with_tip("0x1".to_string(), 1, near_sdk::json_types::U128(50));
+        assert_eq!(1, transfer_count());
+        let order = contract.get_order(order_id).unwrap();
+        assert_eq!((500, 50), (order.amount, order.tip));
+        assert_eq!(50, contract.get_vendor_tips("Paul".to_string()).0);
+    }
+
+    #[test]
+    fn pay_what_you_want_accepts_more_than_the_minimum() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "Album".to_string(), 100, 0);
+        contract.set_preorder("0x1".to_string(), Some(1_000));
+        contract.set_pay_what_you_want("0x1".to_string(), true);
+
+        testing_env!(get_context_with_deposit("Bob", 250));
+        let order_id = contract.buy_product("0x1".to_string(), 1);
+        assert_eq!(250, contract.get_order(order_id).unwrap().amount);
+        assert_eq!(0, transfer_count());
+    }
+
+    #[test]
+    #[should_panic(expected = "Attached deposit does not cover the price")]
+    fn pay_what_you_want_enforces_the_minimum() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "Album".to_string(), 100, 10);
+        contract.set_pay_what_you_want("0x1".to_string(), true);
+
+        testing_env!(get_context_with_deposit("Bob", 99));
+        contract.buy_product("0x1".to_string(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "Product is archived")]
+    fn archived_products_keep_their_pricing_mode() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "Album".to_string(), 100, 10);
+        contract.set_lifecycle_state("0x1".to_string(), LifecycleState::Discontinued);
+        contract.set_lifecycle_state("0x1".to_string(), LifecycleState::Archived);
+        contract.set_pay_what_you_want("0x1".to_string(), true);
+    }
+
+    #[test]
+    fn flat_fee_is_capped_at_the_sale() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "Sticker".to_string(), 5, 10);
+        contract.update_config(ConfigPatch { flat_fee: Some(10), ..Default::default() });
+
+        // the whole price goes to the treasury, nothing is left for the vendor
+        testing_env!(get_context_with_deposit("Bob", 5));
+        contract.buy_product("0x1".to_string(), 1);
+        assert_eq!(1, transfer_count());
+    }
+
+    #[test]
+    fn exempt_buyers_pay_no_fee() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 1000, 10);
+        contract.update_config(ConfigPatch { fee_bps: Some(500), ..Default::default() });
+        contract.add_fee_exemption("charity_near".to_string());
+
+        testing_env!(get_context_with_deposit("charity_near", 1000));
+        contract.buy_product("0x1".to_string(), 1);
+        assert_eq!(0, transfer_count());
+        assert_eq!(1000, contract.get_revenue("Paul".to_string()).0);
+
+        testing_env!(get_context_with_deposit("Bob", 1000));
+        contract.buy_product("0x1".to_string(), 1);
+        assert_eq!(1, transfer_count());
+        assert_eq!(1950, contract.get_revenue("Paul".to_string()).0);
+    }
+
+    #[test]
+    fn vendors_withdraw_accrued_revenue() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 10);
+
+        testing_env!(get_context_with_deposit("Bob", 1000));
+        contract.buy_product("0x1".to_string(), 2);
+
+        testing_env!(get_context_for("Paul"));
+        contract.withdraw_revenue(near_sdk::json_types::U128(600));
+        assert_eq!(400, contract.get_revenue("Paul".to_string()).0);
+        // the transfer and its callback
+        assert_eq!(1, transfer_count());
+        assert_eq!(2, near_sdk::test_utils::get_created_receipts().len());
+    }
+
+    #[test]
+    #[should_panic(expected = "Not enough revenue to withdraw")]
+    fn revenue_cannot_be_overdrawn() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.withdraw_revenue(near_sdk::json_types::U128(1));
+    }
+
+    #[test]
+    fn opted_in_vendors_are_paid_out_above_the_threshold() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 10);
+        contract.set_auto_payout(Some(near_sdk::json_types::U128(1000)));
+
+        testing_env!(get_context_with_deposit("Bob", 500));
+        contract.buy_product("0x1".to_string(), 1);
+        assert_eq!(0, contract.process_payouts(10));
+
+        testing_env!(get_context_with_deposit("Bob", 500));
+        contract.buy_product("0x1".to_string(), 1);
+        assert_eq!(1, contract.process_payouts(10));
+        assert_eq!(0, contract.get_revenue("Paul".to_string()).0);
+        let history = contract.get_payout_history("Paul".to_string());
+        assert_eq!(1, history.len());
+        assert!(history[0].automatic && history[0].amount == 1000);
+    }
+
+    #[test]
+    fn failed_transfers_become_claimable() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        near_sdk::test_utils::testing_env_with_promise_results(get_context_for("Paul"), near_sdk::PromiseResult::Failed);
+        contract.on_transfer_settled("Bob".to_string(), near_sdk::json_types::U128(300));
+        assert_eq!(300, contract.get_claimable("Bob".to_string()).0);
+
+        testing_env!(get_context_for("Bob"));
+        contract.claim();
+        assert_eq!(0, contract.get_claimable("Bob".to_string()).0);
+        assert_eq!(1, transfer_count());
+    }
+
+    #[test]
+    #[should_panic(expected = "Nothing to claim")]
+    fn claim_needs_a_failed_transfer() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.claim();
+    }
+
+    #[test]
+    fn opted_in_vendors_are_paid_in_wnear() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 10);
+        contract.update_config(ConfigPatch { wnear_contract: Some(Some("wrap.near".to_string())), ..Default::default() });
+        contract.set_wnear_payouts(true);
+
+        testing_env!(get_context_with_deposit("Bob", 500));
+        contract.buy_product("0x1".to_string(), 1);
+
+        testing_env!(get_context_for("Paul"));
+        contract.withdraw_revenue(near_sdk::json_types::U128(500));
+        // near_deposit and its callback, no plain transfer
+        assert_eq!(0, transfer_count());
+        assert_eq!(2, near_sdk::test_utils::get_created_receipts().len());
+    }
+
+    #[test]
+    fn null_clears_the_wnear_contract() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.update_config(ConfigPatch { wnear_contract: Some(Some("wrap.near".to_string())), ..Default::default() });
+        contract.update_config(near_sdk::serde_json::from_str(r#"{"fee_bps": 100}"#).unwrap());
+        assert_eq!(Some("wrap.near".to_string()), contract.get_config().wnear_contract);
+
+        contract.update_config(near_sdk::serde_json::from_str(r#"{"wnear_contract": null}"#).unwrap());
+        assert_eq!(None, contract.get_config().wnear_contract);
+    }
+
+    #[test]
+    fn usd_prices_convert_at_the_cached_rate() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 0, 10);
+        contract.add_role_keeper("Paul".to_string());
+        contract.update_usd_rate(near_sdk::json_types::U128(2_000));
+        contract.set_usd_price("0x1".to_string(), Some(550));
+        assert_eq!(11_000, get_item(&contract, "0x1").unwrap().price);
+
+        testing_env!(get_context_with_deposit("Bob", 11_000));
+        contract.buy_product("0x1".to_string(), 1);
+        assert_eq!(9, get_item(&contract, "0x1").unwrap().stock);
+    }
+
+    #[test]
+    #[should_panic(expected = "Exchange rate is stale")]
+    fn stale_rates_block_usd_priced_sales() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 0, 10);
+        contract.add_role_keeper("Paul".to_string());
+        contract.update_usd_rate(near_sdk::json_types::U128(2_000));
+        contract.set_usd_price("0x1".to_string(), Some(550));
+
+        let mut context = get_context_with_deposit("Bob", 11_000);
+        context.block_timestamp = contract.get_config().max_rate_staleness + 1;
+        testing_env!(context);
+        contract.buy_product("0x1".to_string(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "Product is frozen")]
+    fn frozen_products_keep_their_usd_price() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.add_role_moderator("Paul".to_string());
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 0, 10);
+        contract.freeze_product("0x1".to_string());
+        contract.set_usd_price("0x1".to_string(), Some(550));
+    }
+
+    #[test]
+    fn product_detail_shows_display_prices() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        let one_near = 10u128.pow(24);
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 2 * one_near, 10);
+        contract.add_role_keeper("Paul".to_string());
+        contract.update_display_rate("USDC".to_string(), near_sdk::json_types::U128(5_000_000));
+
+        let prices = contract.get_product_detail("0x1".to_string()).unwrap().display_prices;
+        assert_eq!(vec!["NEAR".to_string(), "USDC".to_string()], prices.iter().map(|(symbol, _)| symbol.clone()).collect::<Vec<String>>());
+        assert_eq!(10_000_000, prices[1].1.0);
+    }
+
+    #[test]
+    fn wnear_transfers_buy_through_the_same_pipeline() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 10);
+        contract.update_config(ConfigPatch { wnear_contract: Some(Some("wrap.near".to_string())), ..Default::default() });
+        contract.mint_affiliate_code("CAROL10".to_string(), "carol.near".to_string(), 1_000);
+
+        let mut context = get_context_for("Bob");
+        context.predecessor_account_id = "wrap.near".to_string();
+        testing_env!(context);
+        let msg = r#"{"address":"0x1","quantity":1,"referrer":"CAROL10"}"#.to_string();
+        contract.ft_on_transfer("Bob".to_string(), near_sdk::json_types::U128(800), msg);
+        // nothing settles before the unwrap is back
+        assert_eq!(10, get_item(&contract, "0x1").unwrap().stock);
+        assert_eq!(0, contract.get_revenue("Paul".to_string()).0);
+
+        near_sdk::test_utils::testing_env_with_promise_results(get_context_for("Paul"), near_sdk::PromiseResult::Successful(vec![]));
+        let unused = contract.on_ft_payment_unwrapped("Bob".to_string(), PurchaseIntent { address: "0x1".to_string(), quantity: 1, coupon: None, referrer: Some("CAROL10".to_string()) }, near_sdk::json_types::U128(800), near_sdk::json_types::U128(500));
+        assert_eq!(300, unused.0);
+        assert_eq!(9, get_item(&contract, "0x1").unwrap().stock);
+        assert_eq!(450, contract.get_revenue("Paul".to_string()).0);
+        assert_eq!(50, contract.get_revenue("carol.near".to_string()).0);
+    }
+
+    #[test]
+    fn failed_wnear_unwraps_return_every_token() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 10);
+
+        near_sdk::test_utils::testing_env_with_promise_results(get_context_for("Paul"), near_sdk::PromiseResult::Failed);
+        let unused = contract.on_ft_payment_unwrapped("Bob".to_string(), PurchaseIntent { address: "0x1".to_string(), quantity: 1, coupon: None, referrer: None }, near_sdk::json_types::U128(800), near_sdk::json_types::U128(500));
+        assert_eq!(800, unused.0);
+        assert_eq!(10, get_item(&contract, "0x1").unwrap().stock);
+        assert_eq!(0, contract.get_revenue("Paul".to_string()).0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Token is not accepted")]
+    fn other_tokens_are_refused() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 10);
+        let msg = r#"{"address":"0x1","quantity":1}"#.to_string();
+        contract.ft_on_transfer("Bob".to_string(), near_sdk::json_types::U128(800), msg);
+    }
+
+    #[test]
+    fn nft_holders_buy_at_a_discount() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 10);
+        contract.update_config(ConfigPatch { holder_nft_contract: Some(Some("club.near".to_string())), holder_discount_bps: Some(2_000), ..Default::default() });
+
+        let token = br#"{"token_id":"7","owner_id":"Bob","metadata":null}"#.to_vec();
+        near_sdk::test_utils::testing_env_with_promise_results(get_context_for("Paul"), near_sdk::PromiseResult::Successful(token));
+        contract.on_holder_checked("Bob".to_string(), "0x1".to_string(), 1, near_sdk::json_types::U128(500));
+        assert_eq!(400, contract.get_revenue("Paul".to_string()).0);
+        assert_eq!(1, transfer_count());
+
+        let token = br#"{"token_id":"7","owner_id":"Carol","metadata":null}"#.to_vec();
+        near_sdk::test_utils::testing_env_with_promise_results(get_context_for("Paul"), near_sdk::PromiseResult::Successful(token));
+        contract.on_holder_checked("Bob".to_string(), "0x1".to_string(), 1, near_sdk::json_types::U128(500));
+        assert_eq!(900, contract.get_revenue("Paul".to_string()).0);
+        assert_eq!(8, get_item(&contract, "0x1").unwrap().stock);
+    }
+
+    #[test]
+    #[should_panic(expected = "No holder discount is configured")]
+    fn null_clears_the_holder_nft_contract() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 10);
+        contract.update_config(ConfigPatch { holder_nft_contract: Some(Some("club.near".to_string())), holder_discount_bps: Some(2_000), ..Default::default() });
+        contract.update_config(near_sdk::serde_json::from_str(r#"{"holder_nft_contract": null}"#).unwrap());
+        assert_eq!(None, contract.get_config().holder_nft_contract);
+
+        testing_env!(get_context_with_deposit("Bob", 500));
+        contract.buy_product_as_holder("0x1".to_string(), 1, "7".to_string());
+    }
+
+    #[test]
+    fn token_gated_products_need_the_minimum_balance() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 10);
+        contract.set_token_gate("0x1".to_string(), Some(TokenGate { token: "members.near".to_string(), min_balance: 100 }));
+
+        near_sdk::test_utils::testing_env_with_promise_results(get_context_for("Paul"), near_sdk::PromiseResult::Successful(br#""99""#.to_vec()));
+        contract.on_gate_checked("Bob".to_string(), "0x1".to_string(), 1, near_sdk::json_types::U128(500));
+        assert_eq!(10, get_item(&contract, "0x1").unwrap().stock);
+        assert_eq!(1, transfer_count());
+
+        near_sdk::test_utils::testing_env_with_promise_results(get_context_for("Paul"), near_sdk::PromiseResult::Successful(br#""100""#.to_vec()));
+        contract.on_gate_checked("Bob".to_string(), "0x1".to_string(), 1, near_sdk::json_types::U128(500));
+        assert_eq!(9, get_item(&contract, "0x1").unwrap().stock);
+        assert_eq!(500, contract.get_revenue("Paul".to_string()).0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Product is token gated")]
+    fn token_gated_products_refuse_plain_purchases() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 10);
+        contract.set_token_gate("0x1".to_string(), Some(TokenGate { token: "members.near".to_string(), min_balance: 100 }));
+
+        testing_env!(get_context_with_deposit("Bob", 500));
+        contract.buy_product("0x1".to_string(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "Product is frozen")]
+    fn frozen_products_keep_their_token_gate() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.add_role_moderator("Paul".to_string());
+        contract.set_products("0x1".to_string(), "Members tee".to_string(), 500, 10);
+        contract.set_token_gate("0x1".to_string(), Some(TokenGate { token: "members.near".to_string(), min_balance: 100 }));
+        contract.freeze_product("0x1".to_string());
+        contract.set_token_gate("0x1".to_string(), None);
+    }
+
+    #[test]
+    fn staked_buyers_get_their_tier_discount() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 10);
+        contract.set_stake_tiers(vec![StakeTier { min_stake: 5_000, discount_bps: 2_000 }, StakeTier { min_stake: 1_000, discount_bps: 1_000 }]);
+
+        testing_env!(get_context_with_deposit("Bob", 1_000));
+        contract.stake_for_tier();
+        assert_eq!(1_000, contract.get_tier_discount("Bob".to_string()));
+        testing_env!(get_context_with_deposit("Bob", 450));
+        contract.buy_product("0x1".to_string(), 1);
+        assert_eq!(450, contract.get_revenue("Paul".to_string()).0);
+
+        testing_env!(get_context_for("Bob"));
+        contract.unstake(near_sdk::json_types::U128(1_000));
+        assert_eq!(0, contract.get_tier_discount("Bob".to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Stake is still cooling down")]
+    fn unstaked_near_waits_out_the_cooldown() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        testing_env!(get_context_with_deposit("Bob", 1_000));
+        contract.stake_for_tier();
+        testing_env!(get_context_for("Bob"));
+        contract.unstake(near_sdk::json_types::U128(400));
+        contract.withdraw_stake();
+    }
+
+    #[test]
+    fn affiliate_codes_earn_a_commission() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 10);
+        contract.mint_affiliate_code("CAROL10".to_string(), "carol.near".to_string(), 1_000);
+
+        testing_env!(get_context_with_deposit("Bob", 500));
+        contract.buy_product_with_code("0x1".to_string(), 1, "CAROL10".to_string());
+        assert_eq!(50, contract.get_revenue("carol.near".to_string()).0);
+        assert_eq!(450, contract.get_revenue("Paul".to_string()).0);
+        let stats = contract.get_affiliate_stats("CAROL10".to_string());
+        assert_eq!((1, 500, 50), (stats.sales, stats.volume, stats.commission));
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot use your own affiliate code")]
+    fn affiliates_cannot_refer_themselves() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 10);
+        contract.mint_affiliate_code("CAROL10".to_string(), "carol.near".to_string(), 1_000);
+
+        testing_env!(get_context_with_deposit("carol.near", 500));
+        contract.buy_product_with_code("0x1".to_string(), 1, "CAROL10".to_string());
+    }
+
+    #[test]
+    fn campaigns_stop_discounting_once_the_budget_is_spent() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 10);
+        let campaign_id = contract.create_campaign(vec!["0x1".to_string()], 2_000, near_sdk::json_types::U128(150), 0, 1_000);
+
+        testing_env!(get_context_with_deposit("Bob", 500));
+        contract.buy_product("0x1".to_string(), 1);
+        testing_env!(get_context_with_deposit("Bob", 500));
+        contract.buy_product("0x1".to_string(), 1);
+        testing_env!(get_context_with_deposit("Bob", 500));
+        contract.buy_product("0x1".to_string(), 1);
+        // 400, then 450 on the last 50 of the budget, then the full price
+        assert_eq!(1_350, contract.get_revenue("Paul".to_string()).0);
+        assert_eq!(150, contract.get_campaign(campaign_id).unwrap().spent);
+    }
+
+    #[test]
+    fn price_experiments_charge_the_buyers_bucket() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 10);
+        contract.set_price_experiment("0x1".to_string(), vec![near_sdk::json_types::U128(400), near_sdk::json_types::U128(600)]);
+
+        let bucket = contract.get_price_bucket("0x1".to_string(), "Bob".to_string()).unwrap();
+        assert_eq!(Some(bucket), contract.get_price_bucket("0x1".to_string(), "Bob".to_string()));
+        let price = [400, 600][bucket as usize];
+        testing_env!(get_context_with_deposit("Bob", 600));
+        contract.buy_product("0x1".to_string(), 1);
+        assert_eq!(price, contract.get_revenue("Paul".to_string()).0);
+        let results = &contract.get_price_experiment("0x1".to_string()).unwrap().buckets[bucket as usize];
+        assert_eq!((1, price), (results.units, results.revenue));
+        // the listed price is untouched
+        assert_eq!(500, get_item(&contract, "0x1").unwrap().price);
+    }
+
+    #[test]
+    #[should_panic(expected = "Product is archived")]
+    fn archived_products_cannot_start_price_experiments() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 10);
+        contract.set_lifecycle_state("0x1".to_string(), LifecycleState::Discontinued);
+        contract.set_lifecycle_state("0x1".to_string(), LifecycleState::Archived);
+        contract.set_price_experiment("0x1".to_string(), vec![near_sdk::json_types::U128(400), near_sdk::json_types::U128(600)]);
+    }
+
+    #[test]
+    fn commercial_actions_emit_nep297_events() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 10);
+
+        testing_env!(get_context_with_deposit("Bob", 1_000));
+        contract.buy_product("0x1".to_string(), 2);
+        let events: Vec<String> = near_sdk::test_utils::get_logs().into_iter().filter(|log| log.starts_with("EVENT_JSON:")).collect();
+        assert_eq!(2, events.len());
+        assert!(events[1].contains(r#""standard":"product_store","version":"1.0.0","event":"stock_change""#));
+        assert!(events[1].contains(r#""old_stock":10,"new_stock":8,"reason":"Sale""#));
+        assert!(events[0].contains(r#""event":"purchase","data":[{"channel":"direct","address":"0x1","buyer":"Bob","quantity":2,"amount":"1000","order_id":0}]"#));
+    }
+
+    #[test]
+    fn sync_head_points_at_the_last_mutation() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+
+        let mut context = get_context_for("Paul");
+        context.block_index = 42;
+        testing_env!(context);
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+        let head = contract.get_sync_head();
+
+        let mut context = get_context_for("Paul");
+        context.block_index = 50;
+        testing_env!(context);
+        contract.get_products_list(None, 10);
+        assert_eq!(42, contract.get_sync_head().block_height);
+        assert_eq!(head.state_nonce, contract.get_sync_head().state_nonce);
+    }
+
+    #[test]
+    fn deleted_products_leave_a_tombstone() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        // no trash, so the key is free again right away
+        contract.update_config(ConfigPatch { trash_grace_period: Some(0), ..Default::default() });
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+        contract.delete_products("0x1".to_string());
+
+        match contract.get_products("0x1".to_string(), None) {
+            ProductLookup::Deleted { deleted_by, .. } => assert_eq!("Paul", deleted_by),
+            other => panic!("expected Deleted, got {:?}", other),
+        }
+        assert!(matches!(contract.get_products("0x2".to_string(), None), ProductLookup::NotFound));
+
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+        assert!(matches!(contract.get_products("0x1".to_string(), None), ProductLookup::Found(_)));
+    }
+
+    #[test]
+    fn trashed_products_restore_intact() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+        contract.set_payment_split("0x1".to_string(), vec![("Paul".to_string(), 10_000)]);
+        contract.delete_products("0x1".to_string());
+        assert!(matches!(contract.get_products("0x1".to_string(), None), ProductLookup::Deleted { .. }));
+
+        contract.restore_product("0x1".to_string());
+        let item = get_item(&contract, "0x1").unwrap();
+        assert_eq!((12, 500), (item.stock, item.price));
+        assert_eq!(1, contract.get_payment_split("0x1".to_string()).len());
+        assert!(contract.get_trashed_product("0x1".to_string()).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "Grace period has passed")]
+    fn trash_cannot_be_restored_after_the_grace_period() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.update_config(ConfigPatch { trash_grace_period: Some(1_000), ..Default::default() });
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+        contract.delete_products("0x1".to_string());
+
+        let mut context = get_context_for("Paul");
+        context.block_timestamp = 1_001;
+        testing_env!(context);
+        contract.restore_product("0x1".to_string());
+    }
+
+    #[test]
+    fn trash_bin_lists_deleted_products() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+        contract.set_products("0x2".to_string(), "XBOX".to_string(), 400, 5);
+        contract.delete_products("0x1".to_string());
+        contract.delete_products("0x2".to_string());
+
+        let deleted = contract.get_deleted_products(None, 10);
+        assert_eq!(vec!["0x1".to_string(), "0x2".to_string()], deleted.items.iter().map(|(address, _)| address.clone()).collect::<Vec<_>>());
+        assert_eq!("Paul", deleted.items[0].1.deleted_by);
+        assert!(deleted.cursor.is_none());
+        let first = contract.get_deleted_products(None, 1);
+        assert_eq!("0x1", first.items[0].0);
+        let rest = contract.get_deleted_products(first.cursor, 10);
+        assert_eq!(vec!["0x2".to_string()], rest.items.iter().map(|(address, _)| address.clone()).collect::<Vec<_>>());
+
+        contract.restore_product("0x1".to_string());
+        assert_eq!(1, contract.get_deleted_products_count());
+    }
+
+    #[test]
+    fn purged_products_are_gone_for_good() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+        contract.delete_products("0x1".to_string());
+        contract.add_role_purge("Paul".to_string());
+
+        contract.purge_product("0x1".to_string());
+        assert_eq!(0, contract.get_deleted_products_count());
+        assert!(matches!(contract.get_products("0x1".to_string(), None), ProductLookup::Deleted { .. }));
+        // the key is free again
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+    }
+
+    #[test]
+    #[should_panic(expected = "401")]
+    fn purging_needs_the_purge_role() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+        contract.delete_products("0x1".to_string());
+        contract.purge_product("0x1".to_string());
+    }
+
+    #[test]
+    fn permissions_match_the_guards() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        let permissions = contract.get_permissions();
+        assert_eq!(ALL_ROLES.len(), permissions.len());
+        assert!(permissions[0].1.contains(&"set_products".to_string()));
+        assert!(contract.can_call("set_products".to_string(), "Paul".to_string()));
+        assert!(!contract.can_call("mark_shipped".to_string(), "Paul".to_string()));
+
+        contract.add_role_shipper("Paul".to_string());
+        assert!(contract.can_call("mark_shipped".to_string(), "Paul".to_string()));
+    }
+
+    #[test]
+    fn staff_can_renounce_their_own_role() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.add_role_set_product("Bob".to_string());
+
+        testing_env!(get_context_for("Bob"));
+        contract.renounce_role(ROLE_SET_PRODUCT.to_string());
+        assert!(!contract.can_call("set_products".to_string(), "Bob".to_string()));
+        assert!(near_sdk::test_utils::get_logs().iter().any(|log| log.contains(r#""event":"role_revoked","data":[{"role":"ROLE_SET_PRODUCT","account":"Bob","changed_by":"Bob"}]"#)));
+        assert!(!near_sdk::test_utils::get_logs().iter().any(|log| log.starts_with("role_revoked")));
+        // the owner's grant is untouched
+        assert!(contract.can_call("set_products".to_string(), "Paul".to_string()));
+    }
+
+    #[test]
+    fn role_grants_lapse_until_renewed() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.update_config(ConfigPatch { role_term: Some(1_000), ..Default::default() });
+        contract.add_role_set_product("Bob".to_string());
+        assert_eq!(vec![(ROLE_SET_PRODUCT.to_string(), "Bob".to_string(), 1_000)], contract.get_expiring_roles(2_000));
+        assert!(contract.get_expiring_roles(500).is_empty());
+
+        let mut context = get_context_for("Paul");
+        context.block_timestamp = 1_000;
+        testing_env!(context);
+        assert!(!contract.can_call("set_products".to_string(), "Bob".to_string()));
+        assert_eq!(2_000, contract.renew_role(ROLE_SET_PRODUCT.to_string(), "Bob".to_string()));
+        assert!(contract.can_call("set_products".to_string(), "Bob".to_string()));
+    }
+
+    #[test]
+    fn role_managers_only_manage_their_role() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.add_role_manager(ROLE_SET_PRODUCT.to_string(), "Hr".to_string());
+
+        testing_env!(get_context_for("Hr"));
+        contract.grant_role_to(ROLE_SET_PRODUCT.to_string(), "Bob".to_string());
+        assert!(contract.can_call("set_products".to_string(), "Bob".to_string()));
+        contract.revoke_role_from(ROLE_SET_PRODUCT.to_string(), "Bob".to_string());
+        assert!(!contract.can_call("set_products".to_string(), "Bob".to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "401")]
+    fn role_managers_cannot_grant_other_roles() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.add_role_manager(ROLE_SET_PRODUCT.to_string(), "Hr".to_string());
+
+        testing_env!(get_context_for("Hr"));
+        contract.grant_role_to(ROLE_DELETE_PRODUCT.to_string(), "Bob".to_string());
+    }
+
+    #[test]
+    fn roles_are_granted_and_revoked_in_batches() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.grant_roles(vec![
+            (ROLE_SET_PRODUCT.to_string(), "Bob".to_string()),
+            (ROLE_SHIPPER.to_string(), "Bob".to_string()),
+            (ROLE_SET_PRODUCT.to_string(), "Carol".to_string()),
+        ]);
+        assert!(contract.can_call("mark_shipped".to_string(), "Bob".to_string()));
+        assert!(contract.can_call("set_products".to_string(), "Carol".to_string()));
+
+        contract.revoke_roles(vec![(ROLE_SHIPPER.to_string(), "Bob".to_string()), (ROLE_SHIPPER.to_string(), "Carol".to_string())]);
+        assert!(!contract.can_call("mark_shipped".to_string(), "Bob".to_string()));
+        assert!(contract.can_call("set_products".to_string(), "Bob".to_string()));
+    }
+
+    #[test]
+    fn role_changes_are_recorded() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.add_role_shipper("Bob".to_string());
+        contract.revoke_role_from(ROLE_SHIPPER.to_string(), "Bob".to_string());
+
+        let history = contract.get_role_history(ROLE_SHIPPER.to_string(), 0, 10);
+        assert_eq!(vec![true, false], history.iter().map(|change| change.granted).collect::<Vec<_>>());
+        assert_eq!(("Bob".to_string(), "Paul".to_string()), (history[1].account.clone(), history[1].changed_by.clone()));
+        assert_eq!(1, contract.get_role_history(ROLE_SHIPPER.to_string(), 1, 10).len());
+        // the grants made by new() are on record too
+        assert_eq!(1, contract.get_role_history(ROLE_SET_PRODUCT.to_string(), 0, 10).len());
+    }
+
+    #[test]
+    fn role_changes_emit_events() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.add_role_keeper("Bob".to_string());
+        contract.revoke_role_from(ROLE_KEEPER.to_string(), "Bob".to_string());
+
+        let events: Vec<String> = near_sdk::test_utils::get_logs().into_iter().filter(|log| log.starts_with("EVENT_JSON:")).collect();
+        assert!(events.iter().any(|log| log.contains(r#""event":"role_granted","data":[{"role":"ROLE_KEEPER","account":"Bob","changed_by":"Paul"}]"#)));
+        assert!(events.iter().any(|log| log.contains(r#""event":"role_revoked","data":[{"role":"ROLE_KEEPER","account":"Bob","changed_by":"Paul"}]"#)));
+    }
+
+    #[test]
+    fn guardian_can_pause_and_owner_unpauses() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.add_role_guardian("bot.near".to_string());
+        assert!(contract.can_call("pause".to_string(), "bot.near".to_string()));
+        assert!(!contract.can_call("set_products".to_string(), "bot.near".to_string()));
+
+        testing_env!(get_context_for("bot.near"));
+        contract.pause();
+        assert!(contract.is_paused());
+
+        testing_env!(get_context(vec![], false));
+        contract.unpause();
+        contract.set_products(String::from("a1"), String::from("Milk"), 20, 5);
+        assert!(!contract.is_paused());
+    }
+
+    #[test]
+    #[should_panic(expected = "Contract is paused")]
+    fn paused_contract_refuses_product_writes() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.pause();
+        contract.set_products(String::from("a1"), String::from("Milk"), 20, 5);
+    }
+
+    #[test]
+    #[should_panic(expected = "Contract is paused")]
+    fn paused_contract_refuses_purchases() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products(String::from("a1"), String::from("Milk"), 10, 5);
+        contract.pause();
+        testing_env!(get_context_with_deposit("bob.near", 10));
+        contract.buy_product(String::from("a1"), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "Contract is paused")]
+    fn paused_contract_refuses_payouts() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.pause();
+        testing_env!(get_context_for("Cron"));
+        contract.process_payouts(10);
+    }
+
+    #[test]
+    #[should_panic(expected = "Contract is paused")]
+    fn paused_contract_holds_subscription_charges() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "Magazine".to_string(), 0, 1);
+        let plan_id = contract.create_subscription_plan("0x1".to_string(), 100, 300, None);
+        testing_env!(get_context_with_deposit("Bob", 700));
+        contract.subscribe(plan_id);
+
+        testing_env!(get_context(vec![], false));
+        contract.pause();
+        testing_env!(get_context_for("Cron"));
+        contract.charge_subscriptions(10);
+    }
+
+    #[test]
+    fn paused_contract_still_lets_subscribers_cancel() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "Magazine".to_string(), 0, 1);
+        let plan_id = contract.create_subscription_plan("0x1".to_string(), 100, 300, None);
+        testing_env!(get_context_with_deposit("Bob", 700));
+        let subscription_id = contract.subscribe(plan_id);
+
+        testing_env!(get_context(vec![], false));
+        contract.pause();
+        testing_env!(get_context_for("Bob"));
+        contract.cancel_subscription(subscription_id);
+        assert_eq!(1, transfer_count());
+        assert_eq!(0, contract.get_subscription(subscription_id).unwrap().balance);
+    }
+
+    #[test]
+    #[should_panic(expected = "left == right")]
+    fn guardian_cannot_unpause() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.add_role_guardian("bot.near".to_string());
+        testing_env!(get_context_for("bot.near"));
+        contract.pause();
+        contract.unpause();
+    }
+
+    #[test]
+    fn breaker_trips_after_too_many_deletes_and_owner_resets_it() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.update_config(ConfigPatch { max_deletes_per_window: Some(2), ..Default::default() });
+        for address in ["a1", "a2", "a3", "a4"].iter() {
+            contract.set_products(address.to_string(), String::from("Milk"), 10, 5);
+        }
+        contract.delete_products(String::from("a1"));
+        contract.delete_products(String::from("a2"));
+        assert!(contract.get_circuit_breaker().tripped_at.is_none());
+        contract.delete_products(String::from("a3"));
+        assert_eq!(Some(0), contract.get_circuit_breaker().tripped_at);
+
+        contract.reset_circuit_breaker();
+        assert_eq!("reset_circuit_breaker", contract.get_audit_log(0, 100).last().unwrap().action);
+        contract.delete_products(String::from("a4"));
+        assert_eq!(1, contract.get_circuit_breaker().deletes);
+    }
+
+    #[test]
+    fn breaker_is_off_by_default() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        for i in 0..60 {
+            testing_env!(get_context_for("Paul"));
+            contract.set_products(format!("a{}", i), String::from("Milk"), 10, 5);
+            contract.delete_products(format!("a{}", i));
+        }
+        assert!(contract.get_circuit_breaker().tripped_at.is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "Circuit breaker is tripped")]
+    fn tripped_breaker_refuses_price_changes() {
+        let mut context = get_context(vec![], false);
+        testing_env!(context.clone());
+        let mut contract = Product::new();
+        contract.update_config(ConfigPatch { max_price_changes_per_window: Some(1), ..Default::default() });
+        contract.set_products(String::from("a1"), String::from("Milk"), 10, 5);
+        // same price, only the stock moves
+        contract.set_products(String::from("a1"), String::from("Milk"), 10, 7);
+        contract.set_products(String::from("a1"), String::from("Milk"), 11, 7);
+        context.block_index = 100;
+        testing_env!(context.clone());
+        contract.set_products(String::from("a1"), String::from("Milk"), 12, 7);
+        contract.set_products(String::from("a1"), String::from("Milk"), 13, 7);
+        assert!(contract.get_circuit_breaker().tripped_at.is_some());
+        contract.set_products(String::from("a1"), String::from("Milk"), 14, 7);
+    }
+
+    #[test]
+    fn refused_holder_purchases_are_refunded() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 10);
+        contract.update_config(ConfigPatch { holder_nft_contract: Some(Some("club.near".to_string())), holder_discount_bps: Some(2_000), ..Default::default() });
+
+        // attached the holder price but does not hold the token
+        let token = br#"{"token_id":"7","owner_id":"carol.near","metadata":null}"#.to_vec();
+        near_sdk::test_utils::testing_env_with_promise_results(get_context_for("Paul"), near_sdk::PromiseResult::Successful(token));
+        assert_eq!(None, contract.on_holder_checked("Bob".to_string(), "0x1".to_string(), 1, near_sdk::json_types::U128(400)));
+        assert_eq!(1, transfer_count());
+        assert_eq!(10, get_item(&contract, "0x1").unwrap().stock);
+        assert_eq!(0, contract.get_revenue("Paul".to_string()).0);
+        assert!(near_sdk::test_utils::get_logs().iter().any(|log| log.starts_with("purchase_refused '0x1 Bob Attached deposit does not cover the price'")));
+    }
+
+    #[test]
+    fn gated_purchases_refused_in_the_callback_are_refunded() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 1);
+        contract.set_token_gate("0x1".to_string(), Some(TokenGate { token: "members.near".to_string(), min_balance: 100 }));
+
+        // two buyers passed the up-front check, the first one takes the last unit
+        near_sdk::test_utils::testing_env_with_promise_results(get_context_for("Paul"), near_sdk::PromiseResult::Successful(br#""100""#.to_vec()));
+        contract.on_gate_checked("Bob".to_string(), "0x1".to_string(), 1, near_sdk::json_types::U128(500));
+        near_sdk::test_utils::testing_env_with_promise_results(get_context_for("Paul"), near_sdk::PromiseResult::Successful(br#""100""#.to_vec()));
+        assert_eq!(None, contract.on_gate_checked("carol.near".to_string(), "0x1".to_string(), 1, near_sdk::json_types::U128(500)));
+        assert_eq!(1, transfer_count());
+        assert_eq!(500, contract.get_revenue("Paul".to_string()).0);
+        assert!(near_sdk::test_utils::get_logs().iter().any(|log| log.starts_with("purchase_refused '0x1 carol.near Not enough stock'")));
+    }
+
+    #[test]
+    fn failed_wnear_payouts_are_unwrapped_before_the_revenue_is_credited_back() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+
+        // ft_transfer failed, the tokens are unwrapped and nothing is credited yet
+        near_sdk::test_utils::testing_env_with_promise_results(get_context_for("Paul"), near_sdk::PromiseResult::Failed);
+        contract.on_wnear_sent("wrap.near".to_string(), "Bob".to_string(), near_sdk::json_types::U128(500));
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        assert_eq!(2, receipts.len());
+        assert!(format!("{:?}", receipts[0]).contains(&format!("{:?}", b"near_withdraw".to_vec())));
+        assert_eq!(0, contract.get_revenue("Bob".to_string()).0);
+
+        near_sdk::test_utils::testing_env_with_promise_results(get_context_for("Paul"), near_sdk::PromiseResult::Successful(vec![]));
+        contract.on_wnear_unwrapped("Bob".to_string(), near_sdk::json_types::U128(500));
+        assert_eq!(500, contract.get_revenue("Bob".to_string()).0);
+
+        // still wrapped, the NEAR ledger is not credited
+        near_sdk::test_utils::testing_env_with_promise_results(get_context_for("Paul"), near_sdk::PromiseResult::Failed);
+        contract.on_wnear_unwrapped("Bob".to_string(), near_sdk::json_types::U128(500));
+        assert_eq!(500, contract.get_revenue("Bob".to_string()).0);
+
+        near_sdk::test_utils::testing_env_with_promise_results(get_context_for("Paul"), near_sdk::PromiseResult::Failed);
+        contract.on_wnear_wrapped("wrap.near".to_string(), "Bob".to_string(), near_sdk::json_types::U128(100));
+        assert_eq!(600, contract.get_revenue("Bob".to_string()).0);
+        assert!(near_sdk::test_utils::get_created_receipts().is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "401")]
+    fn revoked_vendors_cannot_publish_revisions() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.add_role_set_product("bob.near".to_string());
+        testing_env!(get_context_for("bob.near"));
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 10);
+        contract.stage_revision("0x1".to_string(), "PS5 Slim".to_string(), 450);
+
+        testing_env!(get_context_for("Paul"));
+        contract.revoke_role_from(ROLE_SET_PRODUCT.to_string(), "bob.near".to_string());
+        testing_env!(get_context_for("bob.near"));
+        contract.publish_revision("0x1".to_string());
+    }
+
+    #[test]
+    fn batches_notify_listeners_once() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.add_listener("indexer.near".to_string());
+        let entries = (0..10).map(|i| ProductEntry { address: format!("0x{}", i), name: "PS5".to_string(), price: 500, stock: 10 }).collect();
+        contract.set_products_batch(entries);
+        assert_eq!(1, near_sdk::test_utils::get_created_receipts().iter().filter(|receipt| format!("{:?}", receipt).contains(r#"receiver_id: "indexer.near""#)).count());
+
+        testing_env!(get_context_for("Paul"));
+        contract.adjust_prices((0..10).map(|i| format!("0x{}", i)).collect(), -1_000);
+        assert_eq!(1, near_sdk::test_utils::get_created_receipts().iter().filter(|receipt| format!("{:?}", receipt).contains(r#"receiver_id: "indexer.near""#)).count());
+    }
+
+    #[test]
+    fn batches_log_one_stock_event_and_one_price_audit() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        let entries = (0..3).map(|i| ProductEntry { address: format!("0x{}", i), name: "PS5".to_string(), price: 500, stock: 10 }).collect();
+        contract.set_products_batch(entries);
+        let stock_events = near_sdk::test_utils::get_logs().iter().filter(|log| log.contains(r#""event":"stock_change""#)).count();
+        assert_eq!(1, stock_events);
+
+        testing_env!(get_context_for("Paul"));
+        let audits = contract.get_audit_log(0, 100).len();
+        contract.set_lifecycle_state("0x2".to_string(), LifecycleState::Discontinued);
+        contract.set_lifecycle_state("0x2".to_string(), LifecycleState::Archived);
+        let summary = contract.adjust_prices(vec!["0x0".to_string(), "0x1".to_string(), "0x2".to_string()], -1_000);
+        assert_eq!(vec!["0x0".to_string(), "0x1".to_string()], summary.updated);
+        assert_eq!(vec![("0x2".to_string(), "Product is archived".to_string())], summary.failed);
+        assert_eq!(450, get_item(&contract, "0x1").unwrap().price);
+        let log = contract.get_audit_log(0, 100);
+        // one line for both prices
+        assert_eq!(audits + 1, log.len());
+        assert_eq!("adjust_prices prices", log.last().unwrap().action);
+    }
+
+    #[test]
+    fn timelock_delay_changes_wait_for_the_current_delay() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_timelock_delay(1_000);
+        contract.set_timelock_delay(0);
+        // the drop to 0 is queued, so an owner change right away is still held back
+        contract.change_owner("Mallory".to_string());
+        assert_eq!("Paul".to_string(), contract.get_owner());
+        assert!(contract.get_pending_action(1).is_some());
+
+        let mut context = get_context_for("Paul");
+        context.block_timestamp = 1_000;
+        testing_env!(context);
+        contract.execute_pending_action(0);
+        contract.cancel_pending_action(1);
+        contract.change_owner("carol.near".to_string());
+        assert_eq!("carol.near".to_string(), contract.get_owner());
+    }
+
+    #[test]
+    fn executed_deletions_are_attributed_to_the_requester() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_timelock_delay(1_000);
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 12);
+        contract.delete_products("0x1".to_string());
+
+        let mut context = get_context_for("mallory.near");
+        context.block_timestamp = 1_000;
+        testing_env!(context);
+        contract.execute_pending_action(0);
+        assert_eq!("Paul".to_string(), contract.get_trashed_product("0x1".to_string()).unwrap().deleted_by);
+        let log = contract.get_audit_log(0, 100);
+        assert_eq!(("Paul".to_string(), "delete".to_string()), (log.last().unwrap().actor.clone(), log.last().unwrap().action.clone()));
+    }
+
+    #[test]
+    fn direct_purchases_are_recorded_as_settled_orders() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 10);
+        contract.add_role_shipper("Paul".to_string());
+
+        testing_env!(get_context_with_deposit("Bob", 500));
+        let order_id = contract.buy_product("0x1".to_string(), 1);
+        let order = contract.get_order(order_id).unwrap();
+        assert_eq!((OrderStatus::Fulfilled, true), (order.status, order.released));
+        assert_eq!(500, contract.get_revenue("Paul".to_string()).0);
+        assert!(contract.get_product_orders("0x1".to_string()).is_empty());
+
+        testing_env!(get_context_for("Paul"));
+        contract.mark_shipped(order_id, "DHL".to_string(), "abc".to_string());
+        assert!(contract.get_order(order_id).unwrap().shipping.is_some());
+    }
+
+    #[test]
+    fn escrowed_direct_purchases_wait_for_delivery() {
+        testing_env!(get_context(vec![], false));
+        let mut contract = Product::new();
+        contract.update_config(ConfigPatch { escrow_direct_sales: Some(true), ..Default::default() });
+        contract.set_products("0x1".to_string(), "PS5".to_string(), 500, 10);
+
+        testing_env!(get_context_with_deposit("Bob", 1_000));
+        let order_id = contract.buy_product("0x1".to_string(), 2);
+        assert_eq!(8, get_item(&contract, "0x1").unwrap().stock);
+        assert_eq!(0, contract.get_revenue("Paul".to_string()).0);
+
+        testing_env!(get_context_for("Paul"));
+        contract.refund_order_units(order_id, 1);
+        assert_eq!(9, get_item(&contract, "0x1").unwrap().stock);
+
+        testing_env!(get_context_for("Bob"));
+        contract.confirm_delivery(order_id);
+        assert_eq!(500, contract.get_revenue("Paul".to_string()).0);
+        assert!(contract.get_product_orders("0x1".to_string()).is_empty());
     }
 }
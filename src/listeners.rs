@@ -0,0 +1,73 @@
+use near_sdk::{env, ext_contract, near_bindgen, AccountId, Gas};
+use near_contract_standards::upgrade::Ownable;
+
+use crate::Product;
+use crate::ProductContract;
+
+const MAX_LISTENERS:usize = 10;
+// 5 TGas
+pub const DEFAULT_LISTENER_GAS:Gas = 5_000_000_000_000;
+
+// interface listener contracts implement to hear about catalog changes
+#[ext_contract(ext_listener)]
+pub trait ProductListener {
+    fn on_product_changed(&mut self, address: String, event: String);
+    // one call for a whole batch, a full batch of single notifications would not fit in the gas limit
+    fn on_products_changed(&mut self, addresses: Vec<String>, event: String);
+}
+
+// registered listener contracts
+#[near_bindgen]
+impl Product{
+    pub fn add_listener(&mut self, account: AccountId) {
+        //validate if owner
+        self.assert_owner();
+        self.touch();
+        assert!(env::is_valid_account_id(account.as_bytes()), "Invalid listener account");
+        if !self.listeners.contains(&account) {
+            assert!(self.listeners.len() < MAX_LISTENERS, "Too many listeners");
+            self.listeners.push(account);
+        }
+    }
+
+    pub fn remove_listener(&mut self, account: AccountId) {
+        //validate if owner
+        self.assert_owner();
+        self.touch();
+        self.listeners.retain(|listener| listener != &account);
+    }
+
+    pub fn set_listener_gas(&mut self, gas: Gas) {
+        //validate if owner
+        self.assert_owner();
+        self.touch();
+        self.listener_gas = gas;
+    }
+
+    pub fn get_listeners(&self) -> Vec<AccountId> {
+        self.listeners.clone()
+    }
+
+    // fire and forget, a failing listener never reverts the change itself; inside a batch the
+    // address is kept for the one notification finish_batch sends
+    pub(crate) fn notify_listeners(&mut self, address: &str, event: &str) {
+        if let Some(log) = self.batch_log.as_mut() {
+            if !log.changed.iter().any(|changed| changed == address) {
+                log.changed.push(address.to_string());
+            }
+            return;
+        }
+        for listener in self.listeners.iter() {
+            ext_listener::on_product_changed(address.to_string(), event.to_string(), listener, 0, self.listener_gas);
+        }
+    }
+
+    pub(crate) fn notify_listeners_of_all(&self, addresses: Vec<String>, event: &str) {
+        if addresses.is_empty() {
+            return;
+        }
+        for listener in self.listeners.iter() {
+            ext_listener::on_products_changed(addresses.clone(), event.to_string(), listener, 0, self.listener_gas);
+        }
+    }
+}
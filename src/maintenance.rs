@@ -0,0 +1,296 @@
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::LookupMap;
+use near_sdk::json_types::Base64VecU8;
+use near_sdk::serde::{Serialize, Deserialize};
+use near_sdk::{env, near_bindgen, AccountId};
+use near_contract_standards::upgrade::Ownable;
+
+use crate::{normalize_name, Item, LifecycleState, Product, Visibility, ALL_ROLES, ROLE_SET_PRODUCT};
+use crate::moderation::Approval;
+use crate::ProductContract;
+use crate::batch::{BatchSummary, MAX_BATCH_SIZE};
+use crate::utils::pagination::scan;
+
+const MAX_EXPORT_CHUNK:u64 = 100;
+
+// records and roles used to share this prefix, a product stored under a role name overwrote the role
+const LEGACY_PREFIX:&[u8] = b"a";
+pub(crate) const RECORDS_PREFIX:&[u8] = b"m";
+pub(crate) const ROLES_PREFIX:&[u8] = b"n";
+
+// one export_state chunk, cursor is None once the last record was exported
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ExportChunk {
+     pub data: Base64VecU8,
+     pub cursor: Option<String>,
+}
+
+// the state first deployments wrote, records and roles both under LEGACY_PREFIX
+#[derive(BorshDeserialize, BorshSerialize)]
+pub(crate) struct LegacyProduct {
+     pub(crate) records: LookupMap<String, LegacyItem>,
+     pub(crate) owner: AccountId,
+     pub(crate) access: LegacyAccessControl,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub(crate) struct LegacyAccessControl {
+     pub(crate) roles: LookupMap<String, Vec<String>>,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub(crate) struct LegacyItem {
+     pub(crate) name: String,
+     pub(crate) price: u128,
+     pub(crate) stock: u8,
+}
+
+// progress of moving records off the legacy prefix, walked in product_keys order; Legacy until
+// the owner has listed the keys to carry over, the old layout has no way to enumerate them
+#[derive(BorshDeserialize, BorshSerialize)]
+pub enum PrefixMigration {
+     Legacy,
+     Copying { next_index: u64 },
+     Cleaning { next_index: u64 },
+     Done,
+}
+
+// maintenance
+#[near_bindgen]
+impl Product{
+    // drops index entries left behind for products that no longer exist, cleaned keys are reported as updated
+    pub fn cleanup_orphans(&mut self, keys: Vec<String>) -> BatchSummary {
+        //validate if owner
+        self.assert_owner();
+        assert!(keys.len() <= MAX_BATCH_SIZE, "Too many entries in one batch");
+
+        let mut summary = BatchSummary::default();
+        for address in keys {
+            if self.records.contains_key(&address) {
+                summary.failed.push((address, "Product exists".to_string()));
+                continue;
+            }
+            self.remove_secondary_entries(&address);
+            summary.updated.push(address);
+        }
+        self.emit_batch_summary("cleanup_orphans", &summary);
+        summary
+    }
+
+    // borsh encoded Vec<(String, Item)> in key order, for off-chain backups and redeploys; records
+    // are public state anyway, so anyone may read them, pass the cursor back for the next chunk
+    pub fn export_state(&self, cursor: Option<String>, limit: u64) -> ExportChunk {
+        let page = scan(&self.ordered_keys, &self.cursor_secret, cursor, std::cmp::min(limit, MAX_EXPORT_CHUNK), |address| {
+            self.records.get(&address).map(|item| (address, item))
+        });
+        ExportChunk { data: Base64VecU8(page.items.try_to_vec().unwrap()), cursor: page.cursor }
+    }
+
+    // loads an export_state chunk into this store, keys that already exist or sit in the trash are
+    // reported as failed, as are records whose creator may not list here; nothing this store did
+    // not grant is taken from the export: no deposit is held, moderation starts over, a freeze carries over
+    pub fn import_state(&mut self, data: Base64VecU8) -> BatchSummary {
+        //validate if owner
+        self.assert_owner();
+        let chunk = Vec::<(String, Item)>::try_from_slice(&data.0).unwrap_or_else(|_| env::panic(b"Invalid export data"));
+        assert!(chunk.len() as u64 <= MAX_EXPORT_CHUNK, "Too many entries in one batch");
+
+        let mut summary = BatchSummary::default();
+        for (address, mut item) in chunk {
+            if self.records.contains_key(&address) {
+                summary.failed.push((address, "Product exists".to_string()));
+                continue;
+            }
+            if self.trash.contains_key(&address) {
+                summary.failed.push((address, "Product is in the trash".to_string()));
+                continue;
+            }
+            if item.creator != self.owner && !self.access.has_role(ROLE_SET_PRODUCT, &item.creator) {
+                summary.failed.push((address, "Creator cannot list here".to_string()));
+                continue;
+            }
+            if self.product_keys.len() >= self.config().max_products {
+                summary.failed.push((address, "Catalog is full".to_string()));
+                continue;
+            }
+            item.listing_deposit = 0;
+            item.approval = self.next_approval(None);
+            item.report_count = 0;
+            item.updated_by = env::signer_account_id();
+            self.save_product(&address, &mut item);
+            self.record_price(&address, item.price);
+            self.tombstones.remove(&address);
+            if self.product_keys.insert(&address) {
+                self.ordered_keys.insert(&address, &());
+            }
+            self.index_vendor(&item.creator, &address);
+            self.index_name(&item.search_key, &address);
+            summary.updated.push(address);
+        }
+        self.emit_batch_summary("import_state", &summary);
+        self.audit("import_state", &summary.updated.len().to_string());
+        summary
+    }
+
+    // every per-product index, kept in one place so deletions and cleanups cannot drift apart
+    pub(crate) fn remove_secondary_entries(&mut self, address: &String) {
+        self.remove_listing_entries(address);
+        self.price_history.remove(address);
+        self.stock_ledger.remove(address);
+        self.sales_buckets.remove(address);
+        self.invitees.remove(address);
+        self.revisions.remove(address);
+        self.previous_revisions.remove(address);
+        self.open_restock_requests.remove(address);
+        self.payment_splits.remove(address);
+        self.token_gates.remove(address);
+        self.product_campaigns.remove(address);
+        self.price_experiments.remove(address);
+        for location in self.product_locations.remove(address).unwrap_or_default() {
+            self.location_stock.remove(&(address.clone(), location));
+        }
+    }
+
+    // what makes a product show up as listed or queued, the rest is kept while it sits in the trash
+    pub(crate) fn remove_listing_entries(&mut self, address: &String) {
+        self.product_keys.remove(address);
+        self.ordered_keys.remove(address);
+        self.pending_deletes.remove(address);
+        self.reports.remove(address);
+        self.reported_products.remove(address);
+    }
+
+    // run once with the new code deployed over a store created by the first release; roles move
+    // right away, records follow through index_legacy_products and migrate_prefixes
+    #[init(ignore_state)]
+    pub fn migrate() -> Self {
+        let legacy: LegacyProduct = env::state_read().expect("No legacy state to migrate");
+        assert_eq!(legacy.owner, env::signer_account_id(), "Only the owner can migrate");
+
+        let mut this = Self::empty_state(legacy.owner);
+        for role in ALL_ROLES.iter() {
+            if let Some(accounts) = legacy.access.roles.get(&role.to_string()) {
+                this.access.roles.insert(&role.to_string(), &accounts);
+            }
+        }
+        this.prefix_migration = PrefixMigration::Legacy;
+        this.audit("migrate", "roles");
+        this
+    }
+
+    // queues legacy records for migrate_prefixes, keys without a legacy record are reported as failed
+    pub fn index_legacy_products(&mut self, keys: Vec<String>) -> BatchSummary {
+        //validate if owner
+        self.assert_owner();
+        assert!(matches!(self.prefix_migration, PrefixMigration::Legacy), "Legacy records are already being copied");
+        assert!(keys.len() <= MAX_BATCH_SIZE, "Too many entries in one batch");
+
+        let legacy: LookupMap<String, LegacyItem> = LookupMap::new(LEGACY_PREFIX.to_vec());
+        let mut summary = BatchSummary::default();
+        for address in keys {
+            // a record stored under a role name was overwritten by the role, nothing to copy
+            if ALL_ROLES.contains(&address.as_str()) || !legacy.contains_key(&address) {
+                summary.failed.push((address, "No legacy record".to_string()));
+                continue;
+            }
+            if self.product_keys.insert(&address) {
+                self.ordered_keys.insert(&address, &());
+            }
+            summary.updated.push(address);
+        }
+        self.emit_batch_summary("index_legacy_products", &summary);
+        summary
+    }
+
+    // resumable, call again until it returns true; product writes are refused until every record is copied
+    pub fn migrate_prefixes(&mut self, batch_size: u64) -> bool {
+        //validate if owner
+        self.assert_owner();
+        assert!(batch_size > 0, "Batch size must be positive");
+        self.touch();
+
+        if let PrefixMigration::Legacy = self.prefix_migration {
+            self.prefix_migration = PrefixMigration::Copying { next_index: 0 };
+        }
+        let total = self.product_keys.len();
+        match self.prefix_migration {
+            PrefixMigration::Copying { next_index } => {
+                let end = std::cmp::min(next_index + batch_size, total);
+                let legacy: LookupMap<String, LegacyItem> = LookupMap::new(LEGACY_PREFIX.to_vec());
+                for index in next_index..end {
+                    let address = self.product_keys.as_vector().get(index).unwrap();
+                    if let Some(item) = legacy.get(&address) {
+                        self.copy_legacy_record(&address, item);
+                    }
+                }
+                if end < total {
+                    self.prefix_migration = PrefixMigration::Copying { next_index: end };
+                    return false;
+                }
+                self.prefix_migration = PrefixMigration::Cleaning { next_index: 0 };
+                self.audit("migrate_prefixes", "copied");
+                false
+            }
+            PrefixMigration::Cleaning { next_index } => {
+                let end = std::cmp::min(next_index + batch_size, total);
+                let mut legacy: LookupMap<String, Item> = LookupMap::new(LEGACY_PREFIX.to_vec());
+                for index in next_index..end {
+                    let address = self.product_keys.as_vector().get(index).unwrap();
+                    legacy.remove_raw(&address.try_to_vec().unwrap());
+                }
+                if end < total {
+                    self.prefix_migration = PrefixMigration::Cleaning { next_index: end };
+                    return false;
+                }
+                for role in ALL_ROLES.iter() {
+                    legacy.remove_raw(&role.to_string().try_to_vec().unwrap());
+                }
+                self.prefix_migration = PrefixMigration::Done;
+                self.audit("migrate_prefixes", "done");
+                true
+            }
+            _ => true,
+        }
+    }
+
+    // legacy listings predate creators and moderation, they are carried over as the owner's and approved
+    fn copy_legacy_record(&mut self, address: &String, legacy: LegacyItem) {
+        let owner = self.owner.clone();
+        let item = Item {
+            search_key: normalize_name(&legacy.name),
+            name: legacy.name,
+            price: legacy.price,
+            stock: legacy.stock,
+            frozen: false,
+            creator: owner.clone(),
+            updated_by: owner.clone(),
+            approval: Approval::Approved,
+            report_count: 0,
+            listing_deposit: 0,
+            version: 0,
+            scheduled_price: None,
+            max_per_buyer: 0,
+            preorder_until: None,
+            backorders: false,
+            rental: None,
+            price_decay: None,
+            visibility: Visibility::Public,
+            state: LifecycleState::Active,
+            pay_what_you_want: false,
+            usd_price: None,
+        };
+        self.records.insert(address, &item);
+        self.record_price(address, item.price);
+        self.index_vendor(&owner, address);
+        self.index_name(&item.search_key, address);
+    }
+
+    pub(crate) fn assert_not_migrating(&self) {
+        assert!(!self.is_migrating(), "Storage migration in progress");
+    }
+
+    pub(crate) fn is_migrating(&self) -> bool {
+        matches!(self.prefix_migration, PrefixMigration::Legacy | PrefixMigration::Copying { .. })
+    }
+}
@@ -0,0 +1,139 @@
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Serialize, Deserialize};
+use near_sdk::{env, near_bindgen, AccountId};
+use near_contract_standards::upgrade::Ownable;
+
+use crate::Product;
+use crate::ProductContract;
+use crate::utils::pagination::{scan, Page};
+
+const MAX_REPORT_REASON_LEN:usize = 280;
+const MAX_REPORTS_PER_PRODUCT:usize = 50;
+
+// moderation state of a listing
+#[derive(Debug, Clone, PartialEq)]
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub enum Approval {
+    Pending,
+    Approved,
+    Rejected { reason: String },
+    // hidden and unpurchasable until a moderator approves or rejects it
+    Quarantined,
+}
+
+#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Report {
+     reporter: AccountId,
+     reason: String,
+     reported_at: u64,
+}
+
+// moderated listing approval workflow
+#[near_bindgen]
+impl Product{
+    pub fn set_moderation_required(&mut self, required: bool) {
+        //validate if owner
+        self.assert_owner();
+        self.touch();
+        self.moderation_required = required;
+    }
+
+    // 0 turns automatic quarantine off
+    pub fn set_quarantine_threshold(&mut self, distinct_reporters: u32) {
+        //validate if owner
+        self.assert_owner();
+        self.touch();
+        assert!(distinct_reporters as usize <= MAX_REPORTS_PER_PRODUCT, "Threshold exceeds the stored reports");
+        self.quarantine_threshold = distinct_reporters;
+    }
+
+    pub fn approve_listing(&mut self, address: String) {
+        self.set_approval(address, Approval::Approved);
+    }
+
+    pub fn reject_listing(&mut self, address: String, reason: String) {
+        self.set_approval(address, Approval::Rejected { reason });
+    }
+
+    fn set_approval(&mut self, address: String, approval: Approval) {
+        //validate sender has permition of ROLE_MODERATOR
+        self.assert_permission("set_approval");
+
+        let mut item = self.load_product(&address).expect("Product does not exist");
+        item.approval = approval;
+        item.updated_by = env::signer_account_id();
+        self.emit_event("set_approval", format!("{} {:?}", address, item.approval));
+        self.audit(&format!("set approval {:?}", item.approval), &address);
+        self.save_product(&address, &mut item);
+    }
+
+    // new listings wait for a moderator when moderation is on, rejected ones go back to the queue when edited
+    pub(crate) fn next_approval(&self, existing: Option<&Approval>) -> Approval {
+        match existing {
+            Some(Approval::Rejected { .. }) | None if self.moderation_required => Approval::Pending,
+            Some(approval) => approval.clone(),
+            None => Approval::Approved,
+        }
+    }
+}
+
+// flag/report products
+#[near_bindgen]
+impl Product{
+    #[payable]
+    pub fn report_product(&mut self, address: String, reason: String) {
+        let report_deposit = self.config().report_deposit;
+        assert!(env::attached_deposit() >= report_deposit, "Attach {} yoctoNEAR to report a product", report_deposit);
+        assert!(reason.len() <= MAX_REPORT_REASON_LEN, "Report reason is too long");
+        let mut item = self.load_product(&address).expect("Product does not exist");
+
+        let mut reports = self.reports.get(&address).unwrap_or_default();
+        if reports.len() < MAX_REPORTS_PER_PRODUCT {
+            reports.push(Report { reporter: env::signer_account_id(), reason, reported_at: env::block_timestamp() });
+        }
+        item.report_count += 1;
+        self.emit_event("report_product", format!("{} {}", address, item.report_count));
+
+        let mut reporters: Vec<&AccountId> = reports.iter().map(|report| &report.reporter).collect();
+        reporters.sort();
+        reporters.dedup();
+        if self.quarantine_threshold > 0
+            && reporters.len() >= self.quarantine_threshold as usize
+            && item.approval == Approval::Approved {
+            item.approval = Approval::Quarantined;
+            self.emit_event("quarantine_product", format!("{} {}", address, reporters.len()));
+        }
+        self.reports.insert(&address, &reports);
+        self.reported_products.insert(&address, &());
+        self.save_product(&address, &mut item);
+        self.refund_surplus(report_deposit);
+    }
+
+    // clears the reports once a moderator has triaged the listing
+    pub fn dismiss_reports(&mut self, address: String) {
+        //validate sender has permition of ROLE_MODERATOR
+        self.assert_permission("dismiss_reports");
+
+        if let Some(mut item) = self.load_product(&address) {
+            item.report_count = 0;
+            self.save_product(&address, &mut item);
+        }
+        self.emit_event("dismiss_reports", address.clone());
+        self.reports.remove(&address);
+        self.reported_products.remove(&address);
+    }
+
+    pub fn get_reports(&self, address: String) -> Vec<Report> {
+        self.reports.get(&address).unwrap_or_default()
+    }
+
+    // listings with open reports, with their report counter
+    pub fn get_reported_products(&self, cursor: Option<String>, limit: u64) -> Page<(String, u32)> {
+        scan(&self.reported_products, &self.cursor_secret, cursor, limit, |address| {
+            self.load_product(&address).map(|item| (address, item.report_count))
+        })
+    }
+}
@@ -0,0 +1,169 @@
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Serialize, Deserialize};
+use near_sdk::{env, near_bindgen, AccountId, Balance};
+
+use crate::Product;
+use crate::ProductContract;
+
+const MAX_OFFERS_PER_PRODUCT:usize = 50;
+
+// escrowed bid below the list price, expires_at is a block timestamp in nanoseconds
+#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Offer {
+     pub address: String,
+     pub buyer: AccountId,
+     pub quantity: u8,
+     pub amount: Balance,
+     pub expires_at: u64,
+}
+
+// price offered to one buyer only, for quantity units at price each
+#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Quote {
+     pub address: String,
+     pub buyer: AccountId,
+     pub price: Balance,
+     pub quantity: u8,
+     pub expires_at: u64,
+}
+
+// make-an-offer negotiation
+#[near_bindgen]
+impl Product{
+    // the attached deposit is the offer for all units together
+    #[payable]
+    pub fn make_offer(&mut self, address: String, quantity: u8, expires_at: u64) -> u64 {
+        self.assert_not_paused();
+        assert!(quantity > 0, "Quantity must be positive");
+        let item = self.load_product(&address).expect("Product does not exist");
+        self.assert_can_buy(&env::predecessor_account_id(), &address, &item);
+        let amount = env::attached_deposit();
+        assert!(amount > 0, "Attach the offered amount");
+        assert!(amount < item.price * quantity as u128, "Offer must be below the list price, buy the product instead");
+        assert!(expires_at > env::block_timestamp(), "Offer must expire in the future");
+        let mut open = self.product_offers.get(&address).unwrap_or_default();
+        assert!(open.len() < MAX_OFFERS_PER_PRODUCT, "Too many open offers");
+
+        let offer_id = self.next_offer_id;
+        self.next_offer_id += 1;
+        let offer = Offer { address: address.clone(), buyer: env::predecessor_account_id(), quantity, amount, expires_at };
+        self.emit_event("make_offer", format!("{} {:?}", offer_id, offer));
+        self.log_offer("made", offer_id, &offer);
+        self.offers.insert(&offer_id, &offer);
+        open.push(offer_id);
+        self.product_offers.insert(&address, &open);
+        offer_id
+    }
+
+    // settles at the offered amount
+    pub fn accept_offer(&mut self, offer_id: u64) {
+        let offer = self.offers.get(&offer_id).expect("Offer does not exist");
+        let mut item = self.load_product(&offer.address).expect("Product does not exist");
+        self.assert_creator_or_override(&item, false);
+        self.assert_purchasable(&item);
+        assert!(env::block_timestamp() < offer.expires_at, "Offer has expired");
+        assert!(item.stock >= offer.quantity, "Not enough stock");
+
+        self.emit_event("accept_offer", offer_id.to_string());
+        self.log_offer("accepted", offer_id, &offer);
+        self.log_purchase("offer", &offer.address, &offer.buyer, offer.quantity, offer.amount, None);
+        self.take_stock(&offer.address, &mut item, offer.quantity);
+        self.pay_vendor(&offer.address, item.creator, &offer.buyer, offer.amount);
+        self.remove_offer(offer_id, &offer);
+    }
+
+    pub fn reject_offer(&mut self, offer_id: u64) {
+        let offer = self.offers.get(&offer_id).expect("Offer does not exist");
+        let item = self.load_product(&offer.address).expect("Product does not exist");
+        self.assert_creator_or_override(&item, false);
+        self.emit_event("reject_offer", offer_id.to_string());
+        self.log_offer("rejected", offer_id, &offer);
+        self.safe_transfer(offer.buyer.clone(), offer.amount);
+        self.remove_offer(offer_id, &offer);
+    }
+
+    // the buyer takes the offer back, expired offers can be reclaimed by anyone for the buyer
+    pub fn withdraw_offer(&mut self, offer_id: u64) {
+        let offer = self.offers.get(&offer_id).expect("Offer does not exist");
+        assert!(env::predecessor_account_id() == offer.buyer || env::block_timestamp() >= offer.expires_at, "401");
+        self.emit_event("withdraw_offer", offer_id.to_string());
+        self.log_offer("withdrawn", offer_id, &offer);
+        self.safe_transfer(offer.buyer.clone(), offer.amount);
+        self.remove_offer(offer_id, &offer);
+    }
+
+    pub fn get_product_offers(&self, address: String) -> Vec<(u64, Offer)> {
+        self.product_offers.get(&address).unwrap_or_default().into_iter()
+            .filter_map(|offer_id| self.offers.get(&offer_id).map(|offer| (offer_id, offer)))
+            .collect()
+    }
+
+    fn remove_offer(&mut self, offer_id: u64, offer: &Offer) {
+        self.offers.remove(&offer_id);
+        let mut open = self.product_offers.get(&offer.address).unwrap_or_default();
+        open.retain(|id| *id != offer_id);
+        if open.is_empty() {
+            self.product_offers.remove(&offer.address);
+        } else {
+            self.product_offers.insert(&offer.address, &open);
+        }
+    }
+}
+
+// per-buyer negotiated quotes
+#[near_bindgen]
+impl Product{
+    // b2b pricing that never shows up in the public list price
+    pub fn create_quote(&mut self, address: String, buyer: AccountId, price: Balance, quantity: u8, expires_at: u64) -> u64 {
+        let item = self.load_product(&address).expect("Product does not exist");
+        self.assert_creator_or_override(&item, false);
+        assert!(quantity > 0, "Quantity must be positive");
+        assert!(expires_at > env::block_timestamp(), "Quote must expire in the future");
+
+        let quote_id = self.next_quote_id;
+        self.next_quote_id += 1;
+        self.quotes.insert(&quote_id, &Quote { address, buyer, price, quantity, expires_at });
+        self.emit_event("create_quote", quote_id.to_string());
+        quote_id
+    }
+
+    pub fn cancel_quote(&mut self, quote_id: u64) {
+        let quote = self.quotes.get(&quote_id).expect("Quote does not exist");
+        let item = self.load_product(&quote.address).expect("Product does not exist");
+        self.assert_creator_or_override(&item, false);
+        self.quotes.remove(&quote_id);
+        self.emit_event("cancel_quote", quote_id.to_string());
+    }
+
+    // single use, only the quoted buyer can redeem it
+    #[payable]
+    pub fn buy_with_quote(&mut self, quote_id: u64) {
+        let quote = self.quotes.get(&quote_id).expect("Quote does not exist");
+        assert_eq!(quote.buyer, env::predecessor_account_id(), "401");
+        assert!(env::block_timestamp() < quote.expires_at, "Quote has expired");
+        let mut item = self.load_product(&quote.address).expect("Product does not exist");
+        self.assert_purchasable(&item);
+        assert!(item.stock >= quote.quantity, "Not enough stock");
+        if item.max_per_buyer > 0 {
+            self.record_units_bought(&quote.buyer, &quote.address, &item, quote.quantity);
+        }
+        let total = quote.price * quote.quantity as u128;
+        assert!(env::attached_deposit() >= total, "Attached deposit does not cover the price");
+        self.record_account_activity(&quote.buyer, total, quote.quantity);
+
+        self.quotes.remove(&quote_id);
+        self.emit_event("buy_with_quote", quote_id.to_string());
+        self.log_purchase("quote", &quote.address, &quote.buyer, quote.quantity, total, None);
+        self.take_stock(&quote.address, &mut item, quote.quantity);
+        self.pay_vendor(&quote.address, item.creator, &quote.buyer, total);
+        self.refund_surplus(total);
+    }
+
+    pub fn get_quote(&self, quote_id: u64) -> Option<Quote> {
+        self.quotes.get(&quote_id)
+    }
+}
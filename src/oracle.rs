@@ -0,0 +1,114 @@
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Serialize, Deserialize};
+use near_sdk::json_types::U128;
+use near_sdk::{env, near_bindgen, Balance};
+use near_contract_standards::upgrade::Ownable;
+
+use crate::{Item, Product};
+use crate::ProductContract;
+
+// last NEAR/USD rate pushed by a keeper
+#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct UsdRate {
+     pub yocto_per_usd: Balance,
+     pub updated_at: u64,
+}
+
+impl UsdRate {
+    pub fn to_yocto(&self, cents: u64) -> Balance {
+        self.yocto_per_usd * cents as u128 / 100
+    }
+}
+
+const ONE_NEAR:Balance = 1_000_000_000_000_000_000_000_000;
+const MAX_DISPLAY_RATES:usize = 10;
+
+// rate of a token shown next to NEAR prices, only used for display
+#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DisplayRate {
+     pub symbol: String,
+     // smallest token units worth one NEAR
+     pub units_per_near: Balance,
+     pub updated_at: u64,
+}
+
+// USD pricing
+#[near_bindgen]
+impl Product{
+    pub fn update_usd_rate(&mut self, yocto_per_usd: U128) {
+        //validate sender has permition of ROLE_KEEPER
+        self.assert_permission("update_usd_rate");
+        assert!(yocto_per_usd.0 > 0, "Rate must be positive");
+        let rate = UsdRate { yocto_per_usd: yocto_per_usd.0, updated_at: env::block_timestamp() };
+        self.emit_event("update_usd_rate", format!("{:?}", rate));
+        self.usd_rate = Some(rate);
+    }
+
+    pub fn get_usd_rate(&self) -> Option<UsdRate> {
+        self.usd_rate.clone()
+    }
+
+    // None goes back to the NEAR price last charged
+    pub fn set_usd_price(&mut self, address: String, cents: Option<u64>) {
+        let mut item = self.load_product(&address).expect("Product does not exist");
+        self.assert_editable(&item);
+        self.assert_creator_or_override(&item, false);
+        item.usd_price = cents;
+        self.save_product(&address, &mut item);
+        self.emit_event("set_usd_price", format!("{} {:?}", address, cents));
+    }
+
+    // USD priced listings cannot be sold without a rate younger than max_rate_staleness
+    pub(crate) fn is_rate_fresh(&self, item: &Item) -> bool {
+        if item.usd_price.is_none() {
+            return true;
+        }
+        self.usd_rate.as_ref()
+            .map(|rate| env::block_timestamp() <= rate.updated_at + self.config().max_rate_staleness)
+            .unwrap_or(false)
+    }
+}
+
+// display currencies
+#[near_bindgen]
+impl Product{
+    pub fn update_display_rate(&mut self, symbol: String, units_per_near: U128) {
+        //validate sender has permition of ROLE_KEEPER
+        self.assert_permission("update_display_rate");
+        let rate = DisplayRate { symbol, units_per_near: units_per_near.0, updated_at: env::block_timestamp() };
+        self.emit_event("update_display_rate", format!("{:?}", rate));
+        match self.display_rates.iter_mut().find(|existing| existing.symbol == rate.symbol) {
+            Some(existing) => *existing = rate,
+            None => {
+                assert!(self.display_rates.len() < MAX_DISPLAY_RATES, "Too many display currencies");
+                self.display_rates.push(rate);
+            },
+        }
+    }
+
+    pub fn remove_display_rate(&mut self, symbol: String) {
+        //validate if owner
+        self.assert_owner();
+        self.display_rates.retain(|rate| rate.symbol != symbol);
+    }
+
+    pub fn get_display_rates(&self) -> Vec<DisplayRate> {
+        self.display_rates.clone()
+    }
+
+    // NEAR first, then every configured token; very large amounts lose the sub-NEAR precision
+    pub(crate) fn display_prices(&self, price: Balance) -> Vec<(String, U128)> {
+        let mut prices = vec![("NEAR".to_string(), U128(price))];
+        for rate in self.display_rates.iter() {
+            let amount = price.checked_mul(rate.units_per_near)
+                .map(|units| units / ONE_NEAR)
+                .unwrap_or_else(|| price / ONE_NEAR * rate.units_per_near);
+            prices.push((rate.symbol.clone(), U128(amount)));
+        }
+        prices
+    }
+}
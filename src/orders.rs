@@ -0,0 +1,471 @@
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Serialize, Deserialize};
+use near_sdk::{env, near_bindgen, AccountId, Balance};
+
+use crate::{Item, Product};
+use crate::ProductContract;
+use crate::history::StockReason;
+
+const MAX_ORDERS_PER_CALL:u64 = 50;
+
+#[derive(Debug, Clone, PartialEq)]
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub enum OrderStatus {
+    // funds escrowed, no stock taken yet
+    Preordered,
+    // queued until increase_stock covers it, first come first served
+    Backordered,
+    Fulfilled,
+    Refunded,
+    Cancelled,
+    // the unit is out with the renter, the security deposit is escrowed
+    Rented,
+    Returned,
+}
+
+// rent-not-buy terms, the price goes to the vendor and the deposit is held until the return
+#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RentalTerms {
+     pub price: Balance,
+     pub security_deposit: Balance,
+     // how long the renter keeps the unit, in nanoseconds
+     pub duration: u64,
+}
+
+// set by the shipper once a fulfilled order leaves the warehouse
+#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Shipping {
+     pub carrier: String,
+     // hash of the carrier tracking number, the number itself stays off-chain
+     pub tracking_hash: String,
+     pub shipped_at: u64,
+     pub delivered_at: Option<u64>,
+}
+
+// every purchase, funds are held until it is settled or refunded; direct purchases are settled at
+// checkout unless escrow_direct_sales is on, so only escrowed ones can be released or partially refunded
+#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Order {
+     pub address: String,
+     pub buyer: AccountId,
+     pub quantity: u8,
+     pub amount: Balance,
+     pub status: OrderStatus,
+     pub created_at: u64,
+     pub shipping: Option<Shipping>,
+     pub vendor: AccountId,
+     // a fulfilled order keeps its amount escrowed until delivery is confirmed
+     pub released: bool,
+     // blocks the automatic release until a moderator resolves it
+     pub disputed: bool,
+     // who got what when the escrow was released
+     pub payouts: Vec<(AccountId, Balance)>,
+     // already paid to the vendor at checkout, never escrowed or refunded
+     pub tip: Balance,
+     // the affiliate code the sale is attributed to
+     pub affiliate: Option<String>,
+     // the price experiment bucket the buyer was in, amount is what that bucket paid
+     pub experiment_bucket: Option<u8>,
+     // when a rented unit is due back
+     pub due_at: Option<u64>,
+}
+
+impl Order {
+    // anything the contract still owes to the buyer or the vendor, such an order keeps its product from being deleted
+    pub(crate) fn holds_escrow(&self) -> bool {
+        match self.status {
+            OrderStatus::Preordered | OrderStatus::Backordered | OrderStatus::Rented => true,
+            OrderStatus::Fulfilled => !self.released,
+            _ => false,
+        }
+    }
+}
+
+// pre-orders
+#[near_bindgen]
+impl Product{
+    // None takes the product out of pre-order, already escrowed orders stay open
+    pub fn set_preorder(&mut self, address: String, fulfill_at: Option<u64>) {
+        let mut item = self.load_product(&address).expect("Product does not exist");
+        self.assert_editable(&item);
+        self.assert_creator_or_override(&item, false);
+        item.preorder_until = fulfill_at;
+        self.save_product(&address, &mut item);
+        self.emit_event("set_preorder", format!("{} {:?}", address, fulfill_at));
+    }
+
+    // oldest first, stops at the first order the stock cannot cover
+    pub fn convert_preorders(&mut self, address: String, limit: u64) -> Vec<u64> {
+        let mut item = self.load_product(&address).expect("Product does not exist");
+        self.assert_creator_or_override(&item, false);
+        self.assert_editable(&item);
+
+        let mut converted = Vec::new();
+        for order_id in self.open_orders(&address, OrderStatus::Preordered, limit) {
+            let mut order = self.orders.get(&order_id).unwrap();
+            if item.stock < order.quantity {
+                break;
+            }
+            self.take_stock(&address, &mut item, order.quantity);
+            order.status = OrderStatus::Fulfilled;
+            self.close_order(order_id, &order);
+            converted.push(order_id);
+        }
+        self.emit_event("convert_preorders", format!("{} {:?}", address, converted));
+        converted
+    }
+
+    pub fn refund_preorders(&mut self, address: String, limit: u64) -> Vec<u64> {
+        let item = self.load_product(&address).expect("Product does not exist");
+        self.assert_creator_or_override(&item, false);
+
+        let refunded = self.open_orders(&address, OrderStatus::Preordered, limit);
+        for order_id in refunded.iter() {
+            self.refund_order(*order_id);
+        }
+        self.emit_event("refund_preorders", format!("{} {:?}", address, refunded));
+        refunded
+    }
+}
+
+// backorders
+#[near_bindgen]
+impl Product{
+    pub fn set_backorders(&mut self, address: String, enabled: bool) {
+        let mut item = self.load_product(&address).expect("Product does not exist");
+        self.assert_editable(&item);
+        self.assert_creator_or_override(&item, false);
+        item.backorders = enabled;
+        self.save_product(&address, &mut item);
+        self.emit_event("set_backorders", format!("{} {}", address, enabled));
+    }
+
+    // restocks, then fills queued backorders oldest first until one does not fit
+    pub fn increase_stock(&mut self, address: String, quantity: u8) -> Vec<u64> {
+        //validate sender has permition of ROLE_SET_PRODUCT
+        self.assert_permission("increase_stock");
+        let item = self.load_product(&address).expect("Product does not exist");
+        self.assert_editable(&item);
+        self.assert_creator_or_override(&item, false);
+        let new_stock = item.stock.checked_add(quantity).expect("Stock out of range");
+        let old_stock = item.stock;
+        self.internal_set_stock(&address, item, new_stock, StockReason::Restock);
+        self.emit_event("set_stock", format!("{} {} -> {}", address, old_stock, new_stock));
+        self.fulfill_restock_requests(&address, quantity as u32);
+        self.fill_backorders(&address)
+    }
+
+    // shared by every path that adds stock, increase_stock and receive_shipment
+    pub(crate) fn fill_backorders(&mut self, address: &String) -> Vec<u64> {
+        let mut item = self.load_product(address).unwrap();
+        let mut filled = Vec::new();
+        for order_id in self.open_orders(address, OrderStatus::Backordered, MAX_ORDERS_PER_CALL) {
+            let mut order = self.orders.get(&order_id).unwrap();
+            if item.stock < order.quantity {
+                break;
+            }
+            self.take_stock(address, &mut item, order.quantity);
+            order.status = OrderStatus::Fulfilled;
+            self.close_order(order_id, &order);
+            filled.push(order_id);
+        }
+        filled
+    }
+
+    // the buyer takes a queued backorder back and gets the escrow refunded
+    pub fn cancel_order(&mut self, order_id: u64) {
+        let mut order = self.orders.get(&order_id).expect("Order does not exist");
+        assert_eq!(order.buyer, env::predecessor_account_id(), "401");
+        assert!(order.status == OrderStatus::Backordered, "Only queued backorders can be cancelled");
+        self.safe_transfer(order.buyer.clone(), order.amount);
+        self.log_refund(order_id, &order, order.quantity, order.amount);
+        order.status = OrderStatus::Cancelled;
+        self.close_order(order_id, &order);
+    }
+}
+
+// rentals
+#[near_bindgen]
+impl Product{
+    // None stops renting the product out, rentals already out stay open until returned
+    pub fn set_rental_terms(&mut self, address: String, terms: Option<RentalTerms>) {
+        let mut item = self.load_product(&address).expect("Product does not exist");
+        self.assert_editable(&item);
+        self.assert_creator_or_override(&item, false);
+        self.emit_event("set_rental_terms", format!("{} {:?}", address, terms));
+        item.rental = terms;
+        self.save_product(&address, &mut item);
+    }
+
+    #[payable]
+    pub fn rent_product(&mut self, address: String) -> u64 {
+        let mut item = self.load_product(&address).expect("Product does not exist");
+        self.assert_can_buy(&env::predecessor_account_id(), &address, &item);
+        let terms = item.rental.clone().expect("Product is not for rent");
+        assert!(item.stock > 0, "Not enough stock");
+        let total = terms.price + terms.security_deposit;
+        assert!(env::attached_deposit() >= total, "Attached deposit does not cover the rental and its deposit");
+
+        let old_stock = item.stock;
+        item.stock -= 1;
+        self.save_product(&address, &mut item);
+        self.notify_listeners(&address, "rent_product");
+        self.check_stock_thresholds(&address, old_stock, item.stock);
+        self.record_stock_movement(&address, old_stock, item.stock, StockReason::Rental);
+
+        self.pay_vendor(&address, item.creator, &env::predecessor_account_id(), terms.price);
+        let order_id = self.place_order(&env::predecessor_account_id(), &address, 1, terms.security_deposit, 0, OrderStatus::Rented);
+        let mut order = self.orders.get(&order_id).unwrap();
+        order.due_at = Some(env::block_timestamp() + terms.duration);
+        self.orders.insert(&order_id, &order);
+        self.log_purchase("rental", &address, &env::predecessor_account_id(), 1, terms.price, Some(order_id));
+        self.refund_surplus(total);
+        order_id
+    }
+
+    // the vendor takes the unit back and keeps the declared damage out of the deposit
+    pub fn return_item(&mut self, order_id: u64, damage_charge: Balance) {
+        let order = self.rental_in_progress(order_id);
+        let item = self.load_product(&order.address).expect("Product does not exist");
+        self.assert_creator_or_override(&item, false);
+        assert!(damage_charge <= order.amount, "Damage charge exceeds the security deposit");
+        self.settle_rental(order_id, order, item, damage_charge);
+    }
+
+    // a vendor who has not settled the return within rental_grace_period after the unit was due
+    // loses the damage claim, the renter takes the whole deposit back
+    pub fn reclaim_rental_deposit(&mut self, order_id: u64) {
+        let order = self.rental_in_progress(order_id);
+        assert_eq!(order.buyer, env::predecessor_account_id(), "401");
+        let due_at = order.due_at.unwrap_or(order.created_at);
+        assert!(env::block_timestamp() >= due_at + self.config().rental_grace_period, "Rental deposit is still held");
+        let item = self.load_product(&order.address).expect("Product does not exist");
+        self.settle_rental(order_id, order, item, 0);
+    }
+
+    fn rental_in_progress(&self, order_id: u64) -> Order {
+        let order = self.orders.get(&order_id).expect("Order does not exist");
+        assert!(order.status == OrderStatus::Rented, "Order is not a rental in progress");
+        order
+    }
+
+    fn settle_rental(&mut self, order_id: u64, mut order: Order, mut item: Item, damage_charge: Balance) {
+        let old_stock = item.stock;
+        let new_stock = old_stock.checked_add(1).expect("Stock out of range");
+
+        if damage_charge > 0 {
+            self.safe_transfer(item.creator.clone(), damage_charge);
+        }
+        if order.amount > damage_charge {
+            self.safe_transfer(order.buyer.clone(), order.amount - damage_charge);
+        }
+        item.stock = new_stock;
+        self.save_product(&order.address, &mut item);
+        self.notify_listeners(&order.address, "return_item");
+        self.check_stock_thresholds(&order.address, old_stock, item.stock);
+        self.record_stock_movement(&order.address, old_stock, item.stock, StockReason::Rental);
+
+        order.status = OrderStatus::Returned;
+        self.close_order(order_id, &order);
+    }
+}
+
+// shipping
+#[near_bindgen]
+impl Product{
+    pub fn mark_shipped(&mut self, order_id: u64, carrier: String, tracking_hash: String) {
+        //validate sender has permition of ROLE_SHIPPER
+        self.assert_permission("mark_shipped");
+        let mut order = self.orders.get(&order_id).expect("Order does not exist");
+        assert!(order.status == OrderStatus::Fulfilled, "Only fulfilled orders can be shipped");
+        assert!(order.shipping.is_none(), "Order is already shipped");
+        let shipping = Shipping { carrier, tracking_hash, shipped_at: env::block_timestamp(), delivered_at: None };
+        self.emit_event("mark_shipped", format!("{} {:?}", order_id, shipping));
+        order.shipping = Some(shipping);
+        self.orders.insert(&order_id, &order);
+    }
+
+    pub fn mark_delivered(&mut self, order_id: u64) {
+        //validate sender has permition of ROLE_SHIPPER
+        self.assert_permission("mark_delivered");
+        let mut order = self.orders.get(&order_id).expect("Order does not exist");
+        let shipping = order.shipping.as_mut().expect("Order is not shipped");
+        assert!(shipping.delivered_at.is_none(), "Order is already delivered");
+        shipping.delivered_at = Some(env::block_timestamp());
+        self.orders.insert(&order_id, &order);
+        self.emit_event("mark_delivered", order_id.to_string());
+    }
+}
+
+// escrow release
+#[near_bindgen]
+impl Product{
+    // the buyer got the goods, the vendor is paid right away
+    pub fn confirm_delivery(&mut self, order_id: u64) {
+        let order = self.releasable_order(order_id);
+        assert_eq!(order.buyer, env::predecessor_account_id(), "401");
+        self.release_escrow_to_vendor(order_id, order);
+    }
+
+    // anyone can release once the delivery is older than the configured delay
+    pub fn release_escrow(&mut self, order_id: u64) {
+        let order = self.releasable_order(order_id);
+        assert!(!order.disputed, "Order is disputed");
+        let delivered_at = order.shipping.as_ref().and_then(|shipping| shipping.delivered_at).expect("Order is not delivered");
+        assert!(env::block_timestamp() >= delivered_at + self.config().escrow_release_delay, "Escrow is still held");
+        self.release_escrow_to_vendor(order_id, order);
+    }
+
+    pub fn dispute_order(&mut self, order_id: u64) {
+        let mut order = self.releasable_order(order_id);
+        assert_eq!(order.buyer, env::predecessor_account_id(), "401");
+        order.disputed = true;
+        self.orders.insert(&order_id, &order);
+        self.emit_event("dispute_order", order_id.to_string());
+    }
+
+    pub fn resolve_dispute(&mut self, order_id: u64, refund_buyer: bool) {
+        //validate sender has permition of ROLE_MODERATOR
+        self.assert_permission("resolve_dispute");
+        let mut order = self.releasable_order(order_id);
+        assert!(order.disputed, "Order is not disputed");
+        self.emit_event("resolve_dispute", format!("{} {}", order_id, refund_buyer));
+        if refund_buyer {
+            self.safe_transfer(order.buyer.clone(), order.amount);
+            self.log_refund(order_id, &order, order.quantity, order.amount);
+            order.status = OrderStatus::Refunded;
+            order.released = true;
+            self.close_order(order_id, &order);
+        } else {
+            self.release_escrow_to_vendor(order_id, order);
+        }
+    }
+
+    // refunds `quantity` units of an order still holding escrow, units already taken from stock go back to it
+    pub fn refund_order_units(&mut self, order_id: u64, quantity: u8) {
+        let mut order = self.orders.get(&order_id).expect("Order does not exist");
+        let mut item = self.load_product(&order.address).expect("Product does not exist");
+        self.assert_creator_or_override(&item, false);
+        let open = matches!(order.status, OrderStatus::Preordered | OrderStatus::Backordered);
+        assert!(open || (order.status == OrderStatus::Fulfilled && !order.released), "Order holds no escrow to refund");
+        assert!(quantity > 0 && quantity <= order.quantity, "Invalid refund quantity");
+
+        let refund = order.amount * quantity as u128 / order.quantity as u128;
+        self.safe_transfer(order.buyer.clone(), refund);
+        self.log_refund(order_id, &order, quantity, refund);
+        if order.status == OrderStatus::Fulfilled {
+            let old_stock = item.stock;
+            item.stock = old_stock.checked_add(quantity).expect("Stock out of range");
+            self.save_product(&order.address, &mut item);
+            self.notify_listeners(&order.address, "refund_order_units");
+            self.check_stock_thresholds(&order.address, old_stock, item.stock);
+            self.record_stock_movement(&order.address, old_stock, item.stock, StockReason::Refund);
+        }
+        order.quantity -= quantity;
+        order.amount -= refund;
+        self.emit_event("refund_order_units", format!("{} {} {}", order_id, quantity, refund));
+        if order.quantity > 0 {
+            self.orders.insert(&order_id, &order);
+        } else {
+            order.status = OrderStatus::Refunded;
+            order.released = !open;
+            self.close_order(order_id, &order);
+        }
+    }
+
+    fn releasable_order(&self, order_id: u64) -> Order {
+        let order = self.orders.get(&order_id).expect("Order does not exist");
+        assert!(order.status == OrderStatus::Fulfilled, "Order is not fulfilled");
+        assert!(!order.released, "Escrow is already released");
+        order
+    }
+
+    fn release_escrow_to_vendor(&mut self, order_id: u64, mut order: Order) {
+        order.payouts = self.settle_sale(&order.address, order.vendor.clone(), &order.buyer, order.amount, &order.affiliate);
+        order.released = true;
+        self.emit_event("release_escrow", order_id.to_string());
+        self.close_order(order_id, &order);
+    }
+}
+
+// orders
+#[near_bindgen]
+impl Product{
+    pub fn get_order(&self, order_id: u64) -> Option<Order> {
+        self.orders.get(&order_id)
+    }
+
+    // orders still holding escrow oldest first, fulfilled orders stay listed until their escrow is released
+    pub fn get_product_orders(&self, address: String) -> Vec<(u64, Order)> {
+        self.product_orders.get(&address).unwrap_or_default().into_iter()
+            .filter_map(|order_id| self.orders.get(&order_id).map(|order| (order_id, order)))
+            .collect()
+    }
+
+    pub(crate) fn place_order(&mut self, buyer: &AccountId, address: &String, quantity: u8, amount: Balance, tip: Balance, status: OrderStatus) -> u64 {
+        let order_id = self.next_order_id;
+        self.next_order_id += 1;
+        let order = Order {
+            address: address.clone(),
+            buyer: buyer.clone(),
+            quantity,
+            amount,
+            status,
+            created_at: env::block_timestamp(),
+            shipping: None,
+            vendor: self.records.get(address).unwrap().creator,
+            released: false,
+            disputed: false,
+            payouts: Vec::new(),
+            tip,
+            affiliate: None,
+            experiment_bucket: None,
+            due_at: None,
+        };
+        self.emit_event("place_order", format!("{} {:?}", order_id, order));
+        self.orders.insert(&order_id, &order);
+        let mut open = self.product_orders.get(address).unwrap_or_default();
+        open.push(order_id);
+        self.product_orders.insert(address, &open);
+        order_id
+    }
+
+    pub(crate) fn open_orders(&self, address: &String, status: OrderStatus, limit: u64) -> Vec<u64> {
+        self.product_orders.get(address).unwrap_or_default().into_iter()
+            .filter(|order_id| self.orders.get(order_id).map(|order| order.status == status).unwrap_or(false))
+            .take(std::cmp::min(limit, MAX_ORDERS_PER_CALL) as usize)
+            .collect()
+    }
+
+    pub(crate) fn refund_order(&mut self, order_id: u64) {
+        let mut order = self.orders.get(&order_id).expect("Order does not exist");
+        self.safe_transfer(order.buyer.clone(), order.amount);
+        self.log_refund(order_id, &order, order.quantity, order.amount);
+        order.status = OrderStatus::Refunded;
+        self.close_order(order_id, &order);
+    }
+
+    // settled orders leave the per-product open list but stay readable by id, a fulfilled order
+    // stays on it until its escrow is released or refunded
+    pub(crate) fn close_order(&mut self, order_id: u64, order: &Order) {
+        self.orders.insert(&order_id, order);
+        self.emit_event("close_order", format!("{} {:?}", order_id, order.status));
+        if order.holds_escrow() {
+            return;
+        }
+        let mut open = self.product_orders.get(&order.address).unwrap_or_default();
+        open.retain(|id| *id != order_id);
+        if open.is_empty() {
+            self.product_orders.remove(&order.address);
+        } else {
+            self.product_orders.insert(&order.address, &open);
+        }
+    }
+}
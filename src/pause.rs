@@ -0,0 +1,36 @@
+use near_sdk::{env, near_bindgen};
+use near_contract_standards::upgrade::Ownable;
+
+use crate::Product;
+use crate::ProductContract;
+
+// emergency stop, a guardian can halt sales, offers, bids, subscription charges, product writes and payouts
+// but only the owner lifts it; withdrawing offers and cancelling subscriptions stay open so buyers can get their funds back
+#[near_bindgen]
+impl Product{
+    pub fn pause(&mut self) {
+        //validate sender has permition of ROLE_GUARDIAN
+        if env::predecessor_account_id() != self.owner {
+            self.assert_permission("pause");
+        }
+        assert!(!self.paused, "Contract is already paused");
+        self.audit("pause", "");
+        self.paused = true;
+    }
+
+    pub fn unpause(&mut self) {
+        //validate if owner
+        self.assert_owner();
+        assert!(self.paused, "Contract is not paused");
+        self.audit("unpause", "");
+        self.paused = false;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub(crate) fn assert_not_paused(&self) {
+        assert!(!self.paused, "Contract is paused");
+    }
+}
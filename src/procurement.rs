@@ -0,0 +1,173 @@
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Serialize, Deserialize};
+use near_sdk::{env, near_bindgen, AccountId, Balance};
+use near_contract_standards::upgrade::Ownable;
+
+use crate::Product;
+use crate::ProductContract;
+use crate::history::StockReason;
+
+const MAX_PURCHASE_ORDER_LINES:usize = 50;
+
+#[derive(Debug, Clone, PartialEq)]
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub enum PurchaseOrderStatus {
+    Open,
+    // the supplier confirmed it will ship
+    Acknowledged,
+    Fulfilled,
+}
+
+#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PurchaseOrderLine {
+     pub address: String,
+     pub quantity: u32,
+     pub unit_cost: Balance,
+}
+
+// a line that arrived short, over, or without being ordered at all
+#[derive(Debug, Clone, PartialEq)]
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Discrepancy {
+     pub address: String,
+     pub expected: u32,
+     pub received: u32,
+}
+
+// stock ordered from a registered supplier, settled off-chain
+#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PurchaseOrder {
+     pub supplier: AccountId,
+     pub lines: Vec<PurchaseOrderLine>,
+     pub status: PurchaseOrderStatus,
+     pub created_by: AccountId,
+     pub created_at: u64,
+     pub discrepancies: Vec<Discrepancy>,
+}
+
+impl PurchaseOrder {
+    pub fn total_cost(&self) -> Balance {
+        self.lines.iter().map(|line| line.unit_cost * line.quantity as u128).sum()
+    }
+}
+
+// suppliers
+#[near_bindgen]
+impl Product{
+    pub fn add_supplier(&mut self, account: AccountId) {
+        //validate if owner
+        self.assert_owner();
+        self.audit("add supplier", &account);
+        self.suppliers.insert(&account);
+    }
+
+    pub fn remove_supplier(&mut self, account: AccountId) {
+        //validate if owner
+        self.assert_owner();
+        self.audit("remove supplier", &account);
+        self.suppliers.remove(&account);
+    }
+
+    pub fn is_supplier(&self, account: AccountId) -> bool {
+        self.suppliers.contains(&account)
+    }
+}
+
+// purchase orders
+#[near_bindgen]
+impl Product{
+    pub fn create_purchase_order(&mut self, supplier: AccountId, lines: Vec<PurchaseOrderLine>) -> u64 {
+        //validate sender has permition of ROLE_WAREHOUSE
+        self.assert_permission("create_purchase_order");
+        assert!(self.suppliers.contains(&supplier), "Supplier is not registered");
+        assert!(!lines.is_empty(), "Purchase order has no lines");
+        assert!(lines.len() <= MAX_PURCHASE_ORDER_LINES, "Too many purchase order lines");
+        for line in lines.iter() {
+            assert!(self.records.contains_key(&line.address), "Product does not exist");
+            assert!(line.quantity > 0, "Quantity must be positive");
+        }
+
+        let po_id = self.next_purchase_order_id;
+        self.next_purchase_order_id += 1;
+        let order = PurchaseOrder {
+            supplier,
+            lines,
+            status: PurchaseOrderStatus::Open,
+            created_by: env::signer_account_id(),
+            created_at: env::block_timestamp(),
+            discrepancies: Vec::new(),
+        };
+        self.emit_event("create_purchase_order", format!("{} {:?}", po_id, order));
+        self.purchase_orders.insert(&po_id, &order);
+        po_id
+    }
+
+    pub fn acknowledge_purchase_order(&mut self, po_id: u64) {
+        let mut order = self.purchase_orders.get(&po_id).expect("Purchase order does not exist");
+        assert_eq!(order.supplier, env::predecessor_account_id(), "401");
+        assert!(order.status == PurchaseOrderStatus::Open, "Purchase order is not open");
+        order.status = PurchaseOrderStatus::Acknowledged;
+        self.purchase_orders.insert(&po_id, &order);
+        self.emit_event("acknowledge_purchase_order", po_id.to_string());
+    }
+
+    // the goods arrived as ordered
+    pub fn fulfill_purchase_order(&mut self, po_id: u64) {
+        let order = self.purchase_orders.get(&po_id).expect("Purchase order does not exist");
+        let received = order.lines.into_iter().map(|line| (line.address, line.quantity)).collect();
+        self.receive_shipment(po_id, received);
+    }
+
+    // stocks what actually arrived, fills queued backorders and closes the order, differences to the
+    // order are kept on it
+    pub fn receive_shipment(&mut self, po_id: u64, received: Vec<(String, u32)>) -> Vec<Discrepancy> {
+        //validate sender has permition of ROLE_WAREHOUSE
+        self.assert_permission("receive_shipment");
+        let mut order = self.purchase_orders.get(&po_id).expect("Purchase order does not exist");
+        assert!(order.status == PurchaseOrderStatus::Acknowledged, "Purchase order is not acknowledged");
+        assert!(received.len() <= MAX_PURCHASE_ORDER_LINES, "Too many purchase order lines");
+
+        let mut totals: Vec<(String, u32, u32)> = order.lines.iter().map(|line| (line.address.clone(), line.quantity, 0)).collect();
+        self.begin_batch();
+        for (address, quantity) in received {
+            if quantity > 0 {
+                let item = self.load_product(&address).expect("Product does not exist");
+                self.assert_editable(&item);
+                let new_stock = (item.stock as u32).checked_add(quantity)
+                    .filter(|stock| *stock <= u8::MAX as u32)
+                    .expect("Stock out of range") as u8;
+                self.internal_set_stock(&address, item, new_stock, StockReason::Receiving);
+                self.fulfill_restock_requests(&address, quantity);
+                self.fill_backorders(&address);
+                self.emit_event("receive_shipment", format!("{} {} {}", po_id, address, quantity));
+            }
+            match totals.iter_mut().find(|(line_address, _, _)| *line_address == address) {
+                Some(total) => total.2 += quantity,
+                None => totals.push((address, 0, quantity)),
+            }
+        }
+        self.flush_batch("receive_shipment");
+
+        order.discrepancies = totals.into_iter()
+            .filter(|(_, expected, received)| expected != received)
+            .map(|(address, expected, received)| Discrepancy { address, expected, received })
+            .collect();
+        for discrepancy in order.discrepancies.iter() {
+            self.emit_event("receiving_discrepancy", format!("{} {:?}", po_id, discrepancy));
+        }
+        order.status = PurchaseOrderStatus::Fulfilled;
+        self.purchase_orders.insert(&po_id, &order);
+        self.emit_event("fulfill_purchase_order", format!("{} {}", po_id, order.total_cost()));
+        order.discrepancies
+    }
+
+    pub fn get_purchase_order(&self, po_id: u64) -> Option<PurchaseOrder> {
+        self.purchase_orders.get(&po_id)
+    }
+}
@@ -0,0 +1,502 @@
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Serialize, Deserialize};
+use near_sdk::json_types::U128;
+use near_sdk::{env, ext_contract, near_bindgen, AccountId, Balance, PromiseOrValue, PromiseResult};
+use near_contract_standards::upgrade::Ownable;
+
+use crate::{Item, Product};
+use crate::catalog::{LifecycleState, Visibility};
+use crate::gating::{Checkout, PURCHASE_CALLBACK_GAS};
+use crate::history::StockReason;
+use crate::moderation::Approval;
+use crate::orders::OrderStatus;
+use crate::ProductContract;
+use crate::revenue::{ext_wnear, ONE_YOCTO, WNEAR_GAS};
+
+const MAX_SPLIT_BPS:u16 = 10_000;
+const MAX_SPLIT_BENEFICIARIES:usize = 10;
+
+const MAX_PURCHASE_WINDOWS:usize = 50;
+// the rolling limit window is tracked in this many buckets
+pub const LIMIT_BUCKETS:u64 = 24;
+
+// purchases are accepted between start and end, both block timestamps in nanoseconds
+#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PurchaseWindow {
+     pub start: u64,
+     pub end: u64,
+}
+
+// a purchase that passed every check, with the discount it was quoted
+struct PurchasePlan {
+     item: Item,
+     status: Option<OrderStatus>,
+     bucket: Option<(u8, Balance)>,
+     campaign: Option<(u64, Balance)>,
+     total: Balance,
+}
+
+// purchases
+#[near_bindgen]
+impl Product{
+    // returns the order id, direct purchases get one too for shipping and as the record of what was paid
+    #[payable]
+    pub fn buy_product(&mut self, address: String, quantity: u8) -> u64 {
+        self.buy_product_with_tip(address, quantity, U128(0))
+    }
+
+    // the tip goes straight to the vendor on top of the price, no store fee or split applies
+    #[payable]
+    pub fn buy_product_with_tip(&mut self, address: String, quantity: u8, tip: U128) -> u64 {
+        let (order_id, spent) = self.internal_buy(&env::predecessor_account_id(), address, quantity, tip.0, env::attached_deposit(), Checkout::default());
+        self.refund_surplus(spent);
+        order_id
+    }
+
+    // the purchase pipeline shared by NEAR and token payments, returns the order id and how much of payment it used
+    pub(crate) fn internal_buy(&mut self, buyer: &AccountId, address: String, quantity: u8, tip: Balance, payment: Balance, checkout: Checkout) -> (u64, Balance) {
+        match self.try_buy(buyer, address, quantity, tip, payment, checkout) {
+            Ok(bought) => bought,
+            Err(error) => env::panic(error.as_bytes()),
+        }
+    }
+
+    // every check runs before the first write, so a callback can refund an Err instead of keeping the deposit
+    pub(crate) fn try_buy(&mut self, buyer: &AccountId, address: String, quantity: u8, tip: Balance, payment: Balance, checkout: Checkout) -> Result<(u64, Balance), String> {
+        let PurchasePlan { mut item, status, bucket, campaign, total } = self.plan_buy(buyer, &address, quantity, tip, payment, &checkout)?;
+        if item.max_per_buyer > 0 {
+            self.record_units_bought(buyer, &address, &item, quantity);
+        }
+        if let Some((campaign_id, discount)) = campaign {
+            self.charge_campaign(campaign_id, &address, discount);
+        }
+        self.record_account_activity(buyer, total, quantity);
+        if let Some((bucket, _)) = bucket {
+            self.record_experiment_sale(&address, buyer, bucket, quantity, total);
+        }
+        if tip > 0 {
+            self.pay_tip(&item.creator, tip);
+        }
+
+        // physical stock is only taken when the pre-order is converted or the backorder filled
+        let direct = status.is_none();
+        if direct {
+            self.emit_event("buy_product", format!("{} {} {} {}", address, quantity, buyer, total));
+        }
+        let order_id = self.place_order(buyer, &address, quantity, total, tip, status.unwrap_or(OrderStatus::Fulfilled));
+        let mut order = self.orders.get(&order_id).unwrap();
+        order.affiliate = checkout.affiliate;
+        order.experiment_bucket = bucket.map(|(bucket, _)| bucket);
+        self.log_purchase(if direct { "direct" } else { "order" }, &address, buyer, quantity, total, Some(order_id));
+        if direct {
+            self.take_stock(&address, &mut item, quantity);
+        }
+        if direct && !self.config().escrow_direct_sales {
+            // paid out at checkout, the order stays for shipping and as the record of what was paid
+            order.payouts = self.settle_sale(&address, item.creator, buyer, total, &order.affiliate);
+            order.released = true;
+            self.close_order(order_id, &order);
+        } else {
+            self.orders.insert(&order_id, &order);
+        }
+        Ok((order_id, total + tip))
+    }
+
+    // reads only, what try_buy is about to charge and record
+    fn plan_buy(&self, buyer: &AccountId, address: &String, quantity: u8, tip: Balance, payment: Balance, checkout: &Checkout) -> Result<PurchasePlan, String> {
+        if quantity == 0 {
+            return Err("Quantity must be positive".to_string());
+        }
+        let item = self.load_product(address).ok_or_else(|| "Product does not exist".to_string())?;
+        if checkout.gate_cleared {
+            self.check_open_to(buyer, address, &item)?;
+        } else {
+            self.check_can_buy(buyer, address, &item)?;
+        }
+        if self.is_migrating() {
+            return Err("Storage migration in progress".to_string());
+        }
+        let status = if item.preorder_until.is_some() {
+            Some(OrderStatus::Preordered)
+        // a queue already waiting goes first, even if this purchase would fit the stock
+        } else if item.backorders && (item.stock < quantity || !self.open_orders(address, OrderStatus::Backordered, 1).is_empty()) {
+            Some(OrderStatus::Backordered)
+        } else {
+            None
+        };
+        if status.is_none() && item.stock < quantity {
+            return Err("Not enough stock".to_string());
+        }
+        if let Some(code) = &checkout.affiliate {
+            self.check_affiliate_code(code, buyer)?;
+        }
+        if item.max_per_buyer > 0 {
+            self.check_units_bought(buyer, address, &item, quantity)?;
+        }
+        let bucket = self.price_bucket(address, buyer);
+        let listed = bucket.map(|(_, price)| price).unwrap_or(item.price) * quantity as u128;
+        // discounts do not stack, the best one applies
+        let discount_bps = std::cmp::max(checkout.discount_bps, self.tier_discount(buyer));
+        let mut discount = listed * discount_bps as u128 / 10_000;
+        let mut campaign = None;
+        if let Some((campaign_id, campaign_discount)) = self.best_campaign(address, listed) {
+            if campaign_discount > discount {
+                discount = campaign_discount;
+                campaign = Some((campaign_id, discount));
+            }
+        }
+        let minimum = listed - discount;
+        let total = if item.pay_what_you_want { payment.saturating_sub(tip) } else { minimum };
+        if total < minimum || payment < total + tip {
+            return Err("Attached deposit does not cover the price".to_string());
+        }
+        self.check_account_limits(buyer, total, quantity)?;
+        Ok(PurchasePlan { item, status, bucket, campaign, total })
+    }
+
+    pub fn set_pay_what_you_want(&mut self, address: String, enabled: bool) {
+        let mut item = self.load_product(&address).expect("Product does not exist");
+        self.assert_editable(&item);
+        self.assert_creator_or_override(&item, false);
+        item.pay_what_you_want = enabled;
+        self.save_product(&address, &mut item);
+        self.emit_event("set_pay_what_you_want", format!("{} {}", address, enabled));
+    }
+
+    pub fn get_vendor_tips(&self, vendor: AccountId) -> U128 {
+        U128(self.vendor_tips.get(&vendor).unwrap_or(0))
+    }
+
+    fn pay_tip(&mut self, vendor: &AccountId, tip: Balance) {
+        self.safe_transfer(vendor.clone(), tip);
+        let tips = self.vendor_tips.get(vendor).unwrap_or(0) + tip;
+        self.vendor_tips.insert(vendor, &tips);
+        self.emit_event("tip", format!("{} {}", vendor, tip));
+    }
+
+    // buyer side entry points, the caller must also be invited to private listings;
+    // token gated listings are only sold through buy_gated_product
+    pub(crate) fn assert_can_buy(&self, buyer: &AccountId, address: &String, item: &Item) {
+        if let Err(error) = self.check_can_buy(buyer, address, item) {
+            env::panic(error.as_bytes());
+        }
+    }
+
+    pub(crate) fn assert_open_to(&self, buyer: &AccountId, address: &String, item: &Item) {
+        if let Err(error) = self.check_open_to(buyer, address, item) {
+            env::panic(error.as_bytes());
+        }
+    }
+
+    pub(crate) fn assert_purchasable(&self, item: &Item) {
+        if let Err(error) = self.check_purchasable(item) {
+            env::panic(error.as_bytes());
+        }
+    }
+
+    fn check_can_buy(&self, buyer: &AccountId, address: &String, item: &Item) -> Result<(), String> {
+        if self.token_gates.contains_key(address) {
+            return Err("Product is token gated".to_string());
+        }
+        self.check_open_to(buyer, address, item)
+    }
+
+    fn check_open_to(&self, buyer: &AccountId, address: &String, item: &Item) -> Result<(), String> {
+        self.check_purchasable(item)?;
+        if item.visibility == Visibility::Private && !self.invitees.get(address).unwrap_or_default().contains(buyer) {
+            return Err("Product is only sold to invitees".to_string());
+        }
+        Ok(())
+    }
+
+    fn check_purchasable(&self, item: &Item) -> Result<(), String> {
+        let error = if self.is_paused() {
+            "Contract is paused"
+        } else if !self.config().purchase_enabled {
+            "Purchases are disabled"
+        } else if !self.is_store_open() {
+            "Store is closed for purchases"
+        } else if item.frozen {
+            "Product is frozen"
+        } else if item.state != LifecycleState::Active {
+            "Product is not for sale"
+        } else if item.approval != Approval::Approved {
+            "Product is not approved"
+        } else if !self.is_rate_fresh(item) {
+            "Exchange rate is stale"
+        } else {
+            return Ok(());
+        };
+        Err(error.to_string())
+    }
+
+    // bookkeeping of a settled sale, the caller checked the stock and emits its own event
+    pub(crate) fn take_stock(&mut self, address: &String, item: &mut Item, quantity: u8) {
+        let old_stock = item.stock;
+        item.stock -= quantity;
+        self.notify_listeners(address, "buy_product");
+        self.check_stock_thresholds(address, old_stock, item.stock);
+        self.record_stock_movement(address, old_stock, item.stock, StockReason::Sale);
+        self.record_sale(address, quantity);
+        self.save_product(address, item);
+    }
+
+    // the store fee goes to the treasury, the rest accrues to the vendor or the product's split table
+    // until withdrawn; rounding dust of a split stays with the vendor
+    pub(crate) fn pay_vendor(&mut self, address: &str, vendor: AccountId, buyer: &AccountId, total: Balance) -> Vec<(AccountId, Balance)> {
+        let config = self.config();
+        let fee = if self.fee_exemptions.contains(&vendor) || self.fee_exemptions.contains(buyer) {
+            0
+        } else {
+            std::cmp::min(total, total * config.fee_bps as u128 / 10_000 + config.flat_fee)
+        };
+        if fee > 0 {
+            self.safe_transfer(self.treasury.clone(), fee);
+        }
+        let proceeds = total - fee;
+        let mut payouts: Vec<(AccountId, Balance)> = self.payment_splits.get(&address.to_string()).unwrap_or_default().into_iter()
+            .map(|(account, bps)| (account, proceeds * bps as u128 / MAX_SPLIT_BPS as u128))
+            .collect();
+        let dust = proceeds - payouts.iter().map(|(_, amount)| amount).sum::<Balance>();
+        match payouts.iter_mut().find(|(account, _)| *account == vendor) {
+            Some(payout) => payout.1 += dust,
+            None if dust > 0 => payouts.push((vendor, dust)),
+            None => {},
+        }
+        for (account, amount) in payouts.iter() {
+            if *amount > 0 {
+                self.credit_revenue(account, *amount);
+            }
+        }
+        if self.payment_splits.contains_key(&address.to_string()) {
+            self.emit_event("split_payment", format!("{} {:?}", address, payouts));
+        }
+        payouts
+    }
+}
+
+// fee exemptions
+#[near_bindgen]
+impl Product{
+    // vendors or buyers settling without store fees, e.g. charities and internal accounts
+    pub fn add_fee_exemption(&mut self, account: AccountId) {
+        //validate if owner
+        self.assert_owner();
+        self.audit("add fee exemption", &account);
+        self.fee_exemptions.insert(&account);
+    }
+
+    pub fn remove_fee_exemption(&mut self, account: AccountId) {
+        //validate if owner
+        self.assert_owner();
+        self.audit("remove fee exemption", &account);
+        self.fee_exemptions.remove(&account);
+    }
+
+    pub fn is_fee_exempt(&self, account: AccountId) -> bool {
+        self.fee_exemptions.contains(&account)
+    }
+}
+
+// payment splits
+#[near_bindgen]
+impl Product{
+    // shares in basis points adding up to 10000, an empty table pays the vendor alone
+    pub fn set_payment_split(&mut self, address: String, split: Vec<(AccountId, u16)>) {
+        let item = self.load_product(&address).expect("Product does not exist");
+        self.assert_editable(&item);
+        self.assert_creator_or_override(&item, false);
+        if split.is_empty() {
+            self.payment_splits.remove(&address);
+        } else {
+            assert!(split.len() <= MAX_SPLIT_BENEFICIARIES, "Too many beneficiaries");
+            assert_eq!(MAX_SPLIT_BPS as u32, split.iter().map(|(_, bps)| *bps as u32).sum::<u32>(), "Split must add up to 10000 basis points");
+            self.payment_splits.insert(&address, &split);
+        }
+        self.emit_event("set_payment_split", format!("{} {:?}", address, split));
+    }
+
+    pub fn get_payment_split(&self, address: String) -> Vec<(AccountId, u16)> {
+        self.payment_splits.get(&address).unwrap_or_default()
+    }
+}
+
+// what a buyer puts in the msg of ft_transfer_call; the store's codes are affiliate codes, a coupon or
+// a referrer is the code buy_product_with_code takes
+#[derive(Debug)]
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PurchaseIntent {
+     pub address: String,
+     pub quantity: u8,
+     pub coupon: Option<String>,
+     pub referrer: Option<String>,
+}
+
+impl PurchaseIntent {
+    fn affiliate(&self) -> Option<String> {
+        match (&self.coupon, &self.referrer) {
+            (Some(coupon), Some(referrer)) if coupon != referrer => env::panic(b"Only one code applies to a purchase"),
+            (coupon, referrer) => coupon.clone().or_else(|| referrer.clone()),
+        }
+    }
+}
+
+#[ext_contract(ext_purchase)]
+pub trait PurchaseCallbacks {
+    fn on_ft_payment_unwrapped(&mut self, buyer: AccountId, intent: PurchaseIntent, amount: U128, spent: U128) -> U128;
+}
+
+// token purchases
+#[near_bindgen]
+impl Product{
+    // NEP-141 receiver, only wNEAR is accepted and priced 1:1 with NEAR; the price is unwrapped
+    // first and the sale settles in NEAR once it is back, the rest is returned as unused
+    pub fn ft_on_transfer(&mut self, sender_id: AccountId, amount: U128, msg: String) -> PromiseOrValue<U128> {
+        let token = env::predecessor_account_id();
+        assert!(self.config().wnear_contract.as_ref() == Some(&token), "Token is not accepted");
+        let intent: PurchaseIntent = near_sdk::serde_json::from_str(&msg).expect("Invalid purchase intent");
+        let checkout = Checkout { affiliate: intent.affiliate(), ..Default::default() };
+        let spent = match self.plan_buy(&sender_id, &intent.address, intent.quantity, 0, amount.0, &checkout) {
+            Ok(plan) => plan.total,
+            Err(error) => env::panic(error.as_bytes()),
+        };
+        ext_wnear::near_withdraw(U128(spent), &token, ONE_YOCTO, WNEAR_GAS)
+            .then(ext_purchase::on_ft_payment_unwrapped(sender_id, intent, amount, U128(spent), &env::current_account_id(), 0, PURCHASE_CALLBACK_GAS))
+            .into()
+    }
+
+    // a failed unwrap returns every token; a purchase refused by now is refunded in NEAR
+    #[private]
+    pub fn on_ft_payment_unwrapped(&mut self, buyer: AccountId, intent: PurchaseIntent, amount: U128, spent: U128) -> U128 {
+        if let PromiseResult::Failed = env::promise_result(0) {
+            self.emit_event("ft_purchase_failed", format!("{} {} {}", intent.address, buyer, spent.0));
+            return amount;
+        }
+        let checkout = Checkout { affiliate: intent.affiliate(), ..Default::default() };
+        self.settle_checked_purchase(buyer, intent.address, intent.quantity, spent.0, checkout);
+        U128(amount.0 - spent.0)
+    }
+}
+
+// flash-sale per-buyer caps
+#[near_bindgen]
+impl Product{
+    // counted over every purchase of the account, so a drop cannot be swept by one buyer
+    pub fn set_max_per_buyer(&mut self, address: String, max_per_buyer: u32) {
+        let mut item = self.load_product(&address).expect("Product does not exist");
+        self.assert_editable(&item);
+        self.assert_creator_or_override(&item, false);
+        item.max_per_buyer = max_per_buyer;
+        self.save_product(&address, &mut item);
+        self.emit_event("set_max_per_buyer", format!("{} {}", address, max_per_buyer));
+    }
+
+    pub fn get_units_bought(&self, address: String, buyer: AccountId) -> u32 {
+        self.units_bought.get(&(address, buyer)).unwrap_or(0)
+    }
+
+    pub(crate) fn record_units_bought(&mut self, buyer: &AccountId, address: &str, item: &Item, quantity: u8) {
+        match self.check_units_bought(buyer, address, item, quantity) {
+            Ok(bought) => self.units_bought.insert(&(address.to_string(), buyer.clone()), &bought),
+            Err(error) => env::panic(error.as_bytes()),
+        };
+    }
+
+    // the buyer's running total including this purchase
+    fn check_units_bought(&self, buyer: &AccountId, address: &str, item: &Item, quantity: u8) -> Result<u32, String> {
+        let bought = self.units_bought.get(&(address.to_string(), buyer.clone())).unwrap_or(0) + quantity as u32;
+        if bought > item.max_per_buyer {
+            return Err(format!("Purchase exceeds the per-buyer limit of {}", item.max_per_buyer));
+        }
+        Ok(bought)
+    }
+}
+
+// spending and units of one account per bucket, oldest first
+#[derive(Debug, Clone, Default)]
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct AccountActivity {
+     buckets: Vec<(u64, Balance, u32)>,
+}
+
+// store-wide per-account purchase limits
+#[near_bindgen]
+impl Product{
+    // spent and units bought by the account within the current window
+    pub fn get_account_activity(&self, account: AccountId) -> (U128, u32) {
+        let config = self.config();
+        let since = env::block_timestamp().saturating_sub(config.limit_window);
+        let bucket = config.limit_window / LIMIT_BUCKETS;
+        self.account_activity.get(&account).unwrap_or_default().buckets.iter()
+            .filter(|(start, _, _)| start + bucket > since)
+            .fold((U128(0), 0), |(spent, units), (_, amount, quantity)| (U128(spent.0 + amount), units + quantity))
+    }
+
+    pub(crate) fn record_account_activity(&mut self, buyer: &AccountId, total: Balance, quantity: u8) {
+        let config = self.config();
+        if config.account_spend_limit == 0 && config.account_quantity_limit == 0 {
+            return;
+        }
+        if let Err(error) = self.check_account_limits(buyer, total, quantity) {
+            env::panic(error.as_bytes());
+        }
+
+        let now = env::block_timestamp();
+        let size = config.limit_window / LIMIT_BUCKETS;
+        let start = now - now % size;
+        let mut activity = self.account_activity.get(buyer).unwrap_or_default();
+        activity.buckets.retain(|(bucket, _, _)| bucket + config.limit_window > start);
+        match activity.buckets.last_mut() {
+            Some((bucket, amount, bought)) if *bucket == start => {
+                *amount += total;
+                *bought += quantity as u32;
+            },
+            _ => activity.buckets.push((start, total, quantity as u32)),
+        }
+        self.account_activity.insert(buyer, &activity);
+    }
+
+    fn check_account_limits(&self, buyer: &AccountId, total: Balance, quantity: u8) -> Result<(), String> {
+        let config = self.config();
+        if config.account_spend_limit == 0 && config.account_quantity_limit == 0 {
+            return Ok(());
+        }
+        let (spent, units) = self.get_account_activity(buyer.clone());
+        if config.account_spend_limit > 0 && spent.0 + total > config.account_spend_limit {
+            return Err("SPEND_LIMIT_EXCEEDED".to_string());
+        }
+        if config.account_quantity_limit > 0 && units + quantity as u32 > config.account_quantity_limit {
+            return Err("QUANTITY_LIMIT_EXCEEDED".to_string());
+        }
+        Ok(())
+    }
+}
+
+// store hours / purchase windows
+#[near_bindgen]
+impl Product{
+    // an empty schedule keeps the store always open
+    pub fn set_purchase_windows(&mut self, windows: Vec<PurchaseWindow>) {
+        //validate if owner
+        self.assert_owner();
+        assert!(windows.len() <= MAX_PURCHASE_WINDOWS, "Too many purchase windows");
+        for window in windows.iter() {
+            assert!(window.start < window.end, "Purchase window must end after it starts");
+        }
+        self.emit_event("set_purchase_windows", format!("{:?}", windows));
+        self.purchase_windows = windows;
+    }
+
+    pub fn get_purchase_windows(&self) -> Vec<PurchaseWindow> {
+        self.purchase_windows.clone()
+    }
+
+    pub fn is_store_open(&self) -> bool {
+        let now = env::block_timestamp();
+        self.purchase_windows.is_empty()
+            || self.purchase_windows.iter().any(|window| window.start <= now && now < window.end)
+    }
+}
@@ -0,0 +1,244 @@
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Serialize, Deserialize};
+use near_sdk::json_types::U128;
+use near_sdk::{env, ext_contract, near_bindgen, AccountId, Balance, Gas, Promise, PromiseResult};
+
+use crate::Product;
+use crate::ProductContract;
+
+// 10 TGas
+const WITHDRAW_CALLBACK_GAS:Gas = 10_000_000_000_000;
+// 10 TGas
+pub(crate) const WNEAR_GAS:Gas = 10_000_000_000_000;
+// 25 TGas, room to unwrap a failed ft_transfer and settle it
+const WNEAR_SENT_GAS:Gas = 25_000_000_000_000;
+// 40 TGas, sends the wrapped tokens and leaves WNEAR_SENT_GAS to settle them
+const WNEAR_WRAPPED_GAS:Gas = 40_000_000_000_000;
+pub(crate) const ONE_YOCTO:Balance = 1;
+const MAX_PAYOUTS_PER_CALL:u64 = 50;
+const PAYOUT_HISTORY_CAPACITY:usize = 20;
+
+#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Payout {
+     pub amount: Balance,
+     pub paid_at: u64,
+     // swept by process_payouts rather than withdrawn by the vendor
+     pub automatic: bool,
+}
+
+#[ext_contract(ext_self)]
+pub trait RevenueCallbacks {
+    fn on_revenue_withdrawn(&mut self, vendor: AccountId, amount: U128);
+    fn on_transfer_settled(&mut self, account: AccountId, amount: U128);
+    fn on_wnear_wrapped(&mut self, wnear: AccountId, vendor: AccountId, amount: U128);
+    fn on_wnear_sent(&mut self, wnear: AccountId, vendor: AccountId, amount: U128);
+    fn on_wnear_unwrapped(&mut self, vendor: AccountId, amount: U128);
+}
+
+// the wrap.near interface
+#[ext_contract(ext_wnear)]
+pub trait WrappedNear {
+    fn near_deposit(&mut self);
+    fn ft_transfer(&mut self, receiver_id: AccountId, amount: U128, memo: Option<String>);
+    fn near_withdraw(&mut self, amount: U128);
+}
+
+// vendor revenue, accrued on settlement and pulled by the vendor
+#[near_bindgen]
+impl Product{
+    pub fn get_revenue(&self, vendor: AccountId) -> U128 {
+        U128(self.revenue.get(&vendor).unwrap_or(0))
+    }
+
+    // the balance is debited up front and credited back if the transfer fails
+    pub fn withdraw_revenue(&mut self, amount: U128) -> Promise {
+        self.assert_not_paused();
+        let vendor = env::predecessor_account_id();
+        let amount = amount.0;
+        let balance = self.revenue.get(&vendor).unwrap_or(0);
+        assert!(amount > 0 && amount <= balance, "Not enough revenue to withdraw");
+        self.set_revenue(&vendor, balance - amount);
+        self.emit_event("withdraw_revenue", format!("{} {}", vendor, amount));
+        self.send_revenue(vendor, amount, false)
+    }
+
+    #[private]
+    pub fn on_revenue_withdrawn(&mut self, vendor: AccountId, amount: U128) {
+        if let PromiseResult::Failed = env::promise_result(0) {
+            self.credit_revenue(&vendor, amount.0);
+            self.emit_event("withdraw_revenue_failed", format!("{} {}", vendor, amount.0));
+        }
+    }
+
+    fn send_revenue(&mut self, vendor: AccountId, amount: Balance, automatic: bool) -> Promise {
+        let mut history = self.payout_history.get(&vendor).unwrap_or_default();
+        if history.len() >= PAYOUT_HISTORY_CAPACITY {
+            history.remove(0);
+        }
+        history.push(Payout { amount, paid_at: env::block_timestamp(), automatic });
+        self.payout_history.insert(&vendor, &history);
+        match self.config().wnear_contract {
+            // vendors must be storage-registered with the wNEAR contract, each step is settled by its own callback
+            Some(wnear) if self.wnear_vendors.contains(&vendor) => {
+                ext_wnear::near_deposit(&wnear, amount, WNEAR_GAS)
+                    .then(ext_self::on_wnear_wrapped(wnear, vendor, U128(amount), &env::current_account_id(), 0, WNEAR_WRAPPED_GAS))
+            },
+            _ => Promise::new(vendor.clone()).transfer(amount)
+                .then(ext_self::on_revenue_withdrawn(vendor, U128(amount), &env::current_account_id(), 0, WITHDRAW_CALLBACK_GAS)),
+        }
+    }
+
+    // a failed near_deposit returned the NEAR, the revenue is credited back as is
+    #[private]
+    pub fn on_wnear_wrapped(&mut self, wnear: AccountId, vendor: AccountId, amount: U128) {
+        if let PromiseResult::Failed = env::promise_result(0) {
+            self.credit_revenue(&vendor, amount.0);
+            self.emit_event("withdraw_revenue_failed", format!("{} {}", vendor, amount.0));
+            return;
+        }
+        ext_wnear::ft_transfer(vendor.clone(), amount, None, &wnear, ONE_YOCTO, WNEAR_GAS)
+            .then(ext_self::on_wnear_sent(wnear, vendor, amount, &env::current_account_id(), 0, WNEAR_SENT_GAS));
+    }
+
+    // a failed ft_transfer leaves the tokens with the store, they are unwrapped before the revenue is credited back
+    #[private]
+    pub fn on_wnear_sent(&mut self, wnear: AccountId, vendor: AccountId, amount: U128) {
+        if let PromiseResult::Failed = env::promise_result(0) {
+            ext_wnear::near_withdraw(amount, &wnear, ONE_YOCTO, WNEAR_GAS)
+                .then(ext_self::on_wnear_unwrapped(vendor, amount, &env::current_account_id(), 0, WITHDRAW_CALLBACK_GAS));
+        }
+    }
+
+    // the revenue is only owed in NEAR again once the NEAR is back
+    #[private]
+    pub fn on_wnear_unwrapped(&mut self, vendor: AccountId, amount: U128) {
+        match env::promise_result(0) {
+            PromiseResult::Successful(_) => {
+                self.credit_revenue(&vendor, amount.0);
+                self.emit_event("withdraw_revenue_failed", format!("{} {}", vendor, amount.0));
+            },
+            _ => self.emit_event("wnear_unwrap_failed", format!("{} {}", vendor, amount.0)),
+        }
+    }
+
+    pub(crate) fn credit_revenue(&mut self, vendor: &AccountId, amount: Balance) {
+        let balance = self.revenue.get(vendor).unwrap_or(0) + amount;
+        self.set_revenue(vendor, balance);
+    }
+
+    fn set_revenue(&mut self, vendor: &AccountId, balance: Balance) {
+        if balance == 0 {
+            self.revenue.remove(vendor);
+        } else {
+            self.revenue.insert(vendor, &balance);
+        }
+    }
+}
+
+// wNEAR payouts
+#[near_bindgen]
+impl Product{
+    // revenue of opted-in vendors is wrapped and sent as the NEP-141 token
+    pub fn set_wnear_payouts(&mut self, enabled: bool) {
+        let vendor = env::predecessor_account_id();
+        if enabled {
+            assert!(self.config().wnear_contract.is_some(), "wNEAR payouts are not configured");
+            self.wnear_vendors.insert(&vendor);
+        } else {
+            self.wnear_vendors.remove(&vendor);
+        }
+        self.emit_event("set_wnear_payouts", format!("{} {}", vendor, enabled));
+    }
+
+    pub fn get_wnear_payouts(&self, vendor: AccountId) -> bool {
+        self.wnear_vendors.contains(&vendor)
+    }
+}
+
+// scheduled payouts
+#[near_bindgen]
+impl Product{
+    // None opts out, otherwise balances of at least the threshold are swept by process_payouts
+    pub fn set_auto_payout(&mut self, threshold: Option<U128>) {
+        let vendor = env::predecessor_account_id();
+        match threshold {
+            Some(threshold) => {
+                self.auto_payout_vendors.insert(&vendor);
+                self.payout_thresholds.insert(&vendor, &threshold.0);
+            },
+            None => {
+                self.auto_payout_vendors.remove(&vendor);
+                self.payout_thresholds.remove(&vendor);
+            },
+        }
+        self.emit_event("set_auto_payout", format!("{} {:?}", vendor, threshold.map(|threshold| threshold.0)));
+    }
+
+    pub fn get_auto_payout(&self, vendor: AccountId) -> Option<U128> {
+        self.payout_thresholds.get(&vendor).map(U128)
+    }
+
+    // called on a schedule by croncat or anyone else, walks `batch` opted-in vendors from where the last run stopped
+    pub fn process_payouts(&mut self, batch: u64) -> u64 {
+        self.assert_not_paused();
+        let vendors = self.auto_payout_vendors.as_vector();
+        let total = vendors.len();
+        if total == 0 {
+            return 0;
+        }
+        let mut paid = 0;
+        for _ in 0..std::cmp::min(std::cmp::min(batch, MAX_PAYOUTS_PER_CALL), total) {
+            let index = self.payout_cursor % total;
+            self.payout_cursor = (index + 1) % total;
+            let vendor = self.auto_payout_vendors.as_vector().get(index).unwrap();
+            let balance = self.revenue.get(&vendor).unwrap_or(0);
+            let threshold = self.payout_thresholds.get(&vendor).unwrap_or(0);
+            if balance == 0 || balance < threshold {
+                continue;
+            }
+            self.set_revenue(&vendor, 0);
+            self.emit_event("process_payout", format!("{} {}", vendor, balance));
+            self.send_revenue(vendor, balance, true);
+            paid += 1;
+        }
+        paid
+    }
+
+    // newest first
+    pub fn get_payout_history(&self, vendor: AccountId) -> Vec<Payout> {
+        self.payout_history.get(&vendor).unwrap_or_default().into_iter().rev().collect()
+    }
+}
+
+// claimable balances, where transfers that failed wait to be claimed
+#[near_bindgen]
+impl Product{
+    pub fn get_claimable(&self, account: AccountId) -> U128 {
+        U128(self.claimable.get(&account).unwrap_or(0))
+    }
+
+    pub fn claim(&mut self) -> Promise {
+        self.assert_not_paused();
+        let account = env::predecessor_account_id();
+        let amount = self.claimable.remove(&account).expect("Nothing to claim");
+        self.emit_event("claim", format!("{} {}", account, amount));
+        self.safe_transfer(account, amount)
+    }
+
+    #[private]
+    pub fn on_transfer_settled(&mut self, account: AccountId, amount: U128) {
+        if let PromiseResult::Failed = env::promise_result(0) {
+            let balance = self.claimable.get(&account).unwrap_or(0) + amount.0;
+            self.claimable.insert(&account, &balance);
+            self.emit_event("transfer_failed", format!("{} {}", account, amount.0));
+        }
+    }
+
+    // every refund and payment out goes through here, a failed transfer is kept for claim()
+    pub(crate) fn safe_transfer(&self, account: AccountId, amount: Balance) -> Promise {
+        Promise::new(account.clone()).transfer(amount)
+            .then(ext_self::on_transfer_settled(account, U128(amount), &env::current_account_id(), 0, WITHDRAW_CALLBACK_GAS))
+    }
+}
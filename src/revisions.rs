@@ -0,0 +1,75 @@
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Serialize, Deserialize};
+use near_sdk::{env, near_bindgen};
+
+use crate::Product;
+use crate::ProductContract;
+use crate::catalog::LifecycleState;
+
+// the editable part of a listing, staged or kept as the last published version; stock is left out,
+// it keeps moving with sales and restocks and a snapshot of it would oversell
+#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Revision {
+     pub name: String,
+     pub price: u128,
+}
+
+// staged edits of published listings
+#[near_bindgen]
+impl Product{
+    // customers keep seeing the published listing until the revision is published
+    pub fn stage_revision(&mut self, address: String, name: String, price: u128) {
+        //validate sender has permition of ROLE_SET_PRODUCT
+        self.assert_permission("stage_revision");
+        let item = self.load_product(&address).expect("Product does not exist");
+        self.assert_creator_or_override(&item, false);
+        assert!(item.state != LifecycleState::Draft, "Product is a draft, edit it directly");
+        self.assert_editable(&item);
+        assert!(name.len() <= self.config().max_name_len as usize, "Product name is too long");
+        let revision = Revision { name, price };
+        self.emit_event("stage_revision", format!("{} {:?}", address, revision));
+        self.revisions.insert(&address, &revision);
+    }
+
+    pub fn get_revision(&self, address: String) -> Option<Revision> {
+        self.revisions.get(&address)
+    }
+
+    pub fn discard_revision(&mut self, address: String) {
+        let item = self.load_product(&address).expect("Product does not exist");
+        self.assert_creator_or_override(&item, false);
+        self.revisions.remove(&address).expect("No staged revision");
+        self.emit_event("discard_revision", address);
+    }
+
+    // swaps the staged revision in, the version it replaces is kept for rollback
+    pub fn publish_revision(&mut self, address: String) {
+        //validate sender has permition of ROLE_SET_PRODUCT
+        self.assert_permission("publish_revision");
+        let revision = self.revisions.get(&address).expect("No staged revision");
+        self.apply_revision(&address, revision);
+        self.revisions.remove(&address);
+        self.emit_event("publish_revision", address);
+    }
+
+    // one step back, only the version replaced by the last publish is kept
+    pub fn rollback(&mut self, address: String) {
+        //validate sender has permition of ROLE_SET_PRODUCT
+        self.assert_permission("rollback");
+        let previous = self.previous_revisions.get(&address).expect("No previous version");
+        self.apply_revision(&address, previous);
+        self.previous_revisions.remove(&address);
+        self.emit_event("rollback", address);
+    }
+
+    fn apply_revision(&mut self, address: &String, revision: Revision) {
+        if let Err(error) = self.check_product_write(address, &revision.name, 0) {
+            env::panic(error.as_bytes());
+        }
+        let current = self.load_product(address).unwrap();
+        self.previous_revisions.insert(address, &Revision { name: current.name, price: current.price });
+        self.apply_product_write(address, revision.name, revision.price, current.stock, 0, LifecycleState::Active);
+    }
+}
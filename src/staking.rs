@@ -0,0 +1,108 @@
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Serialize, Deserialize};
+use near_sdk::json_types::U128;
+use near_sdk::{env, near_bindgen, AccountId, Balance};
+use near_contract_standards::upgrade::Ownable;
+
+use crate::Product;
+use crate::ProductContract;
+
+const MAX_STAKE_TIERS:usize = 10;
+
+// buyers staking at least min_stake get discount_bps off every purchase
+#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StakeTier {
+     pub min_stake: Balance,
+     pub discount_bps: u16,
+}
+
+// only the locked amount counts towards a tier, unlocking NEAR waits out the cooldown
+#[derive(Debug, Clone, Default)]
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Stake {
+     pub locked: Balance,
+     pub unlocking: Balance,
+     pub unlock_at: u64,
+}
+
+// staking tiers
+#[near_bindgen]
+impl Product{
+    // replaces the whole table, an empty one turns tier discounts off
+    pub fn set_stake_tiers(&mut self, tiers: Vec<StakeTier>) {
+        //validate if owner
+        self.assert_owner();
+        assert!(tiers.len() <= MAX_STAKE_TIERS, "Too many tiers");
+        assert!(tiers.iter().all(|tier| tier.discount_bps <= 10_000), "Discount cannot exceed 10000 basis points");
+        let mut tiers = tiers;
+        tiers.sort_by_key(|tier| tier.min_stake);
+        self.audit("set stake tiers", &format!("{:?}", tiers));
+        self.stake_tiers = tiers;
+    }
+
+    pub fn get_stake_tiers(&self) -> Vec<StakeTier> {
+        self.stake_tiers.clone()
+    }
+
+    #[payable]
+    pub fn stake_for_tier(&mut self) -> Stake {
+        assert!(env::attached_deposit() > 0, "Attach the NEAR to stake");
+        let account = env::predecessor_account_id();
+        let mut stake = self.stakes.get(&account).unwrap_or_default();
+        stake.locked += env::attached_deposit();
+        self.stakes.insert(&account, &stake);
+        self.emit_event("stake", format!("{} {}", account, env::attached_deposit()));
+        stake
+    }
+
+    // starts the cooldown, which restarts for everything still unlocking
+    pub fn unstake(&mut self, amount: U128) -> Stake {
+        let account = env::predecessor_account_id();
+        let mut stake = self.stakes.get(&account).expect("Nothing is staked");
+        assert!(amount.0 > 0 && amount.0 <= stake.locked, "Amount exceeds the locked stake");
+        stake.locked -= amount.0;
+        stake.unlocking += amount.0;
+        stake.unlock_at = env::block_timestamp() + self.config().unstake_cooldown;
+        self.stakes.insert(&account, &stake);
+        self.emit_event("unstake", format!("{} {}", account, amount.0));
+        stake
+    }
+
+    pub fn withdraw_stake(&mut self) -> U128 {
+        self.assert_not_paused();
+        let account = env::predecessor_account_id();
+        let mut stake = self.stakes.get(&account).expect("Nothing is staked");
+        assert!(stake.unlocking > 0, "Nothing is unlocking");
+        assert!(env::block_timestamp() >= stake.unlock_at, "Stake is still cooling down");
+        let amount = stake.unlocking;
+        stake.unlocking = 0;
+        if stake.locked == 0 {
+            self.stakes.remove(&account);
+        } else {
+            self.stakes.insert(&account, &stake);
+        }
+        self.safe_transfer(account.clone(), amount);
+        self.emit_event("withdraw_stake", format!("{} {}", account, amount));
+        U128(amount)
+    }
+
+    pub fn get_stake(&self, account: AccountId) -> Option<Stake> {
+        self.stakes.get(&account)
+    }
+
+    // the highest tier the locked stake reaches, 0 without one
+    pub fn get_tier_discount(&self, account: AccountId) -> u16 {
+        self.tier_discount(&account)
+    }
+
+    pub(crate) fn tier_discount(&self, account: &AccountId) -> u16 {
+        if self.stake_tiers.is_empty() {
+            return 0;
+        }
+        let locked = self.stakes.get(account).map(|stake| stake.locked).unwrap_or(0);
+        self.stake_tiers.iter().rev().find(|tier| locked >= tier.min_stake).map(|tier| tier.discount_bps).unwrap_or(0)
+    }
+}
@@ -0,0 +1,147 @@
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Serialize, Deserialize};
+use near_sdk::{env, near_bindgen, AccountId, Balance};
+
+use crate::Product;
+use crate::ProductContract;
+
+const MAX_CHARGES_PER_CALL:u64 = 100;
+
+// recurring price of a product, charged every interval nanoseconds
+#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SubscriptionPlan {
+     pub address: String,
+     pub vendor: AccountId,
+     pub interval: u64,
+     pub price: Balance,
+     // None is NEAR, fungible tokens are not accepted yet
+     pub token: Option<AccountId>,
+}
+
+// prepaid balance a subscriber keeps topped up, access is paused once a charge cannot be covered
+#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Subscription {
+     pub plan_id: u64,
+     pub subscriber: AccountId,
+     pub balance: Balance,
+     pub next_charge_at: u64,
+     pub active: bool,
+}
+
+// subscription plans
+#[near_bindgen]
+impl Product{
+    pub fn create_subscription_plan(&mut self, address: String, interval: u64, price: Balance, token: Option<AccountId>) -> u64 {
+        let item = self.load_product(&address).expect("Product does not exist");
+        self.assert_creator_or_override(&item, false);
+        assert!(token.is_none(), "Only NEAR subscriptions are supported");
+        assert!(interval > 0, "Interval must be positive");
+
+        let plan_id = self.next_plan_id;
+        self.next_plan_id += 1;
+        let plan = SubscriptionPlan { address, vendor: item.creator, interval, price, token };
+        self.emit_event("create_subscription_plan", format!("{} {:?}", plan_id, plan));
+        self.subscription_plans.insert(&plan_id, &plan);
+        plan_id
+    }
+
+    pub fn get_subscription_plan(&self, plan_id: u64) -> Option<SubscriptionPlan> {
+        self.subscription_plans.get(&plan_id)
+    }
+}
+
+// subscriptions
+#[near_bindgen]
+impl Product{
+    // the attached deposit opens the prepaid balance, the first period is charged by the next run
+    #[payable]
+    pub fn subscribe(&mut self, plan_id: u64) -> u64 {
+        self.assert_not_paused();
+        let plan = self.subscription_plans.get(&plan_id).expect("Plan does not exist");
+        let item = self.load_product(&plan.address).expect("Product does not exist");
+        self.assert_can_buy(&env::predecessor_account_id(), &plan.address, &item);
+        assert!(env::attached_deposit() >= plan.price, "Attach at least one period of the plan");
+
+        let subscription = Subscription {
+            plan_id,
+            subscriber: env::predecessor_account_id(),
+            balance: env::attached_deposit(),
+            next_charge_at: env::block_timestamp(),
+            active: true,
+        };
+        self.emit_event("subscribe", format!("{} {:?}", self.subscriptions.len(), subscription));
+        self.subscriptions.push(&subscription);
+        self.subscriptions.len() - 1
+    }
+
+    // topping up resumes a paused subscription
+    #[payable]
+    pub fn top_up_subscription(&mut self, subscription_id: u64) {
+        self.assert_not_paused();
+        let mut subscription = self.subscriptions.get(subscription_id).expect("Subscription does not exist");
+        let plan = self.subscription_plans.get(&subscription.plan_id).unwrap();
+        subscription.balance += env::attached_deposit();
+        if !subscription.active && subscription.balance >= plan.price {
+            subscription.active = true;
+            subscription.next_charge_at = env::block_timestamp();
+        }
+        self.subscriptions.replace(subscription_id, &subscription);
+    }
+
+    // stops future charges and returns what is left of the balance
+    pub fn cancel_subscription(&mut self, subscription_id: u64) {
+        let mut subscription = self.subscriptions.get(subscription_id).expect("Subscription does not exist");
+        assert_eq!(subscription.subscriber, env::predecessor_account_id(), "401");
+        if subscription.balance > 0 {
+            self.safe_transfer(subscription.subscriber.clone(), subscription.balance);
+        }
+        subscription.balance = 0;
+        subscription.active = false;
+        self.emit_event("cancel_subscription", subscription_id.to_string());
+        self.subscriptions.replace(subscription_id, &subscription);
+    }
+
+    // called on a schedule by croncat or anyone else, walks `batch` subscriptions from where the last run stopped
+    pub fn charge_subscriptions(&mut self, batch: u64) -> u64 {
+        self.assert_not_paused();
+        let total = self.subscriptions.len();
+        if total == 0 {
+            return 0;
+        }
+        let now = env::block_timestamp();
+        let mut charged = 0;
+        for _ in 0..std::cmp::min(std::cmp::min(batch, MAX_CHARGES_PER_CALL), total) {
+            let subscription_id = self.charge_cursor % total;
+            self.charge_cursor = (subscription_id + 1) % total;
+            let mut subscription = self.subscriptions.get(subscription_id).unwrap();
+            if !subscription.active || subscription.next_charge_at > now {
+                continue;
+            }
+            let plan = self.subscription_plans.get(&subscription.plan_id).unwrap();
+            if subscription.balance < plan.price {
+                subscription.active = false;
+                self.emit_event("pause_subscription", subscription_id.to_string());
+            } else {
+                subscription.balance -= plan.price;
+                subscription.next_charge_at += plan.interval;
+                self.pay_vendor(&plan.address, plan.vendor, &subscription.subscriber, plan.price);
+                self.log_purchase("subscription", &plan.address, &subscription.subscriber, 1, plan.price, None);
+                charged += 1;
+            }
+            self.subscriptions.replace(subscription_id, &subscription);
+        }
+        charged
+    }
+
+    pub fn get_subscription(&self, subscription_id: u64) -> Option<Subscription> {
+        self.subscriptions.get(subscription_id)
+    }
+
+    pub fn has_access(&self, subscription_id: u64) -> bool {
+        self.subscriptions.get(subscription_id).map(|subscription| subscription.active).unwrap_or(false)
+    }
+}
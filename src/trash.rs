@@ -0,0 +1,92 @@
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Serialize, Deserialize};
+use near_sdk::{env, near_bindgen, AccountId};
+
+use crate::{Item, Product};
+use crate::ProductContract;
+use crate::utils::pagination::{scan, Page};
+
+const MAX_TRASH_PAGE:u64 = 100;
+
+// a deleted listing kept whole, restorable until restorable_until
+#[derive(Debug)]
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TrashedProduct {
+     pub item: Item,
+     pub deleted_at: u64,
+     pub deleted_by: AccountId,
+     pub restorable_until: u64,
+}
+
+// trash
+#[near_bindgen]
+impl Product{
+    // the listing comes back with its stock, history and settings; the listing deposit was settled at deletion
+    pub fn restore_product(&mut self, address: String) {
+        //validate sender has permition of ROLE_DELETE_PRODUCT
+        self.assert_permission("restore_product");
+        self.assert_not_migrating();
+        let trashed = self.trash.get(&address).expect("Product is not in the trash");
+        assert!(env::block_timestamp() <= trashed.restorable_until, "Grace period has passed");
+        assert!(self.product_keys.len() < self.config().max_products, "Catalog is full");
+
+        let mut item = trashed.item;
+        self.trash.remove(&address);
+        self.trash_keys.remove(&address);
+        self.tombstones.remove(&address);
+        self.save_product(&address, &mut item);
+        if self.product_keys.insert(&address) {
+            self.ordered_keys.insert(&address, &());
+        }
+        self.index_vendor(&item.creator, &address);
+        self.index_name(&item.search_key, &address);
+        self.emit_event("restore_product", address.clone());
+        self.notify_listeners(&address, "restore_product");
+        self.audit("restore", &address);
+    }
+
+    // irreversible, so it sits behind its own role rather than ROLE_DELETE_PRODUCT; the tombstone stays
+    pub fn purge_product(&mut self, address: String) {
+        //validate sender has permition of ROLE_PURGE
+        self.assert_permission("purge_product");
+        self.assert_not_paused();
+        self.assert_breaker_closed();
+        self.assert_not_migrating();
+        assert!(self.trash.contains_key(&address), "Product is not in the trash");
+
+        self.trash.remove(&address);
+        self.trash_keys.remove(&address);
+        self.remove_secondary_entries(&address);
+        self.audit("purge", &address);
+    }
+
+    pub fn get_trashed_product(&self, address: String) -> Option<TrashedProduct> {
+        self.trash.get(&address)
+    }
+
+    // everything waiting for a purge, restorable or not, for admins reviewing removals
+    pub fn get_deleted_products(&self, cursor: Option<String>, limit: u64) -> Page<(String, TrashedProduct)> {
+        scan(&self.trash_keys, &self.cursor_secret, cursor, std::cmp::min(limit, MAX_TRASH_PAGE), |address| {
+            self.trash.get(&address).map(|trashed| (address, trashed))
+        })
+    }
+
+    pub fn get_deleted_products_count(&self) -> u64 {
+        self.trash_keys.len()
+    }
+
+    pub(crate) fn move_to_trash(&mut self, address: &String, mut item: Item, deleted_by: &AccountId) {
+        item.listing_deposit = 0;
+        let deleted_at = env::block_timestamp();
+        let trashed = TrashedProduct {
+            item,
+            deleted_at,
+            deleted_by: deleted_by.clone(),
+            restorable_until: deleted_at + self.config().trash_grace_period,
+        };
+        self.trash.insert(address, &trashed);
+        self.trash_keys.insert(address, &());
+        self.remove_listing_entries(address);
+    }
+}
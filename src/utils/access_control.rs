@@ -1,22 +1,49 @@
-use near_sdk::AccountId;
+use near_sdk::{env, AccountId};
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
 use near_sdk::collections::LookupMap;
+use near_sdk::serde::{Serialize, Deserialize};
+
+use crate::events::{log_event, AccessEvent, RoleEventData};
+
+const MAX_HISTORY_PAGE:u64 = 100;
+
+// one grant or revoke, by whoever signed the transaction
+#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RoleChange {
+     pub account: AccountId,
+     pub granted: bool,
+     pub changed_by: AccountId,
+     pub changed_at: u64,
+}
+
 // see: https://github.com/OpenZeppelin/openzeppelin-contracts/blob/master/contracts/access/AccessControl.sol
 #[derive( BorshDeserialize, BorshSerialize)]
 pub struct AccessControl {
 
     pub roles : LookupMap<String, Vec<String>>,
+    // when a grant lapses, as a block timestamp; grants without an entry never do
+    pub expiries: LookupMap<(String, AccountId), u64>,
+    // every membership change of a role, never trimmed, indexed by (role, n)
+    pub history: LookupMap<(String, u64), RoleChange>,
+    pub history_len: LookupMap<String, u64>,
     
 }
 
 
 impl AccessControl {
     
-    pub fn has_role(&mut self, role: &str,  account:&AccountId) -> bool {
+    pub fn has_role(&self, role: &str,  account:&AccountId) -> bool {
+        self.is_member(role, account) && self.expiry(role, account).map(|expires_at| env::block_timestamp() < expires_at).unwrap_or(true)
+    }
+
+    // granted, whether or not the grant has lapsed
+    pub fn is_member(&self, role: &str,  account:&AccountId) -> bool {
         let result = self.roles.get(&role.to_string());
         match result {
             Some(x) => {
-                x.contains(&account)
+                x.contains(account)
             },
            
             None   => false ,
@@ -25,13 +52,13 @@ impl AccessControl {
 
 
     pub fn  setup_role(&mut self,  role: String,  account:AccountId)  {
-        if !self.has_role( &role, &account) {
+        if !self.is_member( &role, &account) {
           
             let result = self.roles.get(&role);
             match result {
                 Some( x) => {
                     let mut item= x.clone();
-                    item.push(account);
+                    item.push(account.clone());
                     self.roles.insert(&role, &item);
 
                 },
@@ -42,7 +69,48 @@ impl AccessControl {
                 }
                 ,
             }
+            self.record_change(&role, &account, true);
         }
     }
+
+    pub fn revoke_role(&mut self, role: &str, account: &AccountId) -> bool {
+        match self.roles.get(&role.to_string()) {
+            Some(mut members) if members.contains(account) => {
+                members.retain(|member| member != account);
+                self.roles.insert(&role.to_string(), &members);
+                self.expiries.remove(&(role.to_string(), account.clone()));
+                self.record_change(role, account, false);
+                true
+            },
+            _ => false,
+        }
+    }
+
+    // oldest first
+    pub fn history(&self, role: &str, from_index: u64, limit: u64) -> Vec<RoleChange> {
+        let end = std::cmp::min(self.history_len.get(&role.to_string()).unwrap_or(0), from_index.saturating_add(std::cmp::min(limit, MAX_HISTORY_PAGE)));
+        (from_index..end).filter_map(|index| self.history.get(&(role.to_string(), index))).collect()
+    }
+
+    fn record_change(&mut self, role: &str, account: &AccountId, granted: bool) {
+        let index = self.history_len.get(&role.to_string()).unwrap_or(0);
+        let change = RoleChange { account: account.clone(), granted, changed_by: env::signer_account_id(), changed_at: env::block_timestamp() };
+        let data = vec![RoleEventData { role: role.to_string(), account: account.clone(), changed_by: change.changed_by.clone() }];
+        log_event(&if granted { AccessEvent::RoleGranted(data) } else { AccessEvent::RoleRevoked(data) });
+        self.history.insert(&(role.to_string(), index), &change);
+        self.history_len.insert(&role.to_string(), &(index + 1));
+    }
+
+    pub fn expiry(&self, role: &str, account: &AccountId) -> Option<u64> {
+        self.expiries.get(&(role.to_string(), account.clone()))
+    }
+
+    pub fn set_expiry(&mut self, role: &str, account: &AccountId, expires_at: Option<u64>) {
+        let key = (role.to_string(), account.clone());
+        match expires_at {
+            Some(expires_at) => self.expiries.insert(&key, &expires_at),
+            None => self.expiries.remove(&key),
+        };
+    }
     
 }
\ No newline at end of file
@@ -1,3 +1,7 @@
 // implement access control to smart contracs
 // see: https://github.com/OpenZeppelin/openzeppelin-contracts/blob/master/contracts/access/AccessControl.sol
-pub mod access_control;
\ No newline at end of file
+pub mod access_control;
+// bounded logs kept in contract storage
+pub mod ring_log;
+// resumable cursors for views walking many records
+pub mod pagination;
@@ -0,0 +1,79 @@
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::TreeMap;
+use near_sdk::serde::{Serialize, Deserialize};
+use near_sdk::env;
+
+// upper bound of records a single view call walks, whatever the filters drop
+pub const MAX_SCAN:u64 = 500;
+
+// partial results of a heavy view, pass the cursor back to resume where it stopped
+#[derive(Debug)]
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub cursor: Option<String>,
+}
+
+// the last key handed out, keys are walked in lexicographic order so pages never shift when
+// products are added or removed in between
+#[derive(BorshDeserialize, BorshSerialize)]
+struct Cursor {
+    after: Option<String>,
+    // first bytes of sha256(secret ++ after), catches cursors that were truncated or edited by hand;
+    // the secret sits in contract state where anyone can read it, so this is no signature
+    tag: Vec<u8>,
+}
+
+const TAG_LEN:usize = 8;
+
+fn tag(secret: &[u8], after: &Option<String>) -> Vec<u8> {
+    let mut payload = secret.to_vec();
+    payload.extend(after.try_to_vec().unwrap());
+    env::sha256(&payload)[..TAG_LEN].to_vec()
+}
+
+pub fn encode_cursor(secret: &[u8], after: Option<String>) -> String {
+    let tag = tag(secret, &after);
+    near_sdk::base64::encode(Cursor { after, tag }.try_to_vec().unwrap())
+}
+
+// no cursor starts from the beginning
+pub fn decode_cursor(secret: &[u8], cursor: Option<String>) -> Option<String> {
+    cursor.and_then(|cursor| {
+        let bytes = near_sdk::base64::decode(&cursor).unwrap_or_else(|_| env::panic(b"Invalid cursor"));
+        let cursor = Cursor::try_from_slice(&bytes).unwrap_or_else(|_| env::panic(b"Invalid cursor"));
+        assert!(cursor.tag == tag(secret, &cursor.after), "Invalid cursor");
+        cursor.after
+    })
+}
+
+// walks `keys` from the cursor until `limit` results are collected, the scan budget is spent
+// or a quarter of the prepaid gas is left
+pub fn scan<T, F>(keys: &TreeMap<String, ()>, secret: &[u8], cursor: Option<String>, limit: u64, mut visit: F) -> Page<T>
+where
+    F: FnMut(String) -> Option<T>,
+{
+    let mut last = decode_cursor(secret, cursor);
+    let walk: Box<dyn Iterator<Item = (String, ())>> = match &last {
+        Some(after) => Box::new(keys.iter_from(after.clone())),
+        None => Box::new(keys.iter()),
+    };
+    let mut remaining = walk.map(|(key, _)| key).peekable();
+    let mut items = Vec::new();
+    let mut scanned = 0;
+    let gas_reserve = env::prepaid_gas() / 4;
+    while remaining.peek().is_some()
+        && (items.len() as u64) < limit
+        && scanned < MAX_SCAN
+        && env::prepaid_gas().saturating_sub(env::used_gas()) > gas_reserve {
+        let key = remaining.next().unwrap();
+        last = Some(key.clone());
+        if let Some(item) = visit(key) {
+            items.push(item);
+        }
+        scanned += 1;
+    }
+    let cursor = if remaining.peek().is_some() { Some(encode_cursor(secret, last)) } else { None };
+    Page { items, cursor }
+}
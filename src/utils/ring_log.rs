@@ -0,0 +1,48 @@
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::Vector;
+// bounded append-only log, once full every new entry overwrites the oldest one
+#[derive( BorshDeserialize, BorshSerialize)]
+pub struct RingLog<T> {
+
+    entries: Vector<T>,
+    capacity: u64,
+    total: u64,
+
+}
+
+
+impl<T: BorshSerialize + BorshDeserialize> RingLog<T> {
+
+    pub fn new(prefix: Vec<u8>, capacity: u64) -> Self {
+        assert!(capacity > 0, "Capacity must be positive");
+        Self { entries: Vector::new(prefix), capacity, total: 0 }
+    }
+
+    // returns the sequence number of the appended entry
+    pub fn push(&mut self, entry: &T) -> u64 {
+        let seq = self.total;
+        if self.entries.len() < self.capacity {
+            self.entries.push(entry);
+        } else {
+            self.entries.replace(seq % self.capacity, entry);
+        }
+        self.total += 1;
+        seq
+    }
+
+    // number of entries ever appended, including the overwritten ones
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    // entries by sequence number, silently skipping the ones that were already overwritten
+    pub fn page(&self, from_index: u64, limit: u64) -> Vec<T> {
+        let oldest = self.total - self.entries.len();
+        let start = std::cmp::max(from_index, oldest);
+        let end = std::cmp::min(self.total, start.saturating_add(limit));
+        (start..end)
+            .filter_map(|seq| self.entries.get(seq % self.capacity))
+            .collect()
+    }
+
+}